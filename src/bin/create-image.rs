@@ -1,31 +1,19 @@
 #[macro_use]
 extern crate clap;
 
-extern crate crc;
-
-use std::fs::File;
-
-use xous_tools::elf::{read_minielf, read_program};
-use xous_tools::tags::bflg::Bflg;
-use xous_tools::tags::inie::IniE;
-use xous_tools::tags::memory::{MemoryRegion, MemoryRegions};
-use xous_tools::tags::xkrn::XousKernel;
-use xous_tools::utils::{parse_csr_csv, parse_u32};
-use xous_tools::xous_arguments::XousArguments;
-
-use clap::{App, Arg};
-
-// fn pad_file_to_4_bytes(f: &mut File) {
-//     while f
-//         .seek(SeekFrom::Current(0))
-//         .expect("couldn't check file position")
-//         & 3
-//         != 0
-//     {
-//         println!("padding...");
-//         f.seek(SeekFrom::Current(1)).expect("couldn't pad file");
-//     }
-// }
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use xous_tools::error::Error;
+use xous_tools::manifest::{Compress, InitProgram, Manifest, ParamValue, RamConfig};
+use xous_tools::memmap::MemoryMap;
+use xous_tools::tags::bflg::BootFlags;
+use xous_tools::tags::cmpr::Compression;
+use xous_tools::tags::memory::RegionAttributes;
+use xous_tools::utils::parse_u32;
+
+use clap::{App, Arg, ArgMatches};
 
 fn main() {
     env_logger::init();
@@ -33,13 +21,21 @@ fn main() {
         .version(crate_version!())
         .author("Sean Cross <sean@xobs.io>")
         .about("Create a boot image for Xous")
+        .arg(
+            Arg::with_name("manifest")
+                .short("m")
+                .long("manifest")
+                .value_name("MANIFEST_TOML")
+                .takes_value(true)
+                .help("Manifest describing the image.  Other options override its values"),
+        )
         .arg(
             Arg::with_name("kernel")
                 .short("k")
                 .long("kernel")
                 .value_name("KERNEL_ELF")
                 .takes_value(true)
-                .required(true)
+                .required_unless("manifest")
                 .help("Kernel ELF image to bundle into the image"),
         )
         .arg(
@@ -51,6 +47,15 @@ fn main() {
                 .number_of_values(1)
                 .help("Initial program to load"),
         )
+        .arg(
+            Arg::with_name("process")
+                .long("process")
+                .value_name("INIT:KEY=VALUE,...")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Set the name, stack, priority or debug flag of an init program, chosen by index or file name"),
+        )
         .arg(
             Arg::with_name("csv")
                 .short("c")
@@ -60,7 +65,7 @@ fn main() {
                 .value_name("CSR_CSV")
                 .help("csr.csv file from litex")
                 .takes_value(true)
-                .required_unless("ram"),
+                .required_unless_one(&["ram", "manifest"]),
         )
         .arg(
             Arg::with_name("ram")
@@ -68,9 +73,43 @@ fn main() {
                 .long("ram")
                 .takes_value(true)
                 .value_name("OFFSET:SIZE")
-                .required_unless("csv")
+                .required_unless_one(&["csv", "manifest"])
                 .help("RAM offset and size, in the form of [offset]:[size]"),
         )
+        .arg(
+            Arg::with_name("main-ram")
+                .long("main-ram")
+                .value_name("REGION")
+                .takes_value(true)
+                .help("Use this region from the csv file as main RAM, rather than the largest \"ram\""),
+        )
+        .arg(
+            Arg::with_name("extra-ram")
+                .long("extra-ram")
+                .value_name("REGION")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Let the kernel also allocate from this region, as an extra bank of RAM"),
+        )
+        .arg(
+            Arg::with_name("region-name")
+                .long("region-name")
+                .value_name("REGION:CODE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Call a memory region by this four-character code, such as sram_ext2:SrE2"),
+        )
+        .arg(
+            Arg::with_name("region-attributes")
+                .long("region-attributes")
+                .value_name("REGION:ATTR,...")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Set the attributes of a memory region, such as SpFl:read,execute,cacheable"),
+        )
         .arg(
             Arg::with_name("debug")
                 .short("d")
@@ -78,137 +117,431 @@ fn main() {
                 .takes_value(false)
                 .help("Reduce kernel-userspace security and enable debugging programs"),
         )
+        .arg(
+            Arg::with_name("flag")
+                .long("flag")
+                .value_name("FLAG,...")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Boot flags to set, such as no_copy,absolute"),
+        )
+        .arg(
+            Arg::with_name("param")
+                .short("p")
+                .long("param")
+                .value_name("KEY=VALUE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Boot parameter to pass to the kernel"),
+        )
+        .arg(
+            Arg::with_name("param-schema")
+                .long("param-schema")
+                .value_name("SCHEMA_TOML")
+                .takes_value(true)
+                .help("Check boot parameters against this schema"),
+        )
+        .arg(
+            Arg::with_name("revision")
+                .long("revision")
+                .value_name("REVISION")
+                .takes_value(true)
+                .help("Source revision to record in the image, such as a git commit"),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .long("compress")
+                .value_name("ALGORITHM")
+                .takes_value(true)
+                .possible_values(&["none", "lz4", "deflate"])
+                .help("Compress the kernel and init programs"),
+        )
+        .arg(
+            Arg::with_name("sign")
+                .short("s")
+                .long("sign")
+                .value_name("PRIVATE_KEY")
+                .takes_value(true)
+                .help("Sign the image with this Ed25519 private key (PEM, raw or hex)"),
+        )
+        .arg(
+            Arg::with_name("encrypt")
+                .long("encrypt")
+                .value_name("KEK_FILE")
+                .takes_value(true)
+                .help("Encrypt the kernel and init programs, wrapping their keys with this key (raw or hex)"),
+        )
+        .arg(
+            Arg::with_name("security-version")
+                .long("security-version")
+                .value_name("VERSION")
+                .takes_value(true)
+                .help("Anti-rollback security version of the image"),
+        )
+        .arg(
+            Arg::with_name("previous-image")
+                .long("previous-image")
+                .value_name("ARGS_BIN")
+                .takes_value(true)
+                .help("Refuse to build if the security version is lower than this image's"),
+        )
+        .arg(
+            Arg::with_name("no-layout-check")
+                .long("no-layout-check")
+                .takes_value(false)
+                .help("Report overlapping or misplaced sections and regions as warnings instead of failing"),
+        )
+        .arg(
+            Arg::with_name("memory-map")
+                .long("memory-map")
+                .takes_value(false)
+                .help("Print a map of main RAM, memory regions and program sections"),
+        )
+        .arg(
+            Arg::with_name("memory-map-svg")
+                .long("memory-map-svg")
+                .value_name("SVG_FILE")
+                .takes_value(true)
+                .help("Draw a map of main RAM, memory regions and program sections as SVG"),
+        )
+        .arg(
+            Arg::with_name("check-reproducible")
+                .long("check-reproducible")
+                .takes_value(false)
+                .help("Build the image twice and fail if the two builds differ"),
+        )
+        .arg(
+            Arg::with_name("print-digest")
+                .long("print-digest")
+                .takes_value(false)
+                .conflicts_with("json")
+                .help("Only print the SHA-256 of the image file, in the format of sha256sum"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .takes_value(false)
+                .help("Print a JSON description of the image instead of text"),
+        )
         .arg(
             Arg::with_name("output")
                 .value_name("OUTPUT")
-                .required(true)
+                .required_unless("manifest")
                 .help("Output file to store tag and init information"),
         )
         .get_matches();
 
-    let mut ram_offset = Default::default();
-    let mut ram_size = Default::default();
-    let mut ram_name = MemoryRegion::make_name("sram");
-    let mut regions = MemoryRegions::new();
-    let mut memory_required = 0;
-
-    if let Some(val) = matches.value_of("ram") {
-        let ram_parts: Vec<&str> = val.split(":").collect();
-        if ram_parts.len() != 2 {
-            eprintln!("Error: --ram argument should be of the form [offset]:[size]");
-            return;
-        }
+    if let Err(e) = run(&matches) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
 
-        ram_offset = match parse_u32(ram_parts[0]) {
-            Ok(o) => o,
-            Err(e) => {
-                eprintln!("Error: Unable to parse {}: {:?}", ram_parts[0], e);
-                return;
-            }
-        };
+fn parse_ram(val: &str) -> Result<RamConfig, Error> {
+    let ram_parts: Vec<&str> = val.split(':').collect();
+    if ram_parts.len() != 2 {
+        return Err(Error::InvalidArgument(
+            "--ram argument should be of the form [offset]:[size]".to_owned(),
+        ));
+    }
+    let parse = |s: &str| {
+        parse_u32(s).map_err(|e| Error::InvalidArgument(format!("--ram: {}", e)))
+    };
+    Ok(RamConfig {
+        start: parse(ram_parts[0])?,
+        length: parse(ram_parts[1])?,
+    })
+}
+
+/// Apply a `--process` option, such as `shell:name=xous-shell,stack=0x20000`,
+/// to the init program it names.
+fn apply_process(inits: &mut [InitProgram], val: &str) -> Result<(), Error> {
+    let invalid = |msg: String| Error::InvalidArgument(format!("--process {}: {}", val, msg));
+    let mut parts = val.splitn(2, ':');
+    let selector = parts.next().unwrap();
+    let settings = parts
+        .next()
+        .ok_or_else(|| invalid("should be of the form [init]:[key]=[value],...".to_owned()))?;
+    let init = match selector.parse::<usize>() {
+        Ok(index) => inits.get_mut(index),
+        Err(_) => inits.iter_mut().find(|init| {
+            init.name.as_deref() == Some(selector)
+                || init.path.file_stem().is_some_and(|stem| stem == selector)
+        }),
+    }
+    .ok_or_else(|| invalid(format!("no init program {}", selector)))?;
 
-        ram_size = match parse_u32(ram_parts[1]) {
-            Ok(o) => o,
-            Err(e) => {
-                eprintln!("Error: Unable to parse {}: {:?}", ram_parts[1], e);
-                return;
+    for setting in settings.split(',').filter(|s| !s.is_empty()) {
+        let mut parts = setting.splitn(2, '=');
+        let key = parts.next().unwrap();
+        let value = parts.next();
+        match (key, value) {
+            ("name", Some(name)) if !name.is_empty() => init.name = Some(name.to_owned()),
+            ("stack", Some(size)) => {
+                init.stack_size = Some(parse_u32(size).map_err(|e| invalid(e.to_string()))?)
             }
-        };
+            ("priority", Some(priority)) => {
+                init.priority = Some(
+                    priority
+                        .parse()
+                        .map_err(|_| invalid(format!("bad priority \"{}\"", priority)))?,
+                )
+            }
+            ("debug", None) | ("debug", Some("true")) => init.debug = true,
+            ("debug", Some("false")) => init.debug = false,
+            _ => return Err(invalid(format!("unknown setting \"{}\"", setting))),
+        }
+    }
+    Ok(())
+}
 
-        memory_required += ram_size / 4096;
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let mut manifest = match matches.value_of("manifest") {
+        Some(path) => Manifest::load(path)?,
+        None => Manifest::default(),
+    };
+
+    if let Some(kernel) = matches.value_of("kernel") {
+        manifest.kernel = Some(kernel.into());
+    }
+
+    // Main RAM given on the command line replaces wherever the manifest
+    // takes it from, rather than being combined with it
+    if matches.is_present("ram") || matches.is_present("csv") {
+        manifest.memory.replace_main_ram(
+            matches.value_of("ram").map(parse_ram).transpose()?,
+            matches.value_of("csv").map(PathBuf::from),
+        );
+    }
+
+    if let Some(name) = matches.value_of("main-ram") {
+        manifest.memory.main_ram = Some(name.to_owned());
     }
 
-    if let Some(csr_csv) = matches.value_of("csv") {
-        let hv = parse_csr_csv(csr_csv).unwrap();
-        let mut found_ram_name = None;
-        fn round_mem(src: u32) -> u32 {
-            (src + 4095) & !4095
+    if let Some(names) = matches.values_of("extra-ram") {
+        manifest
+            .memory
+            .extra_ram
+            .extend(names.map(str::to_owned));
+    }
+
+    // Init programs given on the command line replace those in the manifest
+    if let Some(init_paths) = matches.values_of("init") {
+        manifest.init = init_paths.map(InitProgram::new).collect();
+    }
+
+    if let Some(processes) = matches.values_of("process") {
+        for process in processes {
+            apply_process(&mut manifest.init, process)?;
         }
-        // Look for the largest "ram" block, which we'll treat as main memory
-        for (k, v) in &hv.regions {
-            if k.find("ram").is_some() {
-                if v.length > ram_size {
-                    ram_size = round_mem(v.length);
-                    ram_offset = v.start;
-                    found_ram_name = Some(k.clone());
-                }
-            }
+    }
+
+    if matches.is_present("debug") {
+        manifest.flags.debug = true;
+    }
+
+    if let Some(values) = matches.values_of("region-name") {
+        for value in values {
+            let mut parts = value.splitn(2, ':');
+            let region = parts.next().unwrap();
+            let code = parts.next().ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "--region-name should be of the form [region]:[code], not {}",
+                    value
+                ))
+            })?;
+            manifest
+                .memory
+                .names
+                .insert(region.to_owned(), code.to_owned());
+        }
+    }
+
+    if let Some(values) = matches.values_of("region-attributes") {
+        for value in values {
+            let mut parts = value.splitn(2, ':');
+            let region = parts.next().unwrap();
+            let list = parts.next().ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "--region-attributes should be of the form [region]:[attribute],..., not {}",
+                    value
+                ))
+            })?;
+            let attributes = RegionAttributes::parse_list(list).map_err(|name| {
+                Error::InvalidArgument(format!(
+                    "unknown memory region attribute \"{}\", expected one of {}",
+                    name,
+                    RegionAttributes::NAMES
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            })?;
+            manifest
+                .memory
+                .attributes
+                .insert(region.to_owned(), attributes);
         }
+    }
 
-        if found_ram_name.is_none() {
-            eprintln!("Error: Couldn't find a memory region named \"ram\" in csv file");
-            return;
+    if let Some(lists) = matches.values_of("flag") {
+        for list in lists {
+            manifest.flags.set |= BootFlags::parse_list(list).map_err(|name| {
+                Error::InvalidArgument(format!(
+                    "unknown boot flag \"{}\", expected one of {}",
+                    name,
+                    BootFlags::NAMES
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            })?;
         }
+    }
 
-        // Now that we know which block is ram, add the other regions.
-        let found_ram_name = MemoryRegion::make_name(&found_ram_name.unwrap());
-        for (k, v) in &hv.regions {
-            memory_required += round_mem(v.length) / 4096;
-            let region_name = MemoryRegion::make_name(k);
-            // Don't add the RAM section to the extra regions block.
-            if region_name == found_ram_name {
-                ram_name = region_name;
-                continue;
-            }
-            // Don't add empty sections.
-            if round_mem(v.length) == 0 {
-                continue;
-            }
-            regions.add(MemoryRegion::new(v.start, round_mem(v.length), region_name));
+    // Parameters on the command line add to or override the manifest's
+    if let Some(params) = matches.values_of("param") {
+        for param in params {
+            let mut parts = param.splitn(2, '=');
+            let key = parts.next().unwrap();
+            let value = parts.next().ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "--param should be of the form [key]=[value], not {}",
+                    param
+                ))
+            })?;
+            manifest
+                .boot
+                .params
+                .insert(key.to_owned(), ParamValue::String(value.to_owned()));
         }
     }
 
-    let mut args = XousArguments::new(ram_offset, ram_size, ram_name);
+    if let Some(schema) = matches.value_of("param-schema") {
+        manifest.boot.schema = Some(schema.into());
+    }
 
-    if regions.len() > 0 {
-        args.add(regions);
+    if let Some(revision) = matches.value_of("revision") {
+        manifest.build.revision = Some(revision.to_owned());
     }
 
-    if matches.is_present("debug") {
-        args.add(Bflg::new().debug());
+    if let Some(compress) = matches.value_of("compress") {
+        manifest.compress = Some(match compress {
+            "lz4" => Compress::Lz4,
+            "deflate" => Compress::Deflate,
+            _ => Compress::None,
+        });
+        // Command line options override per-program settings too
+        for init in &mut manifest.init {
+            init.compress = None;
+        }
     }
 
-    let kernel = read_program(
-        matches
-            .value_of("kernel")
-            .expect("kernel was somehow missing"),
-    )
-    .expect("unable to read kernel");
+    if let Some(key) = matches.value_of("sign") {
+        manifest.signing.key = Some(key.into());
+    }
 
-    if let Some(init_paths) = matches.values_of("init") {
-        for init_path in init_paths {
-            let init = read_minielf(init_path).expect("couldn't parse init file");
-            args.add(IniE::new(init.entry_point, init.sections, init.program));
+    if let Some(key) = matches.value_of("encrypt") {
+        manifest.encryption.key = Some(key.into());
+    }
+
+    if let Some(version) = matches.value_of("security-version") {
+        manifest.security.version = Some(parse_u32(version).map_err(|e| {
+            Error::InvalidArgument(format!("--security-version: {}", e))
+        })?);
+    }
+
+    if let Some(previous) = matches.value_of("previous-image") {
+        manifest.security.previous_image = Some(previous.into());
+    }
+
+    if let Some(output) = matches.value_of("output") {
+        manifest.output.image = Some(output.into());
+    }
+
+    let output_filename: PathBuf = manifest.output.image.clone().ok_or_else(|| {
+        Error::InvalidArgument(
+            "no output file given on the command line or in the manifest".to_owned(),
+        )
+    })?;
+
+    // Layout problems are checked here rather than by the builder when
+    // drawing a map, so that the map can show them
+    let check_layout = !matches.is_present("no-layout-check");
+    let show_map = matches.is_present("memory-map") || matches.is_present("memory-map-svg");
+    let builder = manifest.builder().check_layout(check_layout && !show_map);
+    let built = if matches.is_present("check-reproducible") {
+        builder.build_reproducible()?
+    } else {
+        builder.build()?
+    };
+    for warning in &built.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if show_map {
+        let map = MemoryMap::new(&built.args);
+        if matches.is_present("memory-map") {
+            print!("{}", map.to_text());
+        }
+        if let Some(svg_filename) = matches.value_of("memory-map-svg") {
+            fs::write(svg_filename, map.to_svg())
+                .map_err(|e| Error::Io(Some(svg_filename.into()), e))?;
+        }
+    }
+    if !built.layout_problems.is_empty() {
+        if check_layout {
+            return Err(Error::Layout(built.layout_problems));
+        }
+        for problem in &built.layout_problems {
+            eprintln!("Warning: {}", problem);
         }
     }
 
-    let xkrn = XousKernel::new(
-        kernel.text_offset,
-        kernel.text_size,
-        kernel.data_offset,
-        kernel.data_size,
-        kernel.bss_size,
-        kernel.entry_point,
-        kernel.program,
-    );
-    args.add(xkrn);
+    fs::write(&output_filename, &built.image)
+        .map_err(|e| Error::Io(Some(output_filename.clone()), e))?;
+
+    if let Some(json_filename) = &manifest.output.json {
+        fs::write(json_filename, format!("{}\n", built.layout.to_json()))
+            .map_err(|e| Error::Io(Some(json_filename.clone()), e))?;
+    }
+
+    if matches.is_present("print-digest") {
+        println!("{}  {}", built.sha256(), output_filename.display());
+        return Ok(());
+    }
 
-    // Add tags for init and kernel.  These point to the actual data, which should
-    // immediately follow the tags.  Therefore, we must know the length of the tags
-    // before we create them.
+    if matches.is_present("json") {
+        println!("{}", built.layout.to_json());
+        return Ok(());
+    }
 
-    let output_filename = matches
-        .value_of("output")
-        .expect("output filename not present");
-    let f = File::create(output_filename)
-        .expect(&format!("Couldn't create output file {}", output_filename));
-    args.write(&f).expect("Couldn't write to args");
+    println!("Arguments: {}", built.args);
 
-    println!("Arguments: {}", args);
+    let (uncompressed, stored) = built
+        .args
+        .iter::<Compression>()
+        .fold((0, 0), |(u, s), c| (u + c.uncompressed_size(), s + c.stored_size()));
+    if uncompressed > 0 {
+        println!(
+            "Compression saved {} bytes ({} bytes of payloads stored in {} bytes)",
+            uncompressed as i64 - stored as i64,
+            uncompressed,
+            stored
+        );
+    }
 
     println!(
         "Runtime will require {} bytes to track memory allocations",
-        memory_required
+        built.layout.memory_required
     );
-    println!("Image created in file {}", output_filename);
+    if matches.is_present("check-reproducible") {
+        println!("Two builds of the image were identical");
+    }
+    println!("Image created in file {}", output_filename.display());
+    Ok(())
 }
@@ -0,0 +1,62 @@
+#[macro_use]
+extern crate clap;
+
+use std::fs;
+use std::process;
+
+use clap::{App, Arg, ArgMatches};
+
+use xous_tools::diff::diff_images;
+use xous_tools::error::Error;
+use xous_tools::xous_arguments::XousArguments;
+
+fn read_image(filename: &str) -> Result<XousArguments, Error> {
+    let image = fs::read(filename).map_err(|e| Error::Io(Some(filename.into()), e))?;
+    XousArguments::parse(&image).map_err(|e| Error::Image(filename.into(), e))
+}
+
+fn main() {
+    let matches = App::new("Xous Image Differ")
+        .version(crate_version!())
+        .author("Sean Cross <sean@xobs.io>")
+        .about("Describe how two Xous boot images differ, field by field")
+        .arg(
+            Arg::with_name("old")
+                .value_name("OLD_ARGS_BIN")
+                .required(true)
+                .help("Known-good image"),
+        )
+        .arg(
+            Arg::with_name("new")
+                .value_name("NEW_ARGS_BIN")
+                .required(true)
+                .help("Image to compare against it"),
+        )
+        .get_matches();
+
+    // Follow diff(1): exit 0 if the same, 1 if different, 2 on error
+    match run(&matches) {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(2);
+        }
+    }
+}
+
+/// Print how the images differ, returning whether they are equivalent.
+fn run(matches: &ArgMatches) -> Result<bool, Error> {
+    let old = read_image(matches.value_of("old").unwrap())?;
+    let new = read_image(matches.value_of("new").unwrap())?;
+
+    let changes = diff_images(&old, &new);
+    if changes.is_empty() {
+        println!("Images are equivalent");
+        return Ok(true);
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    Ok(false)
+}
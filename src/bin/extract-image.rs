@@ -0,0 +1,150 @@
+#[macro_use]
+extern crate clap;
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use clap::{App, Arg, ArgMatches};
+
+use xous_tools::elf::write_elf;
+use xous_tools::encryption::load_key_encryption_key;
+use xous_tools::error::Error;
+use xous_tools::tags::inie::IniE;
+use xous_tools::tags::proc::Processes;
+use xous_tools::tags::xkrn::XousKernel;
+use xous_tools::xous_arguments::XousArguments;
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), Error> {
+    fs::write(path, data).map_err(|e| Error::Io(Some(path.to_path_buf()), e))?;
+    println!("Wrote {} bytes to {}", data.len(), path.display());
+    Ok(())
+}
+
+fn require_data(has_data: bool, name: &str) -> Result<(), Error> {
+    if !has_data {
+        return Err(Error::InvalidArgument(format!(
+            "{} is encrypted, pass --key to decrypt it",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// File name, without an extension, for the init program at `idx`.  This
+/// is its name from the `Proc` tag with anything that doesn't belong in a
+/// file name replaced, or `init-N` if it has no name or the name is taken.
+fn init_stem(idx: usize, processes: Option<&Processes>, used: &[String]) -> String {
+    let name: String = processes
+        .and_then(|p| p.get(idx))
+        .map(|process| {
+            process
+                .name
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if name.is_empty() || name.starts_with('.') || used.contains(&name) {
+        format!("init-{}", idx)
+    } else {
+        name
+    }
+}
+
+fn main() {
+    let matches = App::new("Xous Image Extractor")
+        .version(crate_version!())
+        .author("Sean Cross <sean@xobs.io>")
+        .about("Extract the kernel and initial programs from a Xous boot image")
+        .arg(
+            Arg::with_name("elf")
+                .short("e")
+                .long("elf")
+                .takes_value(false)
+                .help("Reconstruct ELF files rather than writing flat binaries.  Section names \
+                       aren't stored in the image, so they are guessed, and the kernel's .bss \
+                       is placed right after its .data"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .short("k")
+                .long("key")
+                .value_name("KEK_FILE")
+                .takes_value(true)
+                .help("Key-encryption key used to decrypt encrypted payloads (raw or hex)"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("ARGS_BIN")
+                .required(true)
+                .help("Image file created by create-image"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .value_name("OUTPUT_DIR")
+                .required(true)
+                .help("Directory to write the extracted programs to"),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let input_filename = matches.value_of("input").unwrap();
+    let output_dir = Path::new(matches.value_of("output").unwrap());
+    let as_elf = matches.is_present("elf");
+    let suffix = if as_elf { "elf" } else { "bin" };
+
+    let image = fs::read(input_filename).map_err(|e| Error::Io(Some(input_filename.into()), e))?;
+    let key = matches
+        .value_of("key")
+        .map(load_key_encryption_key)
+        .transpose()?;
+    let args = XousArguments::parse_with_key(&image, key.as_ref())
+        .map_err(|e| Error::Image(input_filename.into(), e))?;
+
+    // Check everything can be decrypted before writing anything out
+    if let Some(xkrn) = args.get::<XousKernel>() {
+        require_data(xkrn.has_data(), "the kernel")?;
+    }
+    for (idx, inie) in args.iter::<IniE>().enumerate() {
+        require_data(inie.has_data(), &format!("init program {}", idx))?;
+    }
+    fs::create_dir_all(output_dir).map_err(|e| Error::Io(Some(output_dir.to_path_buf()), e))?;
+
+    if let Some(xkrn) = args.get::<XousKernel>() {
+        let path = output_dir.join(format!("xous-kernel.{}", suffix));
+        if as_elf {
+            let elf = write_elf(xkrn.entrypoint(), &xkrn.sections(), xkrn.program());
+            write_file(&path, &elf)?;
+        } else {
+            write_file(&path, xkrn.program())?;
+        }
+    }
+
+    let processes = args.get::<Processes>();
+    let mut used = vec!["xous-kernel".to_owned()];
+    for (idx, inie) in args.iter::<IniE>().enumerate() {
+        let stem = init_stem(idx, processes, &used);
+        let path = output_dir.join(format!("{}.{}", stem, suffix));
+        used.push(stem);
+        if as_elf {
+            let elf = write_elf(inie.entrypoint(), inie.sections(), inie.data());
+            write_file(&path, &elf)?;
+        } else {
+            write_file(&path, inie.data())?;
+        }
+    }
+    Ok(())
+}
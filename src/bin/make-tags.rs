@@ -1,7 +1,10 @@
 extern crate xous_tools;
 
 use std::fs::File;
+use std::path::Path;
+use std::process;
 
+use xous_tools::error::Error;
 use xous_tools::make_type;
 // use xous_tools::tags::init::Init;
 use xous_tools::tags::memory::{MemoryRegion, MemoryRegions};
@@ -18,6 +21,13 @@ const LCD_START: XousSize = 0xB0000000;
 const LCD_SIZE: XousSize = 32_768;
 
 fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Error> {
     let mut args = XousArguments::new(RAM_START, RAM_SIZE, make_type!("sram"));
 
     let mut regions = MemoryRegions::new();
@@ -42,6 +52,7 @@ fn main() {
 
     println!("Arguments: {}", args);
 
-    let f = File::create("args.bin").expect("Couldn't create args.bin");
-    args.write(f).expect("Couldn't write to args");
+    let path = Path::new("args.bin");
+    let f = File::create(path).map_err(|e| Error::Io(Some(path.to_path_buf()), e))?;
+    args.write(f)
 }
@@ -0,0 +1,339 @@
+#[macro_use]
+extern crate clap;
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use clap::{App, Arg, ArgMatches};
+use sha2::{Digest, Sha256};
+
+use xous_tools::elf::{read_minielf, read_program, MiniElf};
+use xous_tools::encryption::load_key_encryption_key;
+use xous_tools::error::Error;
+use xous_tools::patch::Patch;
+use xous_tools::seal::Sha256Hash;
+use xous_tools::signing::load_signing_key;
+use xous_tools::tags::bflg::BootFlags;
+use xous_tools::tags::sign::Signature;
+use xous_tools::tags::memory::{MemoryRegion, RegionAttributes};
+use xous_tools::tags::proc::ProcessInfo;
+use xous_tools::utils::parse_u32;
+use xous_tools::validate::validate_layout;
+use xous_tools::xous_arguments::XousArguments;
+
+fn parse_index(value: &str) -> Result<usize, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::InvalidArgument(format!("\"{}\" is not a valid init index", value)))
+}
+
+fn parse_number(option: &str, value: &str) -> Result<u32, Error> {
+    parse_u32(value).map_err(|e| Error::InvalidArgument(format!("{}: {}", option, e)))
+}
+
+fn init_elf(path: &str) -> Result<MiniElf, Error> {
+    read_minielf(path).map_err(|e| Error::Elf(path.into(), e))
+}
+
+/// SHA-256 of an ELF file, to record in the image's `BInf` tag.
+fn elf_hash(path: &str) -> Result<Sha256Hash, Error> {
+    let contents = fs::read(path).map_err(|e| Error::Io(Some(path.into()), e))?;
+    Ok(Sha256::digest(&contents).into())
+}
+
+fn values<'a>(matches: &'a ArgMatches, name: &str) -> Vec<&'a str> {
+    matches
+        .values_of(name)
+        .map(|v| v.collect())
+        .unwrap_or_default()
+}
+
+/// Turn the command line into a list of patches, in the order they apply.
+fn collect_patches(matches: &ArgMatches) -> Result<Vec<Patch>, Error> {
+    let mut patches = vec![];
+
+    if let Some(path) = matches.value_of("kernel") {
+        let kernel = read_program(path).map_err(|e| Error::Elf(path.into(), e))?;
+        patches.push(Patch::ReplaceKernel(kernel, elf_hash(path)?));
+    }
+
+    for spec in values(matches, "replace-init") {
+        let mut parts = spec.splitn(2, '=');
+        let index = parts.next().unwrap();
+        let path = parts.next().ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "--replace-init should be of the form [index]=[elf], not {}",
+                spec
+            ))
+        })?;
+        patches.push(Patch::ReplaceInit(
+            parse_index(index)?,
+            init_elf(path)?,
+            elf_hash(path)?,
+        ));
+    }
+
+    // Remove from the end so that indices refer to the original image
+    let mut removals = values(matches, "remove-init")
+        .into_iter()
+        .map(parse_index)
+        .collect::<Result<Vec<usize>, Error>>()?;
+    removals.sort_unstable();
+    removals.dedup();
+    for index in removals.into_iter().rev() {
+        patches.push(Patch::RemoveInit(index));
+    }
+
+    for path in values(matches, "add-init") {
+        let name = Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        patches.push(Patch::AddInit(
+            init_elf(path)?,
+            ProcessInfo::new(&name),
+            elf_hash(path)?,
+        ));
+    }
+
+    for name in values(matches, "set-flag") {
+        patches.push(Patch::SetFlag(name.to_owned(), true));
+    }
+    for name in values(matches, "clear-flag") {
+        patches.push(Patch::SetFlag(name.to_owned(), false));
+    }
+
+    for name in values(matches, "remove-region") {
+        patches.push(Patch::RemoveRegion(MemoryRegion::make_name(name)));
+    }
+    for spec in values(matches, "add-region") {
+        let parts: Vec<&str> = spec.split(':').collect();
+        if parts.len() != 3 {
+            return Err(Error::InvalidArgument(format!(
+                "--add-region should be of the form [name]:[offset]:[size], not {}",
+                spec
+            )));
+        }
+        let start = parse_number("--add-region", parts[1])?;
+        let length = parse_number("--add-region", parts[2])?;
+        patches.push(Patch::AddRegion(
+            MemoryRegion::new(start, length, MemoryRegion::make_name(parts[0]))
+                .with_attributes(RegionAttributes::default_for(parts[0], false)),
+        ));
+    }
+    for spec in values(matches, "region-attributes") {
+        let mut parts = spec.splitn(2, ':');
+        let name = parts.next().unwrap();
+        let list = parts.next().ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "--region-attributes should be of the form [name]:[attribute],..., not {}",
+                spec
+            ))
+        })?;
+        let attributes = RegionAttributes::parse_list(list).map_err(|e| {
+            Error::InvalidArgument(format!("unknown memory region attribute \"{}\"", e))
+        })?;
+        patches.push(Patch::SetRegionAttributes(
+            MemoryRegion::make_name(name),
+            attributes,
+        ));
+    }
+
+    if let Some(version) = matches.value_of("security-version") {
+        let version = parse_number("--security-version", version)?;
+        patches.push(Patch::SetSecurityVersion(version));
+    }
+
+    // Signing has to happen after everything else has changed
+    if let Some(path) = matches.value_of("sign") {
+        patches.push(Patch::Sign(load_signing_key(path)?));
+    }
+
+    Ok(patches)
+}
+
+fn main() {
+    let flag_names: Vec<&str> = BootFlags::NAMES.iter().map(|(name, _)| *name).collect();
+    env_logger::init();
+    let matches = App::new("Xous Image Patcher")
+        .version(crate_version!())
+        .author("Sean Cross <sean@xobs.io>")
+        .about("Modify an existing Xous boot image without rebuilding it")
+        .arg(
+            Arg::with_name("kernel")
+                .short("k")
+                .long("kernel")
+                .value_name("KERNEL_ELF")
+                .takes_value(true)
+                .help("Replace the kernel with this ELF file"),
+        )
+        .arg(
+            Arg::with_name("replace-init")
+                .long("replace-init")
+                .value_name("INDEX=ELF")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Replace the init program at INDEX"),
+        )
+        .arg(
+            Arg::with_name("remove-init")
+                .long("remove-init")
+                .value_name("INDEX")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Remove the init program at INDEX"),
+        )
+        .arg(
+            Arg::with_name("add-init")
+                .short("i")
+                .long("add-init")
+                .value_name("ELF")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Add an init program after the existing ones"),
+        )
+        .arg(
+            Arg::with_name("set-flag")
+                .long("set-flag")
+                .value_name("FLAG")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&flag_names)
+                .help("Set a boot flag"),
+        )
+        .arg(
+            Arg::with_name("clear-flag")
+                .long("clear-flag")
+                .value_name("FLAG")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&flag_names)
+                .help("Clear a boot flag"),
+        )
+        .arg(
+            Arg::with_name("add-region")
+                .long("add-region")
+                .value_name("NAME:OFFSET:SIZE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Add an extra memory region"),
+        )
+        .arg(
+            Arg::with_name("remove-region")
+                .long("remove-region")
+                .value_name("NAME")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Remove an extra memory region"),
+        )
+        .arg(
+            Arg::with_name("region-attributes")
+                .long("region-attributes")
+                .value_name("NAME:ATTR,...")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Set the attributes of a memory region, such as SpFl:read,execute,cacheable"),
+        )
+        .arg(
+            Arg::with_name("security-version")
+                .long("security-version")
+                .value_name("VERSION")
+                .takes_value(true)
+                .help("Set the anti-rollback security version, which may not go down"),
+        )
+        .arg(
+            Arg::with_name("sign")
+                .short("s")
+                .long("sign")
+                .value_name("PRIVATE_KEY")
+                .takes_value(true)
+                .help("Sign the patched image with this Ed25519 private key"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .value_name("KEK_FILE")
+                .takes_value(true)
+                .help("Key-encryption key used to encrypt added or replaced programs in an encrypted image (raw or hex)"),
+        )
+        .arg(
+            Arg::with_name("no-layout-check")
+                .long("no-layout-check")
+                .takes_value(false)
+                .help("Report overlapping or misplaced sections and regions as warnings instead of failing"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("ARGS_BIN")
+                .required(true)
+                .help("Image file to patch"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .value_name("OUTPUT")
+                .required(true)
+                .help("Where to write the patched image, which may be the input file"),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let input_filename = matches.value_of("input").unwrap();
+    let output_filename = matches.value_of("output").unwrap();
+
+    let image = fs::read(input_filename).map_err(|e| Error::Io(Some(input_filename.into()), e))?;
+    let key = matches
+        .value_of("key")
+        .map(load_key_encryption_key)
+        .transpose()?;
+    // Parsing with the key checks that it's the one the image was encrypted with
+    let mut args = XousArguments::parse_with_key(&image, key.as_ref())
+        .map_err(|e| Error::Image(input_filename.into(), e))?;
+
+    // Patching a signed image would leave behind a signature that no longer
+    // matches, so insist on a key to re-sign it with
+    if args.get::<Signature>().is_some() && !matches.is_present("sign") {
+        return Err(Error::InvalidArgument(format!(
+            "{} is signed, so pass --sign to re-sign it after patching",
+            input_filename
+        )));
+    }
+
+    for patch in collect_patches(matches)? {
+        patch.apply(&mut args, key.as_ref())?;
+    }
+
+    // Refuse to write anything that create-image wouldn't have built
+    let problems = validate_layout(&args);
+    if !problems.is_empty() {
+        if !matches.is_present("no-layout-check") {
+            return Err(Error::Layout(problems));
+        }
+        for problem in &problems {
+            eprintln!("Warning: {}", problem);
+        }
+    }
+
+    // Writing finalizes the arguments, which recomputes every payload offset
+    let mut output = vec![];
+    args.write(&mut output)?;
+    fs::write(output_filename, &output).map_err(|e| Error::Io(Some(output_filename.into()), e))?;
+
+    println!("Arguments: {}", args);
+    println!("Patched image written to {}", output_filename);
+    Ok(())
+}
@@ -1,100 +1,398 @@
-use crc::{crc16, Hasher16};
-use std::env;
-use std::fs::File;
-use std::io;
-use std::io::prelude::*;
-use std::path::Path;
+#[macro_use]
+extern crate clap;
+
+use std::fs;
 use std::process;
-use std::slice;
-use xous_tools::make_type;
 
-fn read_next_tag(b8: *mut u8, byte_offset: &mut usize) -> Result<(u32, u16, u32), ()> {
-    let tag_name = u32::from_le(unsafe { (b8 as *mut u32).add(*byte_offset / 4).read() }) as u32;
-    *byte_offset += 4;
-    let crc = u16::from_le(unsafe { (b8 as *mut u16).add(*byte_offset / 2).read() }) as u16;
-    *byte_offset += 2;
-    let size = u16::from_le(unsafe { (b8 as *mut u16).add(*byte_offset / 2).read() }) as u32 * 4;
-    *byte_offset += 2;
-    Ok((tag_name, crc, size))
-}
+use clap::{App, Arg, ArgMatches};
+use ed25519_dalek::VerifyingKey;
+
+use xous_tools::diff::payload_hash;
+use xous_tools::elf::MiniElfFlags;
+use xous_tools::encryption::{load_key_encryption_key, KeyEncryptionKey};
+use xous_tools::error::Error;
+use xous_tools::layout::ImageLayout;
+use xous_tools::memmap::MemoryMap;
+use xous_tools::seal;
+use xous_tools::tags::bflg::{Bflg, BootFlags};
+use xous_tools::tags::binf::{format_timestamp, BuildInfo};
+use xous_tools::tags::bpar::BootParams;
+use xous_tools::tags::cmpr::Compression;
+use xous_tools::tags::dgst::Digest;
+use xous_tools::tags::encr::Encryption;
+use xous_tools::tags::inie::IniE;
+use xous_tools::signing::{verify_signature, SignatureStatus};
+use xous_tools::tags::memory::MemoryRegions;
+use xous_tools::tags::proc::Processes;
+use xous_tools::tags::sign::Signature;
+use xous_tools::tags::sver::SecurityVersion;
+use xous_tools::tags::xkrn::XousKernel;
+use xous_tools::tags::xram::RamBanks;
+use xous_tools::xous_arguments::{
+    code_name, ParseError, Tag, TagIter, XousArgument, XousArguments,
+};
 
-fn print_tag(b8: *mut u8, size: u32, crc: u16, byte_offset: &mut usize) -> Result<(), ()> {
-    let data = unsafe { slice::from_raw_parts(b8.add(*byte_offset) as *const u8, size as usize) };
-    let data_32 = unsafe { slice::from_raw_parts(b8.add(*byte_offset) as *const u32, size as usize / 4) };
-    *byte_offset += size as usize;
+fn print_raw_tag(tag: &Tag) {
+    print!(
+        "{:08x} ({}) ({} bytes, crc: {:04x}) @ {:08x}:",
+        tag.code,
+        code_name(tag.code),
+        tag.data.len(),
+        tag.crc,
+        tag.offset
+    );
+    for idx in 0..tag.words() {
+        print!(" {:08x}", tag.word(idx));
+    }
+    println!("  CRC: OK");
+}
 
-    let mut digest = crc16::Digest::new(crc16::X25);
-    digest.write(data);
+fn print_xarg(args: &XousArguments, tag: &Tag) {
+    println!("    total size:     {} bytes", tag.word(0) * 4);
+    println!("    version:        {}", tag.word(1));
+    println!(
+        "    main RAM:       {:08x} - {:08x} ({} bytes), named \"{}\"",
+        args.ram_start(),
+        args.ram_start() as u64 + args.ram_length() as u64,
+        args.ram_length(),
+        code_name(args.ram_name())
+    );
+}
 
-    for byte in data_32 {
-        print!(" {:08x}", byte);
+fn print_kernel(xkrn: &XousKernel) {
+    println!("    load offset:    {:08x}", xkrn.load_offset());
+    println!(
+        "    text:           {:08x} - {:08x} ({} bytes)",
+        xkrn.text_offset(),
+        xkrn.text_offset() as u64 + xkrn.text_size() as u64,
+        xkrn.text_size()
+    );
+    println!(
+        "    data:           {:08x} - {:08x} ({} bytes)",
+        xkrn.data_offset(),
+        xkrn.data_offset() as u64 + xkrn.data_size() as u64,
+        xkrn.data_size()
+    );
+    println!("    bss:            {} bytes", xkrn.bss_size());
+    println!("    entrypoint:     {:08x}", xkrn.entrypoint());
+    if xkrn.is_encrypted() {
+        print_decrypted(xkrn.has_data(), xkrn.program());
     }
+}
 
-    if digest.sum16() == crc {
-        print!("  CRC: OK");
+/// Describe an encrypted payload, which has only been decrypted if a key
+/// was given.
+fn print_decrypted(has_data: bool, data: &[u8]) {
+    if has_data {
+        println!(
+            "    decrypted:      {} bytes, sha256 {}",
+            data.len(),
+            payload_hash(data)
+        );
     } else {
-        print!("  CRC: FAIL (calc: {:04x})", digest.sum16());
+        println!("    decrypted:      no, pass --key to decrypt");
     }
-    println!("");
-    Ok(())
 }
 
-fn process_tags(b8: *mut u8) {
-    let mut byte_offset = 0;
-    let mut total_words = 0u32;
-    loop {
-        let (tag_name, crc, size) =
-            read_next_tag(b8, &mut byte_offset).expect("couldn't read next tag");
-        if tag_name == make_type!("XArg") && size == 20 {
-            total_words = unsafe { (b8 as *mut u32).add(byte_offset / 4).read() } * 4;
-            println!(
-                "Found Xous Args Size at offset {}, setting total_words to {}",
-                byte_offset, total_words
-            );
+fn print_init(inie: &IniE) {
+    println!("    load offset:    {:08x}", inie.load_offset());
+    println!("    entrypoint:     {:08x}", inie.entrypoint());
+    // Sections of a compressed program are located within the
+    // decompressed data rather than the image
+    let mut load_offset = if inie.is_compressed() {
+        0
+    } else {
+        inie.load_offset() as u64
+    };
+    let from = if inie.is_compressed() {
+        "unpacked +"
+    } else {
+        ""
+    };
+    for (idx, section) in inie.sections().iter().enumerate() {
+        println!(
+            "    section {}:      {:08x} - {:08x} ({} bytes) from {}{:08x}, flags: {:?}",
+            idx,
+            section.virt,
+            section.virt as u64 + section.size as u64,
+            section.size,
+            from,
+            load_offset,
+            section.flags
+        );
+        if !section.flags.contains(MiniElfFlags::NOCOPY) {
+            load_offset += section.size as u64;
         }
+    }
+    if inie.is_encrypted() {
+        print_decrypted(inie.has_data(), inie.data());
+    }
+}
 
-        let tag_name_bytes = tag_name.to_le_bytes();
-        let tag_name_str = String::from_utf8_lossy(&tag_name_bytes);
+fn print_regions(regions: &MemoryRegions) {
+    for region in regions.regions() {
         print!(
-            "{:08x} ({}) ({} bytes, crc: {:04x}):",
-            tag_name, tag_name_str, size, crc
+            "    region {}:    {:08x} - {:08x} ({} bytes), {}",
+            code_name(region.name()),
+            region.start(),
+            region.start() as u64 + region.length() as u64,
+            region.length(),
+            region.attributes()
         );
-        print_tag(b8, size, crc, &mut byte_offset).expect("couldn't read next data");
-
-        if byte_offset as u32 == total_words {
-            return;
-        }
-        if byte_offset as u32 > total_words {
-            panic!(
-                "exceeded total words ({}) with byte_offset of {}",
-                total_words, byte_offset
-            );
+        if region.unknown_attributes() != 0 {
+            print!(", unknown({:08x})", region.unknown_attributes());
         }
+        println!();
     }
 }
 
-fn doit() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
+fn print_ram_banks(banks: &RamBanks) {
+    for bank in banks.banks() {
         println!(
-            "Usage: {} args.bin",
-            args.get(0).unwrap_or(&"read-tags".to_owned())
+            "    ram bank {}:  {:08x} - {:08x} ({} bytes)",
+            code_name(bank.name()),
+            bank.start(),
+            bank.start() as u64 + bank.length() as u64,
+            bank.length()
         );
-        process::exit(1);
     }
+}
+
+fn print_compression(cmpr: &Compression) {
+    println!("    algorithm:      {}", cmpr.algorithm().name());
+    println!(
+        "    size:           {} bytes stored in {} bytes ({} bytes saved)",
+        cmpr.uncompressed_size(),
+        cmpr.stored_size(),
+        cmpr.uncompressed_size() as i64 - cmpr.stored_size() as i64
+    );
+}
+
+fn print_encryption(encr: &Encryption) {
+    println!("    algorithm:      aes-256-gcm, key wrapped with aes-256-kw");
+    println!("    nonce:          {}", encr.nonce_hex());
+    println!("    tag:            {}", encr.tag_hex());
+    println!("    wrapped key:    {}", encr.wrapped_key_hex());
+}
 
-    let input_filename = Path::new(args.get(1).unwrap()).to_path_buf();
+fn print_bflg(bflg: &Bflg) {
+    for (name, flag) in BootFlags::NAMES {
+        println!("    {:<16}{}", format!("{}:", name), bflg.contains(*flag));
+    }
+    if bflg.unknown_bits() != 0 {
+        println!("    unknown bits:   {:08x}", bflg.unknown_bits());
+    }
+}
 
-    let mut tag_buf = vec![];
-    {
-        let mut f = File::open(input_filename)?;
-        f.read_to_end(&mut tag_buf)?;
+fn print_boot_params(bpar: &BootParams) {
+    for (key, value) in bpar.params() {
+        println!("    {} = {}", key, value);
     }
+}
+
+fn print_build_info(binf: &BuildInfo) {
+    println!("    tool version:   xous-tools {}", binf.tool_version());
+    // Images built without SOURCE_DATE_EPOCH record a time of 0
+    if binf.timestamp() == 0 {
+        println!("    built:          unknown");
+    } else {
+        println!(
+            "    built:          {} ({})",
+            format_timestamp(binf.timestamp()),
+            binf.timestamp()
+        );
+    }
+    if !binf.revision().is_empty() {
+        println!("    revision:       {}", binf.revision());
+    }
+    for (idx, hash) in binf.elf_hashes_hex().iter().enumerate() {
+        if idx == 0 {
+            println!("    kernel ELF:     sha256 {}", hash);
+        } else {
+            println!("    init {} ELF:     sha256 {}", idx - 1, hash);
+        }
+    }
+}
 
-    let byte_buffer = tag_buf.as_mut_ptr();
-    process_tags(byte_buffer);
+fn print_processes(processes: &Processes) {
+    for (idx, process) in processes.processes().iter().enumerate() {
+        println!("    {:<16}{}", format!("init {}:", idx), process);
+    }
+}
+
+fn print_security_version(sver: &SecurityVersion) {
+    println!("    version:        {}", sver.version());
+}
+
+fn print_digest(dgst: &Digest, image: &[u8]) {
+    println!("    sha256:         {}", dgst.hex());
+    match seal::image_digest(image) {
+        Ok(calculated) if &calculated == dgst.digest() => println!("    image digest:   OK"),
+        Ok(calculated) => {
+            let mut actual = Digest::new();
+            actual.set_digest(calculated);
+            println!("    image digest:   MISMATCH, contents hash to {}", actual.hex());
+        }
+        Err(e) => println!("    image digest:   couldn't be checked: {}", e),
+    }
+}
+
+fn print_signature(sign: &Signature, image: &[u8]) {
+    println!("    public key:     {}", sign.public_key_hex());
+    println!("    signature:      {}", sign.signature_hex());
+    // This only shows the image is intact; use verify-signature to check
+    // that it was signed by a trusted key
+    let status = VerifyingKey::from_bytes(sign.public_key())
+        .map_err(|e| e.to_string())
+        .and_then(|key| verify_signature(image, &key).map_err(|e| e.to_string()));
+    match status {
+        Ok(SignatureStatus::Valid) => println!("    self-check:     OK"),
+        Ok(status) => println!("    self-check:     {}", status),
+        Err(e) => println!("    self-check:     couldn't be checked: {}", e),
+    }
+}
+
+fn print_argument(arg: &dyn XousArgument, image: &[u8]) {
+    let any = arg.as_any();
+    if let Some(xkrn) = any.downcast_ref::<XousKernel>() {
+        print_kernel(xkrn);
+    } else if let Some(inie) = any.downcast_ref::<IniE>() {
+        print_init(inie);
+    } else if let Some(regions) = any.downcast_ref::<MemoryRegions>() {
+        print_regions(regions);
+    } else if let Some(banks) = any.downcast_ref::<RamBanks>() {
+        print_ram_banks(banks);
+    } else if let Some(bflg) = any.downcast_ref::<Bflg>() {
+        print_bflg(bflg);
+    } else if let Some(cmpr) = any.downcast_ref::<Compression>() {
+        print_compression(cmpr);
+    } else if let Some(dgst) = any.downcast_ref::<Digest>() {
+        print_digest(dgst, image);
+    } else if let Some(encr) = any.downcast_ref::<Encryption>() {
+        print_encryption(encr);
+    } else if let Some(sign) = any.downcast_ref::<Signature>() {
+        print_signature(sign, image);
+    } else if let Some(bpar) = any.downcast_ref::<BootParams>() {
+        print_boot_params(bpar);
+    } else if let Some(binf) = any.downcast_ref::<BuildInfo>() {
+        print_build_info(binf);
+    } else if let Some(sver) = any.downcast_ref::<SecurityVersion>() {
+        print_security_version(sver);
+    } else if let Some(processes) = any.downcast_ref::<Processes>() {
+        print_processes(processes);
+    } else {
+        println!("    (unknown tag)");
+    }
+}
+
+/// Walk the raw tags so that everything up to a problem is still shown.
+fn print_raw_tags(image: &[u8]) -> Result<(), ParseError> {
+    for tag in TagIter::new(image) {
+        print_raw_tag(&tag?);
+    }
     Ok(())
 }
+
+fn process_tags(image: &[u8], key: Option<&KeyEncryptionKey>) -> Result<(), ParseError> {
+    let args = match XousArguments::parse_with_key(image, key) {
+        Ok(args) => args,
+        Err(e) => {
+            print_raw_tags(image)?;
+            return Err(e);
+        }
+    };
+
+    let mut tags = TagIter::new(image);
+    let xarg = tags.next().expect("image had no XArg tag")?;
+    print_raw_tag(&xarg);
+    print_xarg(&args, &xarg);
+
+    let processes = args.get::<Processes>();
+    let mut init_index = 0;
+    for (tag, arg) in tags.zip(args.arguments.iter()) {
+        print_raw_tag(&tag?);
+        print_argument(arg.as_ref(), image);
+        if arg.as_any().is::<IniE>() {
+            if let Some(process) = processes.and_then(|p| p.get(init_index)) {
+                println!("    process:        {}", process);
+            }
+            init_index += 1;
+        }
+    }
+    Ok(())
+}
+
 fn main() {
-    doit().unwrap();
+    let matches = App::new("Xous Tag Reader")
+        .version(crate_version!())
+        .author("Sean Cross <sean@xobs.io>")
+        .about("Decode and print the tags in a Xous boot image")
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .takes_value(false)
+                .help("Print a JSON description of the image instead of text"),
+        )
+        .arg(
+            Arg::with_name("memory-map")
+                .long("memory-map")
+                .takes_value(false)
+                .conflicts_with("json")
+                .help("Print a map of main RAM, memory regions and program sections instead of the tags"),
+        )
+        .arg(
+            Arg::with_name("memory-map-svg")
+                .long("memory-map-svg")
+                .value_name("SVG_FILE")
+                .takes_value(true)
+                .help("Also draw a map of main RAM, memory regions and program sections as SVG"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .short("k")
+                .long("key")
+                .value_name("KEK_FILE")
+                .takes_value(true)
+                .help("Key-encryption key used to decrypt encrypted payloads (raw or hex)"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("ARGS_BIN")
+                .required(true)
+                .help("Image file created by create-image"),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let input_filename = matches.value_of("input").unwrap();
+    let image = fs::read(input_filename).map_err(|e| Error::Io(Some(input_filename.into()), e))?;
+    let key = matches
+        .value_of("key")
+        .map(load_key_encryption_key)
+        .transpose()?;
+    let bad_image = |e| Error::Image(input_filename.into(), e);
+
+    if let Some(svg_filename) = matches.value_of("memory-map-svg") {
+        let args = XousArguments::parse_with_key(&image, key.as_ref()).map_err(bad_image)?;
+        fs::write(svg_filename, MemoryMap::new(&args).to_svg())
+            .map_err(|e| Error::Io(Some(svg_filename.into()), e))?;
+    }
+
+    if matches.is_present("memory-map") {
+        let args = XousArguments::parse_with_key(&image, key.as_ref()).map_err(bad_image)?;
+        print!("{}", MemoryMap::new(&args).to_text());
+    } else if matches.is_present("json") {
+        let args = XousArguments::parse_with_key(&image, key.as_ref()).map_err(bad_image)?;
+        let layout = ImageLayout::new(&args).map_err(bad_image)?;
+        println!("{}", layout.to_json());
+    } else {
+        process_tags(&image, key.as_ref()).map_err(bad_image)?;
+    }
+    Ok(())
 }
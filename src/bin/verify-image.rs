@@ -0,0 +1,64 @@
+#[macro_use]
+extern crate clap;
+
+use std::fs;
+use std::process;
+
+use clap::{App, Arg, ArgMatches};
+
+use xous_tools::error::Error;
+use xous_tools::tags::sver::image_security_version;
+use xous_tools::verify::Verifier;
+
+fn read_image(filename: &str) -> Result<Vec<u8>, Error> {
+    fs::read(filename).map_err(|e| Error::Io(Some(filename.into()), e))
+}
+
+fn main() {
+    let matches = App::new("Xous Image Verifier")
+        .version(crate_version!())
+        .author("Sean Cross <sean@xobs.io>")
+        .about("Check that a Xous boot image is internally consistent")
+        .arg(
+            Arg::with_name("previous-image")
+                .long("previous-image")
+                .value_name("PREVIOUS_ARGS_BIN")
+                .takes_value(true)
+                .help("Require a security version at least as high as this image's"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("ARGS_BIN")
+                .required(true)
+                .help("Image file created by create-image"),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let input_filename = matches.value_of("input").unwrap();
+    let image = read_image(input_filename)?;
+
+    let mut verifier = Verifier::new();
+    if let Some(previous_filename) = matches.value_of("previous-image") {
+        let previous = read_image(previous_filename)?;
+        let version = image_security_version(&previous)
+            .map_err(|e| Error::Image(previous_filename.into(), e))?;
+        verifier = verifier.minimum_version(version);
+    }
+
+    let violations = verifier.verify(&image);
+    if !violations.is_empty() {
+        return Err(Error::Verification(input_filename.into(), violations));
+    }
+    match image_security_version(&image) {
+        Ok(version) => println!("{}: OK, security version {}", input_filename, version),
+        Err(_) => println!("{}: OK", input_filename),
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+#[macro_use]
+extern crate clap;
+
+use std::fs;
+use std::process;
+
+use clap::{App, Arg, ArgMatches};
+
+use xous_tools::error::Error;
+use xous_tools::signing::{load_verifying_key, verify_signature, SignatureStatus};
+
+fn main() {
+    let matches = App::new("Xous Signature Verifier")
+        .version(crate_version!())
+        .author("Sean Cross <sean@xobs.io>")
+        .about("Check that a Xous boot image was signed by a particular key")
+        .arg(
+            Arg::with_name("key")
+                .short("k")
+                .long("key")
+                .value_name("PUBLIC_KEY")
+                .takes_value(true)
+                .required(true)
+                .help("Ed25519 public key to check against (PEM, raw or hex)"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("ARGS_BIN")
+                .required(true)
+                .help("Image file created by create-image"),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let input_filename = matches.value_of("input").unwrap();
+    let key = load_verifying_key(matches.value_of("key").unwrap())?;
+    let image = fs::read(input_filename).map_err(|e| Error::Io(Some(input_filename.into()), e))?;
+
+    match verify_signature(&image, &key).map_err(|e| Error::Image(input_filename.into(), e))? {
+        SignatureStatus::Valid => println!("{}: signature is valid", input_filename),
+        status => return Err(Error::Signature(input_filename.into(), status)),
+    }
+    Ok(())
+}
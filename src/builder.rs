@@ -0,0 +1,748 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest as _, Sha256};
+
+use crate::elf::{read_minielf, read_program};
+use crate::encryption::load_key_encryption_key;
+use crate::error::{Error, Result};
+use crate::layout::ImageLayout;
+use crate::params::{check_param, ParamSchema};
+use crate::tags::bflg::{Bflg, BootFlags};
+use crate::tags::binf::BuildInfo;
+use crate::tags::bpar::BootParams;
+use crate::tags::cmpr::Algorithm;
+use crate::tags::dgst::Digest;
+use crate::tags::inie::IniE;
+use crate::signing::load_signing_key;
+use crate::tags::memory::{MemoryRegion, MemoryRegions, RegionAttributes, RegionNames};
+use crate::tags::proc::{ProcessInfo, Processes, MAX_NAME_LENGTH};
+use crate::tags::sign::Signature;
+use crate::tags::sver::{image_security_version, SecurityVersion};
+use crate::tags::xkrn::XousKernel;
+use crate::tags::xram::{RamBank, RamBanks};
+use crate::utils::{hex, parse_csr_csv};
+use crate::validate::{validate_layout, LayoutProblem};
+use crate::xous_arguments::{code_name, XousArgumentCode, XousArguments};
+
+/// The result of building an image.
+pub struct BuiltImage {
+    /// The assembled and finalized arguments
+    pub args: XousArguments,
+
+    /// The serialized image, ready to be written to disk
+    pub image: Vec<u8>,
+
+    /// A description of where everything ended up
+    pub layout: ImageLayout,
+
+    /// Anything suspicious that didn't prevent the image from being built
+    pub warnings: Vec<String>,
+
+    /// Overlapping or misplaced items, which are only ever present if
+    /// layout checks were turned off with `check_layout(false)`
+    pub layout_problems: Vec<LayoutProblem>,
+}
+
+impl BuiltImage {
+    /// SHA-256 of the serialized image as a hex string, which is what
+    /// `sha256sum` prints for the image file.
+    pub fn sha256(&self) -> String {
+        hex(&Sha256::digest(&self.image))
+    }
+}
+
+/// Assembles a boot image from a kernel, initial programs and a memory map.
+///
+/// ```no_run
+/// use xous_tools::builder::ImageBuilder;
+///
+/// let built = ImageBuilder::new()
+///     .kernel("xous-kernel")
+///     .init("shell")
+///     .csv("csr.csv")
+///     .debug(true)
+///     .build()
+///     .unwrap();
+/// std::fs::write("args.bin", &built.image).unwrap();
+/// ```
+///
+/// Building is deterministic: the same inputs always give the same image,
+/// as long as the payloads aren't encrypted.  The build time recorded in
+/// the image is 0 unless it is given with `timestamp()` or
+/// `SOURCE_DATE_EPOCH`.
+#[derive(Clone, Debug, Default)]
+pub struct ImageBuilder {
+    kernel: Option<PathBuf>,
+    kernel_compression: Option<Algorithm>,
+    inits: Vec<Init>,
+    csv: Option<PathBuf>,
+    ram: Option<(u32, u32)>,
+    main_ram: Option<String>,
+    extra_ram: Vec<String>,
+    regions: Vec<(String, u32, u32)>,
+    region_attributes: Vec<(String, RegionAttributes)>,
+    region_names: Vec<(String, String)>,
+    flags: BootFlags,
+    params: BootParams,
+    param_schema: Option<PathBuf>,
+    signing_key: Option<PathBuf>,
+    encryption_key: Option<PathBuf>,
+    security_version: Option<u32>,
+    previous_image: Option<PathBuf>,
+    revision: Option<String>,
+    timestamp: Option<u64>,
+    skip_layout_check: bool,
+}
+
+/// An init program to bundle into the image.
+#[derive(Clone, Debug)]
+struct Init {
+    path: PathBuf,
+    compression: Option<Algorithm>,
+    process: ProcessInfo,
+}
+
+/// Round the length of the region `name` up to a whole number of pages.
+fn round_mem(name: &str, length: u32) -> Result<u32> {
+    length.checked_add(4095).map(|len| len & !4095).ok_or_else(|| {
+        Error::InvalidArgument(format!(
+            "memory region {} is too large ({} bytes)",
+            name, length
+        ))
+    })
+}
+
+/// Record that the region `name` is called `code` in the image, failing if
+/// another region is already called that.
+fn check_region_name(
+    sources: &mut BTreeMap<XousArgumentCode, String>,
+    code: XousArgumentCode,
+    name: &str,
+) -> Result<()> {
+    if let Some(other) = sources.insert(code, name.to_owned()) {
+        return Err(Error::RegionNameCollision(other, name.to_owned(), code));
+    }
+    Ok(())
+}
+
+/// SHA-256 of an input file, for the `BInf` tag.
+fn hash_file(path: &Path) -> Result<[u8; 32]> {
+    let contents = fs::read(path).map_err(|e| Error::Io(Some(path.to_path_buf()), e))?;
+    Ok(Sha256::digest(&contents).into())
+}
+
+/// The time to record in the `BInf` tag, which is `SOURCE_DATE_EPOCH` if
+/// it is set.  Otherwise it is 0 rather than the current time, so that
+/// builds can be reproduced.
+fn build_timestamp() -> Result<u64> {
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch.trim().parse().map_err(|_| {
+            Error::InvalidArgument(format!(
+                "SOURCE_DATE_EPOCH should be a number of seconds, not \"{}\"",
+                epoch
+            ))
+        }),
+        Err(_) => Ok(0),
+    }
+}
+
+impl ImageBuilder {
+    pub fn new() -> ImageBuilder {
+        ImageBuilder::default()
+    }
+
+    /// Kernel ELF image to bundle into the image
+    pub fn kernel<P: AsRef<Path>>(mut self, path: P) -> ImageBuilder {
+        self.kernel = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Compress the kernel in the image
+    pub fn compress_kernel(mut self, algorithm: Algorithm) -> ImageBuilder {
+        self.kernel_compression = Some(algorithm);
+        self
+    }
+
+    /// Add an initial program.  Programs are loaded in the order they are added.
+    pub fn init<P: AsRef<Path>>(self, path: P) -> ImageBuilder {
+        self.init_with(path, None, ProcessInfo::default())
+    }
+
+    /// Add an initial program that is compressed in the image
+    pub fn init_compressed<P: AsRef<Path>>(self, path: P, algorithm: Algorithm) -> ImageBuilder {
+        self.init_with(path, Some(algorithm), ProcessInfo::default())
+    }
+
+    /// Add an initial program with a name and loader hints.  If the name is
+    /// empty, the program is named after its file.
+    pub fn init_with<P: AsRef<Path>>(
+        mut self,
+        path: P,
+        compression: Option<Algorithm>,
+        process: ProcessInfo,
+    ) -> ImageBuilder {
+        self.inits.push(Init {
+            path: path.as_ref().to_path_buf(),
+            compression,
+            process,
+        });
+        self
+    }
+
+    /// Take the memory map from a `csr.csv` file from LiteX.  The largest
+    /// region with "ram" in its name becomes main RAM.
+    pub fn csv<P: AsRef<Path>>(mut self, path: P) -> ImageBuilder {
+        self.csv = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Specify main RAM directly
+    pub fn ram(mut self, offset: u32, size: u32) -> ImageBuilder {
+        self.ram = Some((offset, size));
+        self
+    }
+
+    /// Use this region from the CSV file as main RAM, rather than the
+    /// largest one with "ram" in its name
+    pub fn main_ram(mut self, name: &str) -> ImageBuilder {
+        self.main_ram = Some(name.to_owned());
+        self
+    }
+
+    /// Let the kernel allocate from this region from the CSV file or from
+    /// `region()`, as a bank of RAM in addition to main RAM
+    pub fn extra_ram(mut self, name: &str) -> ImageBuilder {
+        self.extra_ram.push(name.to_owned());
+        self
+    }
+
+    /// Add an extra memory region, in addition to any from the CSV file
+    pub fn region(mut self, name: &str, start: u32, length: u32) -> ImageBuilder {
+        self.regions.push((name.to_owned(), start, length));
+        self
+    }
+
+    /// Override the attributes of a memory region, which otherwise come from
+    /// its name or from the CSV file
+    pub fn region_attributes(mut self, name: &str, attributes: RegionAttributes) -> ImageBuilder {
+        self.region_attributes.push((name.to_owned(), attributes));
+        self
+    }
+
+    /// Call the memory region named `name` in the CSV file or in `region()`
+    /// by this four-character code in the image, rather than one made up
+    /// from its name
+    pub fn region_name(mut self, name: &str, code: &str) -> ImageBuilder {
+        self.region_names.push((name.to_owned(), code.to_owned()));
+        self
+    }
+
+    /// Whether layout problems, such as overlapping regions or sections,
+    /// fail the build.  If not, they are returned in
+    /// `BuiltImage::layout_problems` instead.  Defaults to `true`.
+    pub fn check_layout(mut self, check: bool) -> ImageBuilder {
+        self.skip_layout_check = !check;
+        self
+    }
+
+    /// Reduce kernel-userspace security and enable debugging programs
+    pub fn debug(mut self, debug: bool) -> ImageBuilder {
+        self.flags.set(BootFlags::DEBUG, debug);
+        self
+    }
+
+    /// Set these boot flags, in addition to any already set
+    pub fn flags(mut self, flags: BootFlags) -> ImageBuilder {
+        self.flags |= flags;
+        self
+    }
+
+    /// Pass a `key=value` boot parameter to the kernel, replacing any
+    /// earlier value for `key`
+    pub fn param(mut self, key: &str, value: &str) -> ImageBuilder {
+        self.params.set(key, value);
+        self
+    }
+
+    /// Check the boot parameters against the schema in this TOML file
+    pub fn param_schema<P: AsRef<Path>>(mut self, path: P) -> ImageBuilder {
+        self.param_schema = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sign the image with the Ed25519 private key in this file
+    pub fn signing_key<P: AsRef<Path>>(mut self, path: P) -> ImageBuilder {
+        self.signing_key = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Encrypt the kernel and init programs, wrapping each payload's key
+    /// with the key-encryption key in this file
+    pub fn encryption_key<P: AsRef<Path>>(mut self, path: P) -> ImageBuilder {
+        self.encryption_key = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Record the security version of the image, to prevent rollback
+    pub fn security_version(mut self, version: u32) -> ImageBuilder {
+        self.security_version = Some(version);
+        self
+    }
+
+    /// Fail the build if the security version is lower than that of this
+    /// previously shipped image
+    pub fn previous_image<P: AsRef<Path>>(mut self, path: P) -> ImageBuilder {
+        self.previous_image = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Record the source revision the image was built from, such as a git
+    /// commit, in the `BInf` tag
+    pub fn revision(mut self, revision: &str) -> ImageBuilder {
+        self.revision = Some(revision.to_owned());
+        self
+    }
+
+    /// Record this build time, in seconds since the Unix epoch, rather than
+    /// `SOURCE_DATE_EPOCH` or 0 if that isn't set
+    pub fn timestamp(mut self, timestamp: u64) -> ImageBuilder {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Build the image twice from scratch and check that both builds are
+    /// byte-for-byte identical, returning the first.
+    pub fn build_reproducible(&self) -> Result<BuiltImage> {
+        if self.encryption_key.is_some() {
+            return Err(Error::NotReproducible(
+                "encrypted payloads use a new random key every time".to_owned(),
+            ));
+        }
+        let timestamp = match self.timestamp {
+            Some(timestamp) => timestamp,
+            None => build_timestamp()?,
+        };
+        let builder = self.clone().timestamp(timestamp);
+        let first = builder.build()?;
+        let second = builder.build()?;
+        if first.image != second.image {
+            let offset = first
+                .image
+                .iter()
+                .zip(&second.image)
+                .position(|(a, b)| a != b)
+                .unwrap_or_else(|| first.image.len().min(second.image.len()));
+            return Err(Error::NotReproducible(format!(
+                "two builds of the same inputs first differ at offset {}",
+                offset
+            )));
+        }
+        Ok(first)
+    }
+
+    /// Read every input file and assemble the image.
+    pub fn build(&self) -> Result<BuiltImage> {
+        let mut warnings = vec![];
+        let mut ram_offset = 0;
+        let mut ram_size = 0;
+        let mut regions = MemoryRegions::new();
+
+        let kernel_path = self.kernel.as_ref().ok_or(Error::NoKernel)?;
+        if self.ram.is_none() && self.csv.is_none() {
+            return Err(Error::NoMemory);
+        }
+        if self.main_ram.is_some() && self.csv.is_none() {
+            return Err(Error::InvalidArgument(
+                "main RAM can only be chosen by name from a csv file".to_owned(),
+            ));
+        }
+
+        let mut names = RegionNames::new();
+        for (name, code) in &self.region_names {
+            let code = MemoryRegion::parse_code(code).ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "memory region {} can't be called \"{}\", which isn't four ASCII characters",
+                    name, code
+                ))
+            })?;
+            names.set(name, code);
+        }
+        let mut ram_name = names.code("sram");
+
+        match &self.param_schema {
+            Some(path) => ParamSchema::load(path)?.validate(self.params.params())?,
+            None => {
+                for (key, value) in self.params.params() {
+                    check_param(key, value)?;
+                }
+            }
+        }
+
+        if let Some(previous_path) = &self.previous_image {
+            let previous = std::fs::read(previous_path)
+                .map_err(|e| Error::Io(Some(previous_path.clone()), e))?;
+            let previous_version = image_security_version(&previous).map_err(|e| {
+                Error::InvalidArgument(format!("{}: {}", previous_path.display(), e))
+            })?;
+            let version = self.security_version.unwrap_or(0);
+            if version < previous_version {
+                return Err(Error::Rollback(
+                    previous_path.clone(),
+                    previous_version,
+                    version,
+                ));
+            }
+        }
+
+        // Where each region's code came from, to catch two regions that
+        // end up with the same one
+        let mut region_sources = BTreeMap::new();
+
+        if let Some((offset, size)) = self.ram {
+            ram_offset = offset;
+            ram_size = size;
+        }
+
+        if let Some(csv_path) = &self.csv {
+            let hv = parse_csr_csv(&csv_path.to_string_lossy())
+                .map_err(|e| Error::Csv(csv_path.clone(), e))?;
+            warnings.extend(hv.warnings.iter().cloned());
+            let mut found_ram_name = None;
+
+            if let Some(name) = &self.main_ram {
+                let v = hv.regions.get(name).ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "{}: no memory region named {} to use as main RAM",
+                        csv_path.display(),
+                        name
+                    ))
+                })?;
+                ram_size = round_mem(name, v.length)?;
+                ram_offset = v.start;
+                found_ram_name = Some(name.clone());
+            } else {
+                // Look for the largest "ram" block, which we'll treat as main memory
+                for (k, v) in &hv.regions {
+                    if k.contains("ram") && v.length > ram_size {
+                        ram_size = round_mem(k, v.length)?;
+                        ram_offset = v.start;
+                        found_ram_name = Some(k.clone());
+                    }
+                }
+            }
+
+            let found_ram_name =
+                found_ram_name.ok_or_else(|| Error::NoRamRegion(csv_path.clone()))?;
+
+            // Now that we know which block is ram, add the other regions.
+            // These come out of a BTreeMap in name order, so the image
+            // doesn't depend on the order of the lines in the CSV file.
+            ram_name = names.code(&found_ram_name);
+            region_sources.insert(ram_name, found_ram_name.clone());
+            for (k, v) in &hv.regions {
+                // Don't add the RAM section to the extra regions block.
+                if *k == found_ram_name {
+                    continue;
+                }
+                // Don't add empty sections.
+                let length = round_mem(k, v.length)?;
+                if length == 0 {
+                    warnings.push(format!("skipping empty memory region {}", k));
+                    continue;
+                }
+                let region_name = names.code(k);
+                check_region_name(&mut region_sources, region_name, k)?;
+                regions.add(
+                    MemoryRegion::new(v.start, length, region_name)
+                        .with_attributes(RegionAttributes::default_for(k, v.io)),
+                );
+            }
+        }
+
+        if self.csv.is_none() {
+            region_sources.insert(ram_name, "sram".to_owned());
+        }
+        for (name, start, length) in &self.regions {
+            let region_name = names.code(name);
+            check_region_name(&mut region_sources, region_name, name)?;
+            regions.add(
+                MemoryRegion::new(*start, round_mem(name, *length)?, region_name)
+                    .with_attributes(RegionAttributes::default_for(name, false)),
+            );
+        }
+
+        // Extra RAM banks come out of the regions, just like main RAM
+        let mut banks = RamBanks::new();
+        for name in &self.extra_ram {
+            let code = names.code(name);
+            if code == ram_name {
+                return Err(Error::InvalidArgument(format!(
+                    "memory region {} is already main RAM",
+                    name
+                )));
+            }
+            let region = regions.remove(code).ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "can't use memory region {} as extra RAM, which doesn't exist",
+                    name
+                ))
+            })?;
+            banks.add(RamBank::new(region.start(), region.length(), code));
+        }
+
+        for (name, attributes) in &self.region_attributes {
+            let region = regions
+                .get_mut(names.code(name))
+                .ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "can't set attributes of memory region {}, which doesn't exist",
+                        name
+                    ))
+                })?;
+            region.set_attributes(*attributes);
+        }
+        for region in regions.regions() {
+            if region
+                .attributes()
+                .contains(RegionAttributes::CACHEABLE | RegionAttributes::DEVICE)
+            {
+                warnings.push(format!(
+                    "memory region {} is marked as both cacheable and device memory",
+                    code_name(region.name())
+                ));
+            }
+        }
+
+        let mut args = XousArguments::new(ram_offset, ram_size, ram_name);
+
+        if !regions.is_empty() {
+            args.add(regions);
+        }
+
+        if !banks.is_empty() {
+            args.add(banks);
+        }
+
+        if !self.flags.is_empty() {
+            args.add(Bflg::from_flags(self.flags));
+        }
+
+        if !self.params.is_empty() {
+            args.add(self.params.clone());
+        }
+
+        if let Some(version) = self.security_version {
+            args.add(SecurityVersion::new(version));
+        }
+
+        if !self.inits.is_empty() {
+            let mut processes = Processes::new();
+            for init in &self.inits {
+                let mut process = init.process.clone();
+                if process.name.is_empty() {
+                    process.name = init
+                        .path
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                }
+                if process.name.len() > MAX_NAME_LENGTH {
+                    return Err(Error::InvalidArgument(format!(
+                        "process name for {} is too long",
+                        init.path.display()
+                    )));
+                }
+                if processes.processes().iter().any(|p| p.name == process.name) {
+                    warnings.push(format!(
+                        "more than one init program is named \"{}\"",
+                        process.name
+                    ));
+                }
+                processes.add(process);
+            }
+            args.add(processes);
+        }
+
+        let mut elf_hashes = vec![hash_file(kernel_path)?];
+        for init in &self.inits {
+            elf_hashes.push(hash_file(&init.path)?);
+        }
+        let timestamp = match self.timestamp {
+            Some(timestamp) => timestamp,
+            None => build_timestamp()?,
+        };
+        args.add(BuildInfo::new(
+            timestamp,
+            env!("CARGO_PKG_VERSION"),
+            self.revision.as_deref().unwrap_or(""),
+            elf_hashes,
+        ));
+
+        // Filled in once the image has been serialized
+        args.add(Digest::new());
+
+        let kernel =
+            read_program(kernel_path).map_err(|e| Error::Elf(kernel_path.clone(), e))?;
+        let kek = match &self.encryption_key {
+            Some(path) => Some(load_key_encryption_key(path)?),
+            None => None,
+        };
+
+        // Init programs come before the kernel.  All of these tags point to
+        // payloads that immediately follow the tags, so their offsets are
+        // only known once every tag has been added.
+        // A compressed payload is described by a `Cmpr` tag immediately
+        // before the tag that owns it.  Payloads that don't get any smaller
+        // are left uncompressed.  An encrypted payload is compressed first,
+        // and its `Encr` tag goes between the `Cmpr` tag and the owner.
+        for init in &self.inits {
+            let elf = read_minielf(&init.path).map_err(|e| Error::Elf(init.path.clone(), e))?;
+            let mut inie = IniE::new(elf.entry_point, elf.sections, elf.program);
+            if let Some(cmpr) = init.compression.and_then(|a| inie.compress(a)) {
+                args.add(cmpr);
+            }
+            if let Some(kek) = &kek {
+                args.add(inie.encrypt(kek)?);
+            }
+            args.add(inie);
+        }
+
+        let mut xkrn = XousKernel::new(
+            kernel.text_offset,
+            kernel.text_size,
+            kernel.data_offset,
+            kernel.data_size,
+            kernel.bss_size,
+            kernel.entry_point,
+            kernel.program,
+        );
+        if let Some(cmpr) = self.kernel_compression.and_then(|a| xkrn.compress(a)) {
+            args.add(cmpr);
+        }
+        if let Some(kek) = &kek {
+            args.add(xkrn.encrypt(kek)?);
+        }
+        args.add(xkrn);
+
+        // The signature covers everything, so it goes last
+        if let Some(key_path) = &self.signing_key {
+            args.add(Signature::new(load_signing_key(key_path)?));
+        }
+
+        let layout_problems = validate_layout(&args);
+        if !layout_problems.is_empty() && !self.skip_layout_check {
+            return Err(Error::Layout(layout_problems));
+        }
+
+        let mut image = vec![];
+        args.write(&mut image)?;
+        let layout = ImageLayout::new(&args)?;
+
+        Ok(BuiltImage {
+            args,
+            image,
+            layout,
+            warnings,
+            layout_problems,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{init, kernel, write_elfs, TempDir};
+    use crate::verify::verify_image;
+
+    fn builder(dir: &TempDir) -> ImageBuilder {
+        let (kernel, init) = write_elfs(dir, 2);
+        ImageBuilder::new()
+            .kernel(kernel)
+            .init(init)
+            .ram(0x4000_0000, 0x0100_0000)
+            .region("CSRs", 0xf000_0000, 0x3000)
+    }
+
+    #[test]
+    fn build_parse_verify() {
+        let dir = TempDir::new();
+        let built = builder(&dir).build().unwrap();
+        assert!(built.warnings.is_empty());
+        assert!(built.layout_problems.is_empty());
+        assert!(verify_image(&built.image).is_empty());
+
+        let args = XousArguments::parse(&built.image).unwrap();
+        assert_eq!(args.ram_start(), 0x4000_0000);
+        assert_eq!(args.ram_length(), 0x0100_0000);
+        let xkrn = args.get::<XousKernel>().unwrap();
+        assert_eq!(xkrn.entrypoint(), kernel().entrypoint());
+        assert_eq!(xkrn.program(), kernel().program());
+        let inie = args.get::<IniE>().unwrap();
+        for (a, b) in inie.sections().iter().zip(init(2).sections()) {
+            assert_eq!((a.virt, a.size, a.flags), (b.virt, b.size, b.flags));
+        }
+        assert_eq!(inie.data(), init(2).data());
+        let regions = args.get::<MemoryRegions>().unwrap().regions();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].start(), 0xf000_0000);
+    }
+
+    #[test]
+    fn same_inputs_same_image() {
+        let dir = TempDir::new();
+        let a = builder(&dir).build().unwrap();
+        let b = builder(&dir).build_reproducible().unwrap();
+        assert_eq!(a.image, b.image);
+
+        let timestamp = |built: &BuiltImage| built.args.get::<BuildInfo>().unwrap().timestamp();
+        let c = builder(&dir).timestamp(1_700_000_000).build().unwrap();
+        assert_eq!(timestamp(&c), 1_700_000_000);
+        assert_ne!(a.image, c.image);
+
+        // A new key is made for every encrypted payload
+        let kek = dir.write("kek.bin", &[7; 32]);
+        let err = builder(&dir).encryption_key(kek).build_reproducible();
+        assert!(matches!(err, Err(Error::NotReproducible(_))));
+    }
+
+    #[test]
+    fn missing_inputs() {
+        let dir = TempDir::new();
+        let (kernel, _) = write_elfs(&dir, 2);
+        let err = ImageBuilder::new().ram(0x4000_0000, 0x1000).build();
+        assert!(matches!(err, Err(Error::NoKernel)));
+        let err = ImageBuilder::new().kernel(kernel).build();
+        assert!(matches!(err, Err(Error::NoMemory)));
+    }
+
+    #[test]
+    fn process_name_too_long() {
+        let dir = TempDir::new();
+        let (_, init) = write_elfs(&dir, 3);
+        let process = ProcessInfo::new(&"x".repeat(MAX_NAME_LENGTH + 1));
+        let err = builder(&dir).init_with(init, None, process).build();
+        assert!(matches!(err, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn region_too_large() {
+        let dir = TempDir::new();
+        let err = builder(&dir).region("Huge", 0, 0xffff_f001).build();
+        assert!(matches!(err, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn overlapping_regions() {
+        let dir = TempDir::new();
+        let build = builder(&dir).region("Oops", 0x40ff_f000, 0x2000);
+        let err = build.clone().build();
+        assert!(matches!(err, Err(Error::Layout(problems)) if problems.len() == 1));
+
+        // Only reported if layout checks are off
+        let built = build.check_layout(false).build().unwrap();
+        assert_eq!(built.layout_problems.len(), 1);
+    }
+}
@@ -0,0 +1,630 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+use crate::elf::MiniElfSection;
+use crate::tags::bflg::{Bflg, BootFlags};
+use crate::tags::binf::{format_timestamp, BuildInfo};
+use crate::tags::bpar::BootParams;
+use crate::tags::inie::IniE;
+use crate::tags::memory::{MemoryRegion, MemoryRegions};
+use crate::tags::proc::{ProcessInfo, Processes};
+use crate::tags::raw::RawTag;
+use crate::tags::sver::SecurityVersion;
+use crate::tags::xkrn::XousKernel;
+use crate::tags::xram::{RamBank, RamBanks};
+use crate::utils::hex;
+use crate::xous_arguments::{code_name, XousArgument, XousArguments};
+
+/// One semantic difference between two images.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// Something exists only in the new image
+    Added(String /* item */, String /* description */),
+
+    /// Something exists only in the old image
+    Removed(String /* item */, String /* description */),
+
+    /// A field has a different value in the new image
+    Changed(
+        String, /* field */
+        String, /* old */
+        String, /* new */
+    ),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(item, desc) => write!(f, "+ {}: {}", item, desc),
+            Change::Removed(item, desc) => write!(f, "- {}: {}", item, desc),
+            Change::Changed(field, old, new) => write!(f, "~ {}: {} -> {}", field, old, new),
+        }
+    }
+}
+
+/// SHA-256 of a payload, as a hex string.
+pub fn payload_hash(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// The addresses covered by a bank or region.  The end is computed as a
+/// `u64`, since a region may end at 4 GiB.
+fn range(start: u32, length: u32) -> String {
+    format!("{:08x} - {:08x}", start, start as u64 + length as u64)
+}
+
+#[derive(Default)]
+struct Differ {
+    changes: Vec<Change>,
+}
+
+impl Differ {
+    fn field<T: PartialEq + fmt::Display>(&mut self, name: &str, old: T, new: T) {
+        if old != new {
+            self.changes.push(Change::Changed(
+                name.to_owned(),
+                old.to_string(),
+                new.to_string(),
+            ));
+        }
+    }
+
+    fn hex(&mut self, name: &str, old: u32, new: u32) {
+        self.field(name, format!("{:08x}", old), format!("{:08x}", new));
+    }
+
+    fn payload(&mut self, name: &str, old: &[u8], new: &[u8]) {
+        if old != new {
+            self.changes.push(Change::Changed(
+                format!("{}.payload", name),
+                format!("{} bytes, sha256 {}", old.len(), payload_hash(old)),
+                format!("{} bytes, sha256 {}", new.len(), payload_hash(new)),
+            ));
+        }
+    }
+
+    fn main_ram(&mut self, old: &XousArguments, new: &XousArguments) {
+        self.field(
+            "ram.name",
+            code_name(old.ram_name()),
+            code_name(new.ram_name()),
+        );
+        self.hex("ram.start", old.ram_start(), new.ram_start());
+        self.hex("ram.length", old.ram_length(), new.ram_length());
+    }
+
+    fn ram_banks(&mut self, old: &XousArguments, new: &XousArguments) {
+        fn collect(args: &XousArguments) -> BTreeMap<String, &RamBank> {
+            args.iter::<RamBanks>()
+                .flat_map(|xram| xram.banks())
+                .map(|bank| (code_name(bank.name()), bank))
+                .collect()
+        }
+        let old = collect(old);
+        let new = collect(new);
+        for (name, bank) in &old {
+            match new.get(name) {
+                None => self.changes.push(Change::Removed(
+                    format!("ram bank {}", name),
+                    range(bank.start(), bank.length()),
+                )),
+                Some(new_bank) => {
+                    self.hex(
+                        &format!("ram bank {}.start", name),
+                        bank.start(),
+                        new_bank.start(),
+                    );
+                    self.hex(
+                        &format!("ram bank {}.length", name),
+                        bank.length(),
+                        new_bank.length(),
+                    );
+                }
+            }
+        }
+        for (name, bank) in &new {
+            if !old.contains_key(name) {
+                self.changes.push(Change::Added(
+                    format!("ram bank {}", name),
+                    range(bank.start(), bank.length()),
+                ));
+            }
+        }
+    }
+
+    fn regions(&mut self, old: &XousArguments, new: &XousArguments) {
+        fn collect(args: &XousArguments) -> BTreeMap<String, &MemoryRegion> {
+            let mut map = BTreeMap::new();
+            for regions in args.iter::<MemoryRegions>() {
+                for region in regions.regions() {
+                    map.insert(code_name(region.name()), region);
+                }
+            }
+            map
+        }
+        let old = collect(old);
+        let new = collect(new);
+        for (name, region) in &old {
+            match new.get(name) {
+                None => self.changes.push(Change::Removed(
+                    format!("region {}", name),
+                    range(region.start(), region.length()),
+                )),
+                Some(new_region) => {
+                    self.hex(
+                        &format!("region {}.start", name),
+                        region.start(),
+                        new_region.start(),
+                    );
+                    self.hex(
+                        &format!("region {}.length", name),
+                        region.length(),
+                        new_region.length(),
+                    );
+                    self.field(
+                        &format!("region {}.attributes", name),
+                        region.attributes(),
+                        new_region.attributes(),
+                    );
+                }
+            }
+        }
+        for (name, region) in &new {
+            if !old.contains_key(name) {
+                self.changes.push(Change::Added(
+                    format!("region {}", name),
+                    range(region.start(), region.length()),
+                ));
+            }
+        }
+    }
+
+    fn boot_flags(&mut self, old: Option<&Bflg>, new: Option<&Bflg>) {
+        let none = Bflg::new();
+        let old = old.unwrap_or(&none);
+        let new = new.unwrap_or(&none);
+        for (name, flag) in BootFlags::NAMES {
+            let field = format!("bflg.{}", name);
+            self.field(&field, old.contains(*flag), new.contains(*flag));
+        }
+        self.hex("bflg.unknown", old.unknown_bits(), new.unknown_bits());
+    }
+
+    fn security_version(&mut self, old: Option<&SecurityVersion>, new: Option<&SecurityVersion>) {
+        // Images without an SVer tag are version 0
+        self.field(
+            "sver.version",
+            old.map_or(0, SecurityVersion::version),
+            new.map_or(0, SecurityVersion::version),
+        );
+    }
+
+    fn boot_params(&mut self, old: Option<&BootParams>, new: Option<&BootParams>) {
+        let none = BootParams::new();
+        let old = old.unwrap_or(&none);
+        let new = new.unwrap_or(&none);
+        for (key, value) in old.params() {
+            let item = format!("bpar.{}", key);
+            match new.get(key) {
+                Some(new_value) => self.field(&item, value.as_str(), new_value),
+                None => self.changes.push(Change::Removed(item, value.clone())),
+            }
+        }
+        for (key, value) in new.params() {
+            if old.get(key).is_none() {
+                self.changes
+                    .push(Change::Added(format!("bpar.{}", key), value.clone()));
+            }
+        }
+    }
+
+    fn build_info(&mut self, old: Option<&BuildInfo>, new: Option<&BuildInfo>) {
+        let (old, new) = match (old, new) {
+            (None, None) => return,
+            (Some(old), None) => {
+                self.changes.push(Change::Removed(
+                    "binf".to_owned(),
+                    format!("xous-tools {}", old.tool_version()),
+                ));
+                return;
+            }
+            (None, Some(new)) => {
+                self.changes.push(Change::Added(
+                    "binf".to_owned(),
+                    format!("xous-tools {}", new.tool_version()),
+                ));
+                return;
+            }
+            (Some(old), Some(new)) => (old, new),
+        };
+        self.field(
+            "binf.timestamp",
+            format_timestamp(old.timestamp()),
+            format_timestamp(new.timestamp()),
+        );
+        self.field("binf.tool_version", old.tool_version(), new.tool_version());
+        self.field("binf.revision", old.revision(), new.revision());
+        let old_hashes = old.elf_hashes_hex();
+        let new_hashes = new.elf_hashes_hex();
+        for idx in 0..old_hashes.len().max(new_hashes.len()) {
+            let item = format!("binf.elf_hash[{}]", idx);
+            match (old_hashes.get(idx), new_hashes.get(idx)) {
+                (Some(o), Some(n)) => self.field(&item, o, n),
+                (Some(o), None) => self.changes.push(Change::Removed(item, o.clone())),
+                (None, Some(n)) => self.changes.push(Change::Added(item, n.clone())),
+                (None, None) => (),
+            }
+        }
+    }
+
+    fn kernel(&mut self, old: Option<&XousKernel>, new: Option<&XousKernel>) {
+        match (old, new) {
+            (None, None) => (),
+            (Some(old), None) => self.changes.push(Change::Removed(
+                "kernel".to_owned(),
+                format!("{} bytes of text", old.text_size()),
+            )),
+            (None, Some(new)) => self.changes.push(Change::Added(
+                "kernel".to_owned(),
+                format!("{} bytes of text", new.text_size()),
+            )),
+            (Some(old), Some(new)) => {
+                self.hex("kernel.text_offset", old.text_offset(), new.text_offset());
+                self.field("kernel.text_size", old.text_size(), new.text_size());
+                self.hex("kernel.data_offset", old.data_offset(), new.data_offset());
+                self.field("kernel.data_size", old.data_size(), new.data_size());
+                self.field("kernel.bss_size", old.bss_size(), new.bss_size());
+                self.hex("kernel.entrypoint", old.entrypoint(), new.entrypoint());
+                if old.has_data() && new.has_data() {
+                    self.payload("kernel", old.program(), new.program());
+                } else {
+                    self.payload("kernel", old.last_data(), new.last_data());
+                }
+            }
+        }
+    }
+
+    fn section(&mut self, name: &str, old: &MiniElfSection, new: &MiniElfSection) {
+        self.hex(&format!("{}.virt", name), old.virt, new.virt);
+        self.field(&format!("{}.size", name), old.size, new.size);
+        self.field(
+            &format!("{}.flags", name),
+            format!("{:?}", old.flags),
+            format!("{:?}", new.flags),
+        );
+    }
+
+    fn process(&mut self, idx: usize, old: Option<&ProcessInfo>, new: Option<&ProcessInfo>) {
+        let none = ProcessInfo::default();
+        let old = old.unwrap_or(&none);
+        let new = new.unwrap_or(&none);
+        let name = format!("init[{}]", idx);
+        self.field(&format!("{}.name", name), &old.name, &new.name);
+        let stack = |p: &ProcessInfo| p.stack_size.map_or("default".to_owned(), |s| s.to_string());
+        self.field(&format!("{}.stack_size", name), stack(old), stack(new));
+        let priority = |p: &ProcessInfo| p.priority.map_or("default".to_owned(), |s| s.to_string());
+        self.field(&format!("{}.priority", name), priority(old), priority(new));
+        self.field(&format!("{}.debug", name), old.debug, new.debug);
+    }
+
+    fn init(&mut self, idx: usize, old: Option<&IniE>, new: Option<&IniE>) {
+        let name = format!("init[{}]", idx);
+        match (old, new) {
+            (None, None) => (),
+            (Some(old), None) => self.changes.push(Change::Removed(
+                name,
+                format!("entrypoint {:08x}", old.entrypoint()),
+            )),
+            (None, Some(new)) => self.changes.push(Change::Added(
+                name,
+                format!("entrypoint {:08x}", new.entrypoint()),
+            )),
+            (Some(old), Some(new)) => {
+                self.hex(
+                    &format!("{}.entrypoint", name),
+                    old.entrypoint(),
+                    new.entrypoint(),
+                );
+                let count = old.sections().len().max(new.sections().len());
+                for sidx in 0..count {
+                    let section_name = format!("{}.section[{}]", name, sidx);
+                    match (old.sections().get(sidx), new.sections().get(sidx)) {
+                        (Some(o), Some(n)) => self.section(&section_name, o, n),
+                        (Some(o), None) => self
+                            .changes
+                            .push(Change::Removed(section_name, o.to_string())),
+                        (None, Some(n)) => self
+                            .changes
+                            .push(Change::Added(section_name, n.to_string())),
+                        (None, None) => (),
+                    }
+                }
+                // Payloads that couldn't be decrypted are compared as stored
+                if old.has_data() && new.has_data() {
+                    self.payload(&name, old.data(), new.data());
+                } else {
+                    self.payload(&name, old.last_data(), new.last_data());
+                }
+            }
+        }
+    }
+
+    fn raw_tags(&mut self, old: &XousArguments, new: &XousArguments) {
+        fn collect(args: &XousArguments) -> BTreeMap<String, Vec<&[u8]>> {
+            let mut map: BTreeMap<String, Vec<&[u8]>> = BTreeMap::new();
+            for tag in args.iter::<RawTag>() {
+                map.entry(tag.name()).or_default().push(tag.data());
+            }
+            map
+        }
+        let old = collect(old);
+        let new = collect(new);
+        let empty = vec![];
+        let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            let old = old.get(name).unwrap_or(&empty);
+            let new = new.get(name).unwrap_or(&empty);
+            for idx in 0..old.len().max(new.len()) {
+                let item = format!("tag {}[{}]", name, idx);
+                match (old.get(idx), new.get(idx)) {
+                    (Some(o), None) => self
+                        .changes
+                        .push(Change::Removed(item, format!("{} bytes", o.len()))),
+                    (None, Some(n)) => self
+                        .changes
+                        .push(Change::Added(item, format!("{} bytes", n.len()))),
+                    (Some(o), Some(n)) => self.payload(&item, o, n),
+                    (None, None) => (),
+                }
+            }
+        }
+    }
+}
+
+/// Line up two images tag by tag and describe how they differ.  Payload
+/// offsets are ignored, since they move whenever anything changes size.
+pub fn diff_images(old: &XousArguments, new: &XousArguments) -> Vec<Change> {
+    let mut differ = Differ::default();
+    differ.main_ram(old, new);
+    differ.ram_banks(old, new);
+    differ.regions(old, new);
+    differ.boot_flags(old.get::<Bflg>(), new.get::<Bflg>());
+    differ.security_version(old.get::<SecurityVersion>(), new.get::<SecurityVersion>());
+    differ.boot_params(old.get::<BootParams>(), new.get::<BootParams>());
+    differ.build_info(old.get::<BuildInfo>(), new.get::<BuildInfo>());
+    differ.kernel(old.get::<XousKernel>(), new.get::<XousKernel>());
+    let old_inits: Vec<&IniE> = old.iter::<IniE>().collect();
+    let new_inits: Vec<&IniE> = new.iter::<IniE>().collect();
+    for idx in 0..old_inits.len().max(new_inits.len()) {
+        differ.init(
+            idx,
+            old_inits.get(idx).copied(),
+            new_inits.get(idx).copied(),
+        );
+        // Added and removed programs are already reported above
+        if idx < old_inits.len() && idx < new_inits.len() {
+            differ.process(
+                idx,
+                old.get::<Processes>().and_then(|p| p.get(idx)),
+                new.get::<Processes>().and_then(|p| p.get(idx)),
+            );
+        }
+    }
+    differ.raw_tags(old, new);
+    differ.changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::MiniElfFlags;
+    use crate::test_util::{init, kernel, pattern, sample_args, section, write_image};
+
+    fn added(item: &str, desc: &str) -> Change {
+        Change::Added(item.to_owned(), desc.to_owned())
+    }
+
+    fn removed(item: &str, desc: &str) -> Change {
+        Change::Removed(item.to_owned(), desc.to_owned())
+    }
+
+    fn changed(field: &str, old: &str, new: &str) -> Change {
+        Change::Changed(field.to_owned(), old.to_owned(), new.to_owned())
+    }
+
+    /// `sample_args()` with a different kernel and init program.
+    fn args_with(xkrn: XousKernel, inie: IniE) -> XousArguments {
+        let mut args = XousArguments::new(0x4000_0000, 0x0100_0000, make_type!("SrEx"));
+        let mut regions = MemoryRegions::new();
+        regions.add(MemoryRegion::new(0xf000_0000, 0x3000, make_type!("CSRs")));
+        args.add(regions);
+        args.add(xkrn);
+        args.add(inie);
+        args
+    }
+
+    #[test]
+    fn identical() {
+        assert!(diff_images(&sample_args(), &sample_args()).is_empty());
+
+        // Payload offsets don't count
+        let mut args = sample_args();
+        let parsed = XousArguments::parse(&write_image(&mut args)).unwrap();
+        assert!(diff_images(&sample_args(), &parsed).is_empty());
+    }
+
+    #[test]
+    fn regions() {
+        let mut new = sample_args();
+        let boot = MemoryRegion::new(0xffff_f000, 0x1000, make_type!("Boot"));
+        new.get_mut::<MemoryRegions>().unwrap().add(boot);
+        assert_eq!(
+            diff_images(&sample_args(), &new),
+            [added("region Boot", "fffff000 - 100000000")]
+        );
+        assert_eq!(
+            diff_images(&new, &sample_args()),
+            [removed("region Boot", "fffff000 - 100000000")]
+        );
+    }
+
+    #[test]
+    fn ram_banks() {
+        let mut new = sample_args();
+        let mut xram = RamBanks::new();
+        xram.add(RamBank::new(0xffff_0000, 0x0001_0000, make_type!("Bnk1")));
+        new.add(xram);
+        assert_eq!(
+            diff_images(&sample_args(), &new),
+            [added("ram bank Bnk1", "ffff0000 - 100000000")]
+        );
+        assert_eq!(
+            diff_images(&new, &sample_args()),
+            [removed("ram bank Bnk1", "ffff0000 - 100000000")]
+        );
+    }
+
+    #[test]
+    fn main_ram() {
+        let mut new = XousArguments::new(0x4000_0000, 0x0200_0000, make_type!("SrEx"));
+        for arg in sample_args().arguments {
+            new.arguments.push(arg);
+        }
+        assert_eq!(
+            diff_images(&sample_args(), &new),
+            [changed("ram.length", "01000000", "02000000")]
+        );
+    }
+
+    #[test]
+    fn kernel_payload() {
+        let xkrn = XousKernel::new(
+            0xff00_0000,
+            16,
+            0xff40_0000,
+            8,
+            32,
+            0xff00_0000,
+            pattern(24, 7),
+        );
+        assert_eq!(
+            diff_images(&sample_args(), &args_with(xkrn, init(2))),
+            [changed(
+                "kernel.payload",
+                &format!("24 bytes, sha256 {}", payload_hash(&pattern(24, 1))),
+                &format!("24 bytes, sha256 {}", payload_hash(&pattern(24, 7))),
+            )]
+        );
+    }
+
+    #[test]
+    fn init_entrypoint() {
+        let inie = IniE::new(
+            0x2000_0008,
+            vec![
+                section(0x2000_0000, 16, MiniElfFlags::EXECUTE),
+                section(0x2001_0000, 8, MiniElfFlags::WRITE),
+                section(0x2001_0008, 32, MiniElfFlags::WRITE | MiniElfFlags::NOCOPY),
+            ],
+            pattern(24, 2),
+        );
+        assert_eq!(
+            diff_images(&sample_args(), &args_with(kernel(), inie)),
+            [changed("init[0].entrypoint", "20000000", "20000008")]
+        );
+    }
+
+    #[test]
+    fn security_version() {
+        let mut old = sample_args();
+        old.add(SecurityVersion::new(2));
+        let mut new = sample_args();
+        new.add(SecurityVersion::new(3));
+        assert_eq!(diff_images(&old, &new), [changed("sver.version", "2", "3")]);
+        assert_eq!(
+            diff_images(&sample_args(), &new),
+            [changed("sver.version", "0", "3")]
+        );
+
+        // A missing tag is the same as version 0
+        let mut zero = sample_args();
+        zero.add(SecurityVersion::new(0));
+        assert!(diff_images(&sample_args(), &zero).is_empty());
+    }
+
+    #[test]
+    fn boot_params() {
+        let mut bpar = BootParams::new();
+        bpar.set("console", "uart0");
+        bpar.set("log-level", "info");
+        let mut old = sample_args();
+        old.add(bpar);
+
+        let mut bpar = BootParams::new();
+        bpar.set("log-level", "debug");
+        bpar.set("baud", "115200");
+        let mut new = sample_args();
+        new.add(bpar);
+
+        assert_eq!(
+            diff_images(&old, &new),
+            [
+                removed("bpar.console", "uart0"),
+                changed("bpar.log-level", "info", "debug"),
+                added("bpar.baud", "115200"),
+            ]
+        );
+
+        // Only the values matter, not the order they were set in
+        let mut bpar = BootParams::new();
+        bpar.set("log-level", "info");
+        bpar.set("console", "uart0");
+        let mut reordered = sample_args();
+        reordered.add(bpar);
+        assert!(diff_images(&old, &reordered).is_empty());
+    }
+
+    #[test]
+    fn build_info() {
+        let mut old = sample_args();
+        old.add(BuildInfo::new(0, "0.9.0", "abc123", vec![[1; 32], [2; 32]]));
+        let mut new = sample_args();
+        new.add(BuildInfo::new(
+            86400,
+            "0.9.0",
+            "def456",
+            vec![[1; 32], [3; 32], [4; 32]],
+        ));
+        assert_eq!(
+            diff_images(&old, &new),
+            [
+                changed(
+                    "binf.timestamp",
+                    "1970-01-01 00:00:00 UTC",
+                    "1970-01-02 00:00:00 UTC"
+                ),
+                changed("binf.revision", "abc123", "def456"),
+                changed("binf.elf_hash[1]", &"02".repeat(32), &"03".repeat(32)),
+                added("binf.elf_hash[2]", &"04".repeat(32)),
+            ]
+        );
+        assert_eq!(
+            diff_images(&old, &sample_args()),
+            [removed("binf", "xous-tools 0.9.0")]
+        );
+    }
+
+    #[test]
+    fn boot_flags() {
+        let mut new = sample_args();
+        new.add(Bflg::new().debug());
+        assert_eq!(
+            diff_images(&sample_args(), &new),
+            [changed("bflg.debug", "false", "true")]
+        );
+    }
+}
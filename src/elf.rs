@@ -5,7 +5,7 @@ use std::fs::File;
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use xmas_elf::program::Type as ProgramType;
-use xmas_elf::sections::ShType;
+use xmas_elf::sections::{SectionHeader, ShType};
 use xmas_elf::ElfFile;
 
 // Normal ELF flags
@@ -53,6 +53,14 @@ pub struct MiniElfSection {
 
 impl fmt::Display for MiniElfSection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Sections decoded from an image have no name
+        if self.name.is_empty() {
+            return write!(
+                f,
+                "Section: {} bytes loading @ {:08x} flags: {:?}",
+                self.size, self.virt, self.flags
+            );
+        }
         write!(
             f,
             "Section {}: {} bytes loading @ {:08x} flags: {:?}",
@@ -91,7 +99,7 @@ pub enum ElfReadError {
     ParseElfError(&'static str),
 
     /// Section wasn't in range
-    SectionRangeError,
+    SectionRangeError(String /* section name */),
 
     /// Section wasn't word-aligned
     SectionNotAligned(String /* section name */, usize /* section size */),
@@ -114,7 +122,7 @@ impl fmt::Display for ElfReadError {
             ReadFileError(e) => write!(f, "couldn't read from the file: {}", e),
             OpenElfError(e) => write!(f, "couldn't open the elf file: {}", e),
             ParseElfError(e) => write!(f, "couldn't parse the elf file: {}", e),
-            SectionRangeError => write!(f, "elf section pointed outside of the file"),
+            SectionRangeError(s) => write!(f, "elf section {} pointed outside of the file", s),
             SectionNotAligned(s, a) => write!(f, "elf section {} had unaligned length {}", s, a),
             FileSeekError(e) => write!(f, "couldn't seek in the output file: {}", e),
             WriteSectionError(e) => write!(f, "couldn't write a section to the output file: {}", e),
@@ -122,14 +130,45 @@ impl fmt::Display for ElfReadError {
     }
 }
 
+impl std::error::Error for ElfReadError {}
+
+/// Contents of a section, checking that it lies inside the file first.
+fn section_data<'a>(
+    file: &[u8],
+    elf: &ElfFile<'a>,
+    s: &SectionHeader<'a>,
+    name: &str,
+) -> Result<&'a [u8], ElfReadError> {
+    if s.offset().checked_add(s.size()).is_none_or(|end| end > file.len() as u64) {
+        return Err(ElfReadError::SectionRangeError(name.to_owned()));
+    }
+    Ok(s.raw_data(elf))
+}
+
+/// Parse an ELF file, making sure its header tables lie inside the file so
+/// that iterating over them can't panic.
+fn parse_elf(file: &[u8]) -> Result<ElfFile<'_>, ElfReadError> {
+    let elf = ElfFile::new(file).map_err(ElfReadError::ParseElfError)?;
+    let pt2 = &elf.header.pt2;
+    let ph_end = pt2.ph_offset() + pt2.ph_count() as u64 * pt2.ph_entry_size() as u64;
+    if ph_end > file.len() as u64 {
+        return Err(ElfReadError::ParseElfError("program headers are outside of the file"));
+    }
+    let sh_end = pt2.sh_offset() + pt2.sh_count() as u64 * pt2.sh_entry_size() as u64;
+    if sh_end > file.len() as u64 {
+        return Err(ElfReadError::ParseElfError("section headers are outside of the file"));
+    }
+    Ok(elf)
+}
+
 pub fn read_program<P: AsRef<Path>>(filename: P) -> Result<ProgramDescription, ElfReadError> {
     let mut b = Vec::new();
     {
-        let mut fi = File::open(filename).or_else(|x| Err(ElfReadError::OpenElfError(x)))?;
+        let mut fi = File::open(filename).map_err(ElfReadError::OpenElfError)?;
         fi.read_to_end(&mut b)
-            .or_else(|x| Err(ElfReadError::ReadFileError(x)))?;
+            .map_err(ElfReadError::ReadFileError)?;
     }
-    let elf = ElfFile::new(&b).or_else(|x| Err(ElfReadError::ParseElfError(x)))?;
+    let elf = parse_elf(&b)?;
     let entry_point = elf.header.pt2.entry_point() as u32;
     let mut program_data = Cursor::new(Vec::new());
 
@@ -144,10 +183,8 @@ pub fn read_program<P: AsRef<Path>>(filename: P) -> Result<ProgramDescription, E
     debug!("ELF: {:?}", elf.header);
     for ph in elf.program_iter() {
         debug!("Program Header: {:?}", ph);
-        if ph.get_type() == Ok(ProgramType::Load) {
-            if phys_offset == 0 {
-                phys_offset = ph.physical_addr();
-            }
+        if ph.get_type() == Ok(ProgramType::Load) && phys_offset == 0 {
+            phys_offset = ph.physical_addr();
         }
         debug!("Physical address: {:08x}", ph.physical_addr());
         debug!("Virtual address: {:08x}", ph.virtual_addr());
@@ -201,9 +238,9 @@ pub fn read_program<P: AsRef<Path>>(filename: P) -> Result<ProgramDescription, E
                 debug!("Padding text size by {} bytes...", bytes_to_add);
                 program_data
                     .seek(SeekFrom::Current(bytes_to_add as i64))
-                    .or_else(|x| Err(ElfReadError::FileSeekError(x)))?;
+                    .map_err(ElfReadError::FileSeekError)?;
                 text_size += bytes_to_add as u32;
-                program_offset += bytes_to_add as u64;
+                program_offset += bytes_to_add;
                 // panic!(
                 //     "size not correct!  should be {:08x}, was {:08x}, need to add {} bytes",
                 //     text_offset + text_size,
@@ -222,25 +259,26 @@ pub fn read_program<P: AsRef<Path>>(filename: P) -> Result<ProgramDescription, E
             "s offset: {:08x}  program_offset: {:08x}  Bytes: {}  seek: {}",
             s.offset(),
             program_offset,
-            s.raw_data(&elf).len(),
+            s.size(),
             program_offset
         );
-        let section_data = s.raw_data(&elf);
+        let section_data = section_data(&b, &elf, &s, name)?;
         debug!(
-            "Section start: {:02x} {:02x} {:02x} {:02x} going into offset 0x{:08x}",
-            section_data[0], section_data[1], section_data[2], section_data[3], program_offset
+            "Section start: {:02x?} going into offset 0x{:08x}",
+            &section_data[..section_data.len().min(4)],
+            program_offset
         );
         program_data
             .seek(SeekFrom::Start(program_offset))
-            .or_else(|x| Err(ElfReadError::FileSeekError(x)))?;
+            .map_err(ElfReadError::FileSeekError)?;
         program_data
-            .write(section_data)
-            .or_else(|x| Err(ElfReadError::WriteSectionError(x)))?;
+            .write_all(section_data)
+            .map_err(ElfReadError::WriteSectionError)?;
         program_offset += section_data.len() as u64;
     }
     let observed_size = program_data
         .seek(SeekFrom::End(0))
-        .or_else(|e| Err(ElfReadError::SeekFromEndError(e)))?;
+        .map_err(ElfReadError::SeekFromEndError)?;
 
     debug!("Text size: {} bytes", text_size);
     debug!("Text offset: {:08x}", text_offset);
@@ -262,11 +300,11 @@ pub fn read_program<P: AsRef<Path>>(filename: P) -> Result<ProgramDescription, E
 pub fn read_minielf<P: AsRef<Path>>(filename: P) -> Result<MiniElf, ElfReadError> {
     let mut b = Vec::new();
     {
-        let mut fi = File::open(filename).or_else(|x| Err(ElfReadError::OpenElfError(x)))?;
+        let mut fi = File::open(filename).map_err(ElfReadError::OpenElfError)?;
         fi.read_to_end(&mut b)
-            .or_else(|x| Err(ElfReadError::ReadFileError(x)))?;
+            .map_err(ElfReadError::ReadFileError)?;
     }
-    let elf = ElfFile::new(&b).or_else(|x| Err(ElfReadError::ParseElfError(x)))?;
+    let elf = parse_elf(&b)?;
     let entry_point = elf.header.pt2.entry_point() as u32;
     let mut program_data = Cursor::new(Vec::new());
 
@@ -320,43 +358,40 @@ pub fn read_minielf<P: AsRef<Path>>(filename: P) -> Result<MiniElf, ElfReadError
             flags |= MiniElfFlags::WRITE;
         }
 
-        debug!("Adding {} to the file", name);
-        debug!(
-            "{} offset: {:08x}  program_offset: {:08x}  bytes: {}  padding: {}  seek: {}",
-            name,
-            s.offset(),
-            program_offset,
-            s.raw_data(&elf).len(),
-            padding,
-            program_offset
-        );
-        let section_data = s.raw_data(&elf);
-        debug!(
-            "Section start: {:02x} {:02x} {:02x} {:02x} going into offset 0x{:08x}",
-            section_data[0], section_data[1], section_data[2], section_data[3], program_offset
-        );
-
-        // If this section gets copied, add it to the program stream.
+        // If this section gets copied, add it to the program stream.  Sections
+        // without any bits may point past the end of the file, so don't
+        // look at their data.
         if s.get_type() != Ok(ShType::NoBits) {
+            let section_data = section_data(&b, &elf, &s, name)?;
+            debug!("Adding {} to the file", name);
+            debug!(
+                "{} offset: {:08x}  program_offset: {:08x}  bytes: {}  padding: {}  seek: {}",
+                name,
+                s.offset(),
+                program_offset,
+                section_data.len(),
+                padding,
+                program_offset
+            );
             program_data
                 .seek(SeekFrom::Start(program_offset))
-                .or_else(|x| Err(ElfReadError::FileSeekError(x)))?;
+                .map_err(ElfReadError::FileSeekError)?;
             program_data
-                .write(section_data)
-                .or_else(|x| Err(ElfReadError::WriteSectionError(x)))?;
+                .write_all(section_data)
+                .map_err(ElfReadError::WriteSectionError)?;
             program_offset += section_data.len() as u64;
-            program_offset += padding as u64;
+            program_offset += padding;
         }
         sections.push(MiniElfSection {
             virt: s.address() as u32,
             size: (size + padding) as u32,
-            flags: flags,
+            flags,
             name: name.to_string(),
         });
     }
     let observed_size = program_data
         .seek(SeekFrom::End(0))
-        .or_else(|e| Err(ElfReadError::SeekFromEndError(e)))?;
+        .map_err(ElfReadError::SeekFromEndError)?;
 
     debug!("Program size: {} bytes", observed_size);
     Ok(MiniElf {
@@ -365,3 +400,234 @@ pub fn read_minielf<P: AsRef<Path>>(filename: P) -> Result<MiniElf, ElfReadError
         program: program_data.into_inner(),
     })
 }
+
+/// Pick a name for a section that came from an image and so has none.
+fn guess_section_name(section: &MiniElfSection, used: &[String]) -> String {
+    let base = if !section.name.is_empty() {
+        section.name.clone()
+    } else if section.flags.contains(MiniElfFlags::NOCOPY) {
+        ".bss".to_owned()
+    } else if section.flags.contains(MiniElfFlags::EXECUTE) {
+        ".text".to_owned()
+    } else if section.flags.contains(MiniElfFlags::WRITE) {
+        ".data".to_owned()
+    } else {
+        ".rodata".to_owned()
+    };
+    let mut name = base.clone();
+    let mut suffix = 1;
+    while used.contains(&name) {
+        name = format!("{}.{}", base, suffix);
+        suffix += 1;
+    }
+    name
+}
+
+/// Build a 32-bit RISC-V ELF file from a set of sections.
+///
+/// `program` contains the data for every section that does not have
+/// `MiniElfFlags::NOCOPY` set, in order, just like `MiniElf::program`.
+/// Each section that isn't empty gets a matching `PT_LOAD` program header,
+/// and every section, empty or not, gets a section header, so the result
+/// can be read back in with `read_minielf()` or `read_program()`.
+///
+/// Images don't record section names, so unless `name` is set they are
+/// guessed from each section's flags.
+pub fn write_elf(entry_point: u32, sections: &[MiniElfSection], program: &[u8]) -> Vec<u8> {
+    const EHDR_SIZE: u32 = 52;
+    const PHDR_SIZE: u32 = 32;
+    const SHDR_SIZE: u32 = 40;
+    const EM_RISCV: u16 = 243;
+    const SHT_PROGBITS: u32 = 1;
+    const SHT_STRTAB: u32 = 3;
+    const SHT_NOBITS: u32 = 8;
+
+    let sections: Vec<&MiniElfSection> = sections.iter().collect();
+    let loaded = sections.iter().filter(|s| s.size != 0).count() as u32;
+
+    // Section names, starting with the empty name for the null section
+    let mut names = vec![];
+    let mut shstrtab = vec![0u8];
+    let mut name_offsets = vec![];
+    for section in &sections {
+        let name = guess_section_name(section, &names);
+        name_offsets.push(shstrtab.len() as u32);
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+        names.push(name);
+    }
+    let shstrtab_name = shstrtab.len() as u32;
+    shstrtab.extend_from_slice(b".shstrtab\0");
+
+    // Work out where everything goes
+    let data_start = EHDR_SIZE + PHDR_SIZE * loaded;
+    let mut file_offsets = vec![];
+    let mut program_offset = 0usize;
+    let mut file_offset = data_start;
+    for section in &sections {
+        file_offsets.push(file_offset);
+        if !section.flags.contains(MiniElfFlags::NOCOPY) {
+            file_offset += section.size;
+        }
+    }
+    let shstrtab_offset = file_offset;
+    let shdr_offset = (shstrtab_offset + shstrtab.len() as u32 + 3) & !3;
+
+    fn w32(out: &mut Vec<u8>, val: u32) {
+        out.extend_from_slice(&val.to_le_bytes());
+    }
+    fn w16(out: &mut Vec<u8>, val: u16) {
+        out.extend_from_slice(&val.to_le_bytes());
+    }
+
+    let mut out = vec![];
+
+    // ELF header
+    out.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+    out.extend_from_slice(&[0; 8]);
+    w16(&mut out, 2); // ET_EXEC
+    w16(&mut out, EM_RISCV);
+    w32(&mut out, 1); // EV_CURRENT
+    w32(&mut out, entry_point);
+    w32(&mut out, EHDR_SIZE); // Program headers immediately follow
+    w32(&mut out, shdr_offset);
+    w32(&mut out, 0); // Flags
+    w16(&mut out, EHDR_SIZE as u16);
+    w16(&mut out, PHDR_SIZE as u16);
+    w16(&mut out, loaded as u16);
+    w16(&mut out, SHDR_SIZE as u16);
+    w16(&mut out, sections.len() as u16 + 2);
+    w16(&mut out, sections.len() as u16 + 1);
+
+    // Program headers
+    for (section, offset) in sections.iter().zip(&file_offsets) {
+        if section.size == 0 {
+            continue;
+        }
+        let copied = !section.flags.contains(MiniElfFlags::NOCOPY);
+        let mut flags = 4; // PF_R
+        if section.flags.contains(MiniElfFlags::WRITE) {
+            flags |= 2; // PF_W
+        }
+        if section.flags.contains(MiniElfFlags::EXECUTE) {
+            flags |= 1; // PF_X
+        }
+        w32(&mut out, 1); // PT_LOAD
+        w32(&mut out, *offset);
+        w32(&mut out, section.virt);
+        w32(&mut out, section.virt);
+        w32(&mut out, if copied { section.size } else { 0 });
+        w32(&mut out, section.size);
+        w32(&mut out, flags);
+        w32(&mut out, 4);
+    }
+
+    // Section data, which may be short if the image was truncated
+    for section in &sections {
+        if section.flags.contains(MiniElfFlags::NOCOPY) {
+            continue;
+        }
+        let start = program_offset.min(program.len());
+        let end = (program_offset + section.size as usize).min(program.len());
+        out.extend_from_slice(&program[start..end]);
+        out.resize(out.len() + section.size as usize - (end - start), 0);
+        program_offset += section.size as usize;
+    }
+    out.extend_from_slice(&shstrtab);
+    out.resize(shdr_offset as usize, 0);
+
+    // Section headers, starting with the null section
+    out.resize(out.len() + SHDR_SIZE as usize, 0);
+    for ((section, offset), name) in sections.iter().zip(&file_offsets).zip(&name_offsets) {
+        let mut flags = SHF_ALLOC as u32;
+        if section.flags.contains(MiniElfFlags::WRITE) {
+            flags |= SHF_WRITE as u32;
+        }
+        if section.flags.contains(MiniElfFlags::EXECUTE) {
+            flags |= SHF_EXECINSTR as u32;
+        }
+        w32(&mut out, *name);
+        if section.flags.contains(MiniElfFlags::NOCOPY) {
+            w32(&mut out, SHT_NOBITS);
+        } else {
+            w32(&mut out, SHT_PROGBITS);
+        }
+        w32(&mut out, flags);
+        w32(&mut out, section.virt);
+        w32(&mut out, *offset);
+        w32(&mut out, section.size);
+        w32(&mut out, 0); // Link
+        w32(&mut out, 0); // Info
+        w32(&mut out, 4); // Alignment
+        w32(&mut out, 0); // Entry size
+    }
+    w32(&mut out, shstrtab_name);
+    w32(&mut out, SHT_STRTAB);
+    w32(&mut out, 0);
+    w32(&mut out, 0);
+    w32(&mut out, shstrtab_offset);
+    w32(&mut out, shstrtab.len() as u32);
+    w32(&mut out, 0);
+    w32(&mut out, 0);
+    w32(&mut out, 1);
+    w32(&mut out, 0);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{pattern, section, TempDir};
+
+    #[test]
+    fn write_and_read_minielf() {
+        let sections = vec![
+            section(0x2000_0000, 16, MiniElfFlags::EXECUTE),
+            section(0x2000_8000, 0, MiniElfFlags::NONE),
+            section(0x2001_0000, 8, MiniElfFlags::WRITE),
+            section(0x2001_0008, 32, MiniElfFlags::WRITE | MiniElfFlags::NOCOPY),
+        ];
+        let program = pattern(24, 3);
+        let dir = TempDir::new();
+        let path = dir.write("init.elf", &write_elf(0x2000_0004, &sections, &program));
+
+        let elf = read_minielf(&path).unwrap();
+        assert_eq!(elf.entry_point, 0x2000_0004);
+        assert_eq!(elf.program, program);
+        // Empty sections survive too
+        assert_eq!(elf.sections.len(), sections.len());
+        for (a, b) in elf.sections.iter().zip(&sections) {
+            assert_eq!((a.virt, a.size, a.flags), (b.virt, b.size, b.flags));
+        }
+        assert_eq!(elf.sections[0].name, ".text");
+        assert_eq!(elf.sections[3].name, ".bss");
+    }
+
+    #[test]
+    fn write_and_read_program() {
+        let sections = vec![
+            MiniElfSection {
+                name: ".text".to_owned(),
+                ..section(0xff00_0000, 16, MiniElfFlags::EXECUTE)
+            },
+            MiniElfSection {
+                name: ".data".to_owned(),
+                ..section(0xff40_0000, 8, MiniElfFlags::WRITE)
+            },
+            MiniElfSection {
+                name: ".bss".to_owned(),
+                ..section(0xff40_0008, 32, MiniElfFlags::WRITE | MiniElfFlags::NOCOPY)
+            },
+        ];
+        let program = pattern(24, 4);
+        let dir = TempDir::new();
+        let path = dir.write("kernel.elf", &write_elf(0xff00_0000, &sections, &program));
+
+        let kernel = read_program(&path).unwrap();
+        assert_eq!(kernel.entry_point, 0xff00_0000);
+        assert_eq!((kernel.text_offset, kernel.text_size), (0xff00_0000, 16));
+        assert_eq!((kernel.data_offset, kernel.data_size), (0xff40_0000, 8));
+        assert_eq!(kernel.bss_size, 32);
+        assert_eq!(kernel.program, program);
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+use std::path::Path;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{AeadInPlace, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce, Tag};
+use aes_kw::KekAes256;
+
+use crate::error::Error;
+use crate::signing::{read_key, KeyFile};
+
+/// A 256-bit key used to wrap the key that each payload is encrypted with.
+pub struct KeyEncryptionKey([u8; 32]);
+
+impl fmt::Debug for KeyEncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Don't leak the key into logs
+        write!(f, "KeyEncryptionKey(..)")
+    }
+}
+
+impl KeyEncryptionKey {
+    pub fn new(key: [u8; 32]) -> KeyEncryptionKey {
+        KeyEncryptionKey(key)
+    }
+}
+
+/// Load a key-encryption key, which is 32 raw bytes or 64 hex digits.
+pub fn load_key_encryption_key<P: AsRef<Path>>(path: P) -> Result<KeyEncryptionKey, Error> {
+    let path = path.as_ref();
+    match read_key(path)? {
+        KeyFile::Raw(key) => Ok(KeyEncryptionKey(key)),
+        KeyFile::Pem(_) => Err(Error::Key(
+            path.to_path_buf(),
+            "key-encryption keys must be raw or hex, not PEM".to_owned(),
+        )),
+    }
+}
+
+/// The parts of an encrypted payload that are stored outside of the
+/// ciphertext itself.
+pub struct Sealed {
+    pub nonce: [u8; 12],
+    pub tag: [u8; 16],
+    pub wrapped_key: [u8; 40],
+}
+
+/// Encrypt `data` in place with a fresh random key, which is wrapped with
+/// `kek`.  `aad` is authenticated but not encrypted.
+pub fn encrypt(kek: &KeyEncryptionKey, aad: &[u8], data: &mut [u8]) -> Result<Sealed, Error> {
+    let mut key = [0u8; 32];
+    let mut sealed = Sealed {
+        nonce: [0; 12],
+        tag: [0; 16],
+        wrapped_key: [0; 40],
+    };
+    OsRng
+        .try_fill_bytes(&mut key)
+        .and_then(|_| OsRng.try_fill_bytes(&mut sealed.nonce))
+        .map_err(|e| Error::Encryption(format!("couldn't generate a key: {}", e)))?;
+
+    let tag = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+        .encrypt_in_place_detached(Nonce::from_slice(&sealed.nonce), aad, data)
+        .map_err(|_| Error::Encryption(format!("{} bytes is too large to encrypt", data.len())))?;
+    sealed.tag.copy_from_slice(&tag);
+
+    KekAes256::from(kek.0)
+        .wrap(&key, &mut sealed.wrapped_key)
+        .map_err(|e| Error::Encryption(format!("couldn't wrap the key: {}", e)))?;
+    Ok(sealed)
+}
+
+/// Decrypt `data` in place.  Fails if `kek` is the wrong key or if the
+/// payload or any of its parameters have been modified.
+pub fn decrypt(kek: &KeyEncryptionKey, aad: &[u8], sealed: &Sealed, data: &mut [u8]) -> bool {
+    let mut key = [0u8; 32];
+    if KekAes256::from(kek.0)
+        .unwrap(&sealed.wrapped_key, &mut key)
+        .is_err()
+    {
+        return false;
+    }
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+        .decrypt_in_place_detached(
+            Nonce::from_slice(&sealed.nonce),
+            aad,
+            data,
+            Tag::from_slice(&sealed.tag),
+        )
+        .is_ok()
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::elf::ElfReadError;
+use crate::manifest::ManifestError;
+use crate::params::ParamError;
+use crate::patch::PatchError;
+use crate::signing::SignatureStatus;
+use crate::utils::ConfigError;
+use crate::validate::LayoutProblem;
+use crate::verify::Violation;
+use crate::xous_arguments::{code_name, ParseError, XousArgumentCode};
+
+/// Everything that can go wrong while building, reading or modifying an image.
+#[derive(Debug)]
+pub enum Error {
+    /// No kernel was specified
+    NoKernel,
+
+    /// Neither a RAM range nor a CSV file was specified
+    NoMemory,
+
+    /// A value given on the command line or in a manifest was invalid
+    InvalidArgument(String),
+
+    /// The CSV file couldn't be read or parsed
+    Csv(PathBuf, ConfigError),
+
+    /// The CSV file didn't have a region with "ram" in its name
+    NoRamRegion(PathBuf),
+
+    /// An ELF file couldn't be read
+    Elf(PathBuf, ElfReadError),
+
+    /// The manifest couldn't be loaded
+    Manifest(ManifestError),
+
+    /// The boot parameters were invalid, or didn't match the schema
+    Params(ParamError),
+
+    /// A signing, verifying or encryption key couldn't be loaded
+    Key(PathBuf, String),
+
+    /// A payload couldn't be encrypted
+    Encryption(String),
+
+    /// An existing image couldn't be parsed
+    Parse(ParseError),
+
+    /// An image file couldn't be parsed
+    Image(PathBuf, ParseError),
+
+    /// An image file broke one or more of the verifier's invariants
+    Verification(PathBuf, Vec<Violation>),
+
+    /// An image file wasn't signed by the expected key
+    Signature(PathBuf, SignatureStatus),
+
+    /// The security version is lower than that of a previously shipped image
+    Rollback(PathBuf /* previous image */, u32 /* previous */, u32 /* new */),
+
+    /// A patch couldn't be applied to an image
+    Patch(PatchError),
+
+    /// Building the same inputs twice gave different images
+    NotReproducible(String),
+
+    /// Two memory regions would have the same code in the image
+    RegionNameCollision(String /* first */, String /* second */, XousArgumentCode),
+
+    /// The kernel, init programs or memory regions collide or don't fit
+    Layout(Vec<LayoutProblem>),
+
+    /// A tag wrote a different number of bytes than it advertised
+    TagLength(String /* tag */, u32 /* advertised */, u32 /* actual */),
+
+    /// A tag was too large to be described by its header
+    TagTooLarge(String /* tag */, usize /* bytes */),
+
+    /// A tag's data was not a multiple of four bytes
+    TagNotAligned(String /* tag */, usize /* bytes */),
+
+    /// Reading or writing a file failed
+    Io(Option<PathBuf>, io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            NoKernel => write!(f, "no kernel was specified"),
+            NoMemory => write!(f, "no RAM range or csv file was specified"),
+            InvalidArgument(s) => write!(f, "{}", s),
+            Csv(p, e) => write!(f, "{}: {}", p.display(), e),
+            NoRamRegion(p) => write!(
+                f,
+                "{}: couldn't find a memory region named \"ram\" in csv file",
+                p.display()
+            ),
+            Elf(p, e) => write!(f, "{}: {}", p.display(), e),
+            Manifest(e) => write!(f, "{}", e),
+            Params(e) => write!(f, "{}", e),
+            Key(p, e) => write!(f, "{}: {}", p.display(), e),
+            Encryption(e) => write!(f, "encryption failed: {}", e),
+            Parse(e) => write!(f, "{}", e),
+            Image(p, e) => write!(f, "{}: {}", p.display(), e),
+            Verification(p, violations) => {
+                write!(f, "{}: {} problem(s) found", p.display(), violations.len())?;
+                for violation in violations {
+                    write!(f, "\n    {}", violation)?;
+                }
+                Ok(())
+            }
+            Signature(p, status) => write!(f, "{}: {}", p.display(), status),
+            Rollback(p, old, new) => write!(
+                f,
+                "security version {} is lower than version {} of {}",
+                new,
+                old,
+                p.display()
+            ),
+            Patch(e) => write!(f, "{}", e),
+            NotReproducible(s) => write!(f, "image is not reproducible: {}", s),
+            RegionNameCollision(a, b, code) => write!(
+                f,
+                "memory regions {} and {} would both be called \"{}\" in the image, \
+                 so give one of them another name with --region-name",
+                a,
+                b,
+                code_name(*code)
+            ),
+            Layout(problems) => {
+                write!(f, "image layout has {} problem(s)", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n    {}", problem)?;
+                }
+                Ok(())
+            }
+            TagLength(t, a, b) => write!(
+                f,
+                "tag {} advertised it would write {} bytes, but it wrote {} bytes",
+                t, a, b
+            ),
+            TagTooLarge(t, s) => write!(f, "tag {} is {} bytes, which is too large", t, s),
+            TagNotAligned(t, s) => {
+                write!(f, "tag {} is {} bytes, which is not a multiple of 4", t, s)
+            }
+            Io(Some(p), e) => write!(f, "{}: {}", p.display(), e),
+            Io(None, e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Csv(_, e) => Some(e),
+            Error::Elf(_, e) => Some(e),
+            Error::Manifest(e) => Some(e),
+            Error::Params(e) => Some(e),
+            Error::Parse(e) => Some(e),
+            Error::Image(_, e) => Some(e),
+            Error::Patch(e) => Some(e),
+            Error::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(None, e)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::Parse(e)
+    }
+}
+
+impl From<PatchError> for Error {
+    fn from(e: PatchError) -> Error {
+        Error::Patch(e)
+    }
+}
+
+impl From<ManifestError> for Error {
+    fn from(e: ManifestError) -> Error {
+        Error::Manifest(e)
+    }
+}
+
+impl From<ParamError> for Error {
+    fn from(e: ParamError) -> Error {
+        Error::Params(e)
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::elf::MiniElfFlags;
+use crate::tags::bflg::Bflg;
+use crate::tags::binf::BuildInfo;
+use crate::tags::bpar::BootParams;
+use crate::tags::dgst::Digest;
+use crate::tags::inie::IniE;
+use crate::tags::memory::MemoryRegions;
+use crate::tags::proc::{ProcessInfo, Processes};
+use crate::tags::sver::SecurityVersion;
+use crate::tags::xkrn::XousKernel;
+use crate::tags::xram::RamBanks;
+use crate::xous_arguments::{
+    code_name, tag_crc, ParseError, XousArgument, XousArguments, XousSize,
+};
+
+/// A machine-readable description of an image, suitable for serializing
+/// to JSON.  Describes the image as it was last finalized.
+#[derive(Debug, Serialize)]
+pub struct ImageLayout {
+    /// Total size of the image, including payloads
+    pub image_size: u32,
+
+    /// Size of the tag stream, not including payloads
+    pub tags_size: u32,
+
+    /// Main RAM, as described by the `XArg` tag
+    pub main_ram: RegionLayout,
+
+    /// Additional banks of RAM from `XRam`
+    pub ram_banks: Vec<RegionLayout>,
+
+    /// Additional memory regions from `MREx`
+    pub regions: Vec<RegionLayout>,
+
+    /// Boot flags that are set, if there was a `Bflg` tag
+    pub boot_flags: Option<Vec<String>>,
+
+    /// Boot parameters from the `BPar` tag, if there is one
+    pub boot_params: Option<BTreeMap<String, String>>,
+
+    /// Provenance from the `BInf` tag, if there is one
+    pub build_info: Option<BuildInfoLayout>,
+
+    /// Anti-rollback version from the `SVer` tag, if there is one
+    pub security_version: Option<u32>,
+
+    /// SHA-256 of the image from the `Dgst` tag, if there is one
+    pub digest: Option<String>,
+
+    /// The kernel, if there is one
+    pub kernel: Option<KernelLayout>,
+
+    /// Every initial program, in load order
+    pub init: Vec<InitLayout>,
+
+    /// Every tag, including `XArg`, in image order
+    pub tags: Vec<TagLayout>,
+
+    /// Bytes the runtime needs to track memory allocations
+    pub memory_required: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegionLayout {
+    pub name: String,
+    pub code: u32,
+    pub start: u32,
+    pub length: u32,
+
+    /// Attributes from `MREx`, which main RAM doesn't have
+    pub attributes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BuildInfoLayout {
+    pub tool_version: String,
+
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub revision: String,
+
+    /// SHA-256 of the kernel ELF, then of each init program's ELF
+    pub elf_hashes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KernelLayout {
+    pub load_offset: u32,
+    pub payload_size: u32,
+
+    /// Bytes the payload occupies in the image, which is smaller than
+    /// `payload_size` if it is compressed
+    pub stored_size: u32,
+
+    /// Whether the payload is stored encrypted
+    pub encrypted: bool,
+    pub text_offset: u32,
+    pub text_size: u32,
+    pub data_offset: u32,
+    pub data_size: u32,
+    pub bss_size: u32,
+    pub entrypoint: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitLayout {
+    /// Name and loader hints from the `Proc` tag, if there is one
+    pub process: Option<ProcessLayout>,
+    pub load_offset: u32,
+    pub payload_size: u32,
+
+    /// Bytes the payload occupies in the image, which is smaller than
+    /// `payload_size` if it is compressed
+    pub stored_size: u32,
+
+    /// Whether the payload is stored encrypted
+    pub encrypted: bool,
+    pub entrypoint: u32,
+    pub sections: Vec<SectionLayout>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProcessLayout {
+    pub name: String,
+    pub stack_size: Option<u32>,
+    pub priority: Option<u8>,
+    pub debug: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SectionLayout {
+    pub virt: u32,
+    pub size: u32,
+    pub flags: Vec<String>,
+
+    /// Where the section's data lives in the image, or `None` if the
+    /// section is not copied (e.g. `.bss`) or the program is compressed.
+    /// Encryption doesn't move data, so this points into the ciphertext
+    /// if the program is encrypted.
+    pub load_offset: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagLayout {
+    pub name: String,
+    pub code: u32,
+    pub offset: u32,
+    pub length: u32,
+    pub crc: u16,
+}
+
+fn flag_names(flags: MiniElfFlags) -> Vec<String> {
+    let mut names = vec![];
+    if flags.contains(MiniElfFlags::WRITE) {
+        names.push("write".to_owned());
+    }
+    if flags.contains(MiniElfFlags::NOCOPY) {
+        names.push("nocopy".to_owned());
+    }
+    if flags.contains(MiniElfFlags::EXECUTE) {
+        names.push("execute".to_owned());
+    }
+    names
+}
+
+impl RegionLayout {
+    fn new(name: u32, start: XousSize, length: XousSize) -> RegionLayout {
+        RegionLayout {
+            name: code_name(name),
+            code: name,
+            start,
+            length,
+            attributes: None,
+        }
+    }
+}
+
+impl BuildInfoLayout {
+    fn new(binf: &BuildInfo) -> BuildInfoLayout {
+        BuildInfoLayout {
+            tool_version: binf.tool_version().to_owned(),
+            timestamp: binf.timestamp(),
+            revision: binf.revision().to_owned(),
+            elf_hashes: binf.elf_hashes_hex(),
+        }
+    }
+}
+
+impl KernelLayout {
+    /// Describe the kernel from the `XKrn` tag at byte `offset`.
+    fn new(xkrn: &XousKernel, offset: usize) -> Result<KernelLayout, ParseError> {
+        let bad_length = || ParseError::BadTagLength(offset, xkrn.code(), xkrn.length() as usize);
+        let payload_size = xkrn
+            .text_size()
+            .checked_add(xkrn.data_size())
+            .ok_or_else(bad_length)?;
+        Ok(KernelLayout {
+            load_offset: xkrn.load_offset(),
+            payload_size,
+            stored_size: xkrn.last_data().len() as u32,
+            encrypted: xkrn.is_encrypted(),
+            text_offset: xkrn.text_offset(),
+            text_size: xkrn.text_size(),
+            data_offset: xkrn.data_offset(),
+            data_size: xkrn.data_size(),
+            bss_size: xkrn.bss_size(),
+            entrypoint: xkrn.entrypoint(),
+        })
+    }
+}
+
+impl ProcessLayout {
+    fn new(process: &ProcessInfo) -> ProcessLayout {
+        ProcessLayout {
+            name: process.name.clone(),
+            stack_size: process.stack_size,
+            priority: process.priority,
+            debug: process.debug,
+        }
+    }
+}
+
+impl InitLayout {
+    /// Describe the init program from the `IniE` tag at byte `offset`.
+    fn new(
+        inie: &IniE,
+        process: Option<&ProcessInfo>,
+        offset: usize,
+    ) -> Result<InitLayout, ParseError> {
+        let bad_length = || ParseError::BadTagLength(offset, inie.code(), inie.length() as usize);
+        let mut load_offset = inie.load_offset();
+        let mut sections = vec![];
+        for section in inie.sections() {
+            let copied = !section.flags.contains(MiniElfFlags::NOCOPY);
+            sections.push(SectionLayout {
+                virt: section.virt,
+                size: section.size,
+                flags: flag_names(section.flags),
+                load_offset: if copied && !inie.is_compressed() {
+                    Some(load_offset)
+                } else {
+                    None
+                },
+            });
+            if copied {
+                // A corrupt tag can claim more than fits in an image
+                load_offset = load_offset
+                    .checked_add(section.size)
+                    .ok_or_else(bad_length)?;
+            }
+        }
+        Ok(InitLayout {
+            process: process.map(ProcessLayout::new),
+            load_offset: inie.load_offset(),
+            payload_size: inie.data_len(),
+            stored_size: inie.last_data().len() as u32,
+            encrypted: inie.is_encrypted(),
+            entrypoint: inie.entrypoint(),
+            sections,
+        })
+    }
+}
+
+impl ImageLayout {
+    /// Describe `args`.  Load offsets are only meaningful once the arguments
+    /// have been finalized, either by `write()` or by being parsed.  Fails
+    /// if the kernel or an init program claims more data than can exist.
+    pub fn new(args: &XousArguments) -> Result<ImageLayout, ParseError> {
+        let tags_size = args.len();
+        let mut image_size = tags_size;
+        let mut tags = vec![TagLayout {
+            name: "XArg".to_owned(),
+            code: make_type!("XArg"),
+            offset: 0,
+            length: 20,
+            crc: args.xarg_crc(),
+        }];
+        let mut offset = 28;
+        let mut kernel = None;
+        let mut init = vec![];
+        for arg in &args.arguments {
+            let any = arg.as_ref().as_any();
+            if let Some(xkrn) = any.downcast_ref::<XousKernel>() {
+                if kernel.is_none() {
+                    kernel = Some(KernelLayout::new(xkrn, offset as usize)?);
+                }
+            } else if let Some(inie) = any.downcast_ref::<IniE>() {
+                let process = args.get::<Processes>().and_then(|p| p.get(init.len()));
+                init.push(InitLayout::new(inie, process, offset as usize)?);
+            }
+            let mut data = vec![];
+            // Serializing into a Vec cannot fail
+            arg.serialize(&mut data).unwrap();
+            tags.push(TagLayout {
+                name: arg.name(),
+                code: arg.code(),
+                offset,
+                length: arg.length(),
+                crc: tag_crc(&data),
+            });
+            offset += 8 + arg.length();
+            image_size += arg.last_data().len() as u32;
+        }
+
+        let ram_banks = args
+            .iter::<RamBanks>()
+            .flat_map(|xram| xram.banks())
+            .map(|bank| RegionLayout::new(bank.name(), bank.start(), bank.length()))
+            .collect();
+
+        let mut regions = vec![];
+        for mrex in args.iter::<MemoryRegions>() {
+            for region in mrex.regions() {
+                let mut layout =
+                    RegionLayout::new(region.name(), region.start(), region.length());
+                layout.attributes = Some(
+                    region
+                        .attributes()
+                        .names()
+                        .into_iter()
+                        .map(str::to_owned)
+                        .collect(),
+                );
+                regions.push(layout);
+            }
+        }
+
+        let boot_flags = args.get::<Bflg>().map(|bflg| {
+            bflg.flags()
+                .names()
+                .into_iter()
+                .map(str::to_owned)
+                .collect()
+        });
+
+        Ok(ImageLayout {
+            image_size,
+            tags_size,
+            main_ram: RegionLayout::new(args.ram_name(), args.ram_start(), args.ram_length()),
+            ram_banks,
+            regions,
+            boot_flags,
+            boot_params: args
+                .get::<BootParams>()
+                .map(|bpar| bpar.params().iter().cloned().collect()),
+            build_info: args.get::<BuildInfo>().map(BuildInfoLayout::new),
+            security_version: args.get::<SecurityVersion>().map(SecurityVersion::version),
+            digest: args.get::<Digest>().map(Digest::hex),
+            kernel,
+            init,
+            tags,
+            memory_required: args.memory_required(),
+        })
+    }
+
+    /// Render this layout as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        // None of the layout types can fail to serialize
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{pattern, sample_args, section, write_image};
+
+    #[test]
+    fn image_layout() {
+        let mut args = sample_args();
+        let image = write_image(&mut args);
+        let layout = ImageLayout::new(&args).unwrap();
+        assert_eq!(layout.image_size as usize, image.len());
+        assert_eq!(layout.tags_size, args.len());
+        let names: Vec<&str> = layout.tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert_eq!(names, ["XArg", "MREx", "XKrn", "IniE"]);
+        assert_eq!(layout.main_ram.name, "SrEx");
+        assert_eq!(layout.main_ram.start, 0x4000_0000);
+        assert_eq!(layout.main_ram.attributes, None);
+        assert_eq!(layout.regions[0].name, "CSRs");
+        assert!(layout.regions[0].attributes.is_some());
+        assert!(layout.ram_banks.is_empty());
+        assert!(layout.to_json().contains("\"main_ram\""));
+    }
+
+    #[test]
+    fn kernel_layout() {
+        let mut args = sample_args();
+        let image = write_image(&mut args);
+        let layout = ImageLayout::new(&args).unwrap();
+        let kernel = layout.kernel.unwrap();
+        assert_eq!(kernel.payload_size, 24);
+        assert_eq!(kernel.stored_size, 24);
+        assert!(!kernel.encrypted);
+        let start = kernel.load_offset as usize;
+        assert_eq!(&image[start..start + 24], &pattern(24, 1)[..]);
+    }
+
+    #[test]
+    fn init_layout() {
+        let mut args = sample_args();
+        write_image(&mut args);
+        let layout = ImageLayout::new(&args).unwrap();
+        let init = &layout.init[0];
+        assert!(init.process.is_none());
+        assert_eq!(init.payload_size, 24);
+        let offsets: Vec<Option<u32>> = init.sections.iter().map(|s| s.load_offset).collect();
+        let start = init.load_offset;
+        assert_eq!(offsets, [Some(start), Some(start + 16), None]);
+        assert_eq!(init.sections[2].flags, ["write", "nocopy"]);
+    }
+
+    #[test]
+    fn overflowing_sections() {
+        let mut args = sample_args();
+        args.add(IniE::new(
+            0x3000_0000,
+            vec![
+                section(0x3000_0000, 0xffff_f000, MiniElfFlags::EXECUTE),
+                section(0x3000_0000, 0x2000, MiniElfFlags::WRITE),
+            ],
+            vec![],
+        ));
+        let offset = ImageLayout::new(&sample_args()).unwrap().tags_size as usize;
+        let err = ImageLayout::new(&args).unwrap_err();
+        assert!(matches!(err, ParseError::BadTagLength(o, _, _) if o == offset));
+
+        let mut args = XousArguments::new(0x4000_0000, 0x0100_0000, make_type!("SrEx"));
+        args.add(XousKernel::new(0, 0xffff_ffff, 0, 1, 0, 0, vec![]));
+        let err = ImageLayout::new(&args).unwrap_err();
+        assert!(matches!(err, ParseError::BadTagLength(28, _, _)));
+    }
+}
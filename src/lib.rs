@@ -1,6 +1,22 @@
 extern crate csv;
 extern crate log;
 #[macro_use] pub mod xous_arguments;
+pub mod builder;
 pub mod tags;
 pub mod utils;
 pub mod elf;
+pub mod encryption;
+pub mod diff;
+pub mod error;
+pub mod layout;
+pub mod manifest;
+pub mod memmap;
+pub mod params;
+pub mod patch;
+pub mod seal;
+pub mod signing;
+pub mod validate;
+pub mod verify;
+
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,625 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+use crate::builder::ImageBuilder;
+use crate::tags::bflg::BootFlags;
+use crate::tags::cmpr::Algorithm;
+use crate::tags::memory::RegionAttributes;
+use crate::tags::proc::ProcessInfo;
+
+/// A declarative description of an image, normally read from a TOML file.
+///
+/// ```toml
+/// kernel = "xous-kernel"
+/// compress = "lz4"
+///
+/// [[init]]
+/// path = "shell"
+/// name = "xous-shell"
+/// stack-size = 0x20000
+/// priority = 2
+///
+/// [[init]]
+/// path = "gdb-server"
+/// debug-only = true
+/// compress = "none"
+///
+/// [memory]
+/// csv = "csr.csv"
+/// main-ram = "main_ram"
+/// extra-ram = ["sram_ext"]
+///
+/// [memory.names]
+/// sram_ext2 = "SrE2"
+///
+/// [memory.attributes]
+/// SpFl = ["read", "execute", "cacheable"]
+///
+/// [flags]
+/// debug = true
+/// set = ["no_copy"]
+///
+/// [boot]
+/// schema = "boot-params.toml"
+///
+/// [boot.params]
+/// log-level = "info"
+/// baud = 115200
+///
+/// [build]
+/// revision = "v0.9.5-12-g1234abc"
+///
+/// [signing]
+/// key = "signing-key.pem"
+///
+/// [encryption]
+/// key = "payload-key.bin"
+///
+/// [security]
+/// version = 3
+/// previous-image = "released/args.bin"
+///
+/// [output]
+/// image = "args.bin"
+/// json = "args.json"
+/// ```
+///
+/// Relative paths are resolved against the directory containing the
+/// manifest.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Kernel ELF image
+    pub kernel: Option<PathBuf>,
+
+    /// How to compress the kernel and any init program that doesn't say
+    pub compress: Option<Compress>,
+
+    /// Initial programs, in load order
+    #[serde(default)]
+    pub init: Vec<InitProgram>,
+
+    #[serde(default)]
+    pub memory: MemoryConfig,
+
+    #[serde(default)]
+    pub flags: FlagsConfig,
+
+    #[serde(default)]
+    pub boot: BootConfig,
+
+    #[serde(default)]
+    pub build: BuildConfig,
+
+    #[serde(default)]
+    pub signing: SigningConfig,
+
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+
+    #[serde(default)]
+    pub security: SecurityConfig,
+
+    #[serde(default)]
+    pub output: OutputConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+pub struct InitProgram {
+    /// ELF file for this program
+    pub path: PathBuf,
+
+    /// Only include this program in debug images
+    #[serde(default)]
+    pub debug_only: bool,
+
+    /// How to compress this program, overriding the manifest's default
+    pub compress: Option<Compress>,
+
+    /// Name of the process, which defaults to the file's stem
+    pub name: Option<String>,
+
+    /// Stack size in bytes, if the loader's default isn't wanted
+    pub stack_size: Option<u32>,
+
+    /// Scheduling priority, if the loader's default isn't wanted
+    pub priority: Option<u8>,
+
+    /// Start this process with debugging enabled
+    #[serde(default)]
+    pub debug: bool,
+}
+
+impl InitProgram {
+    pub fn new<P: Into<PathBuf>>(path: P) -> InitProgram {
+        InitProgram {
+            path: path.into(),
+            debug_only: false,
+            compress: None,
+            name: None,
+            stack_size: None,
+            priority: None,
+            debug: false,
+        }
+    }
+
+    /// The name and loader hints to record for this program.
+    pub fn process(&self) -> ProcessInfo {
+        ProcessInfo {
+            name: self.name.clone().unwrap_or_default(),
+            stack_size: self.stack_size,
+            priority: self.priority,
+            debug: self.debug,
+        }
+    }
+}
+
+/// Payload compression, as written in a manifest
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compress {
+    None,
+    Lz4,
+    Deflate,
+}
+
+impl Compress {
+    pub fn algorithm(self) -> Option<Algorithm> {
+        match self {
+            Compress::None => None,
+            Compress::Lz4 => Some(Algorithm::Lz4),
+            Compress::Deflate => Some(Algorithm::Deflate),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+pub struct MemoryConfig {
+    /// csr.csv file from LiteX
+    pub csv: Option<PathBuf>,
+
+    /// Region in the CSV file to use as main RAM, rather than the largest
+    /// one with "ram" in its name
+    pub main_ram: Option<String>,
+
+    /// Regions the kernel may also allocate from, as extra banks of RAM
+    #[serde(default)]
+    pub extra_ram: Vec<String>,
+
+    /// Main RAM, if not taken from the CSV file
+    pub ram: Option<RamConfig>,
+
+    /// Extra memory regions
+    #[serde(default)]
+    pub regions: Vec<RegionConfig>,
+
+    /// Four-character codes for memory regions, by name, for regions that
+    /// would otherwise get the same code
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
+
+    /// Attributes for memory regions, by name, overriding the defaults
+    #[serde(default, deserialize_with = "attribute_names")]
+    pub attributes: BTreeMap<String, RegionAttributes>,
+}
+
+impl MemoryConfig {
+    /// Take main RAM from `ram` or `csv` instead of wherever this config
+    /// takes it from, rather than combining the two.  The `main-ram` and
+    /// `extra-ram` names refer to regions in the old CSV file, so they are
+    /// dropped as well.
+    pub fn replace_main_ram(&mut self, ram: Option<RamConfig>, csv: Option<PathBuf>) {
+        self.ram = ram;
+        self.csv = csv;
+        self.main_ram = None;
+        self.extra_ram.clear();
+    }
+}
+
+fn attribute_names<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, RegionAttributes>, D::Error> {
+    let mut map = BTreeMap::new();
+    for (region, names) in BTreeMap::<String, Vec<String>>::deserialize(deserializer)? {
+        let mut attributes = RegionAttributes::empty();
+        for name in names {
+            attributes |= RegionAttributes::from_name(&name).ok_or_else(|| {
+                D::Error::custom(format!("unknown memory region attribute \"{}\"", name))
+            })?;
+        }
+        map.insert(region, attributes);
+    }
+    Ok(map)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RamConfig {
+    pub start: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegionConfig {
+    pub name: String,
+    pub start: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlagsConfig {
+    /// Reduce kernel-userspace security and enable debugging programs
+    #[serde(default)]
+    pub debug: bool,
+
+    /// Other boot flags to set, by name
+    #[serde(default, deserialize_with = "flag_names")]
+    pub set: BootFlags,
+}
+
+impl FlagsConfig {
+    /// Every boot flag that should be set, including `debug`.
+    pub fn boot_flags(&self) -> BootFlags {
+        if self.debug {
+            self.set | BootFlags::DEBUG
+        } else {
+            self.set
+        }
+    }
+}
+
+fn flag_names<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BootFlags, D::Error> {
+    let mut flags = BootFlags::empty();
+    for name in Vec::<String>::deserialize(deserializer)? {
+        flags |= BootFlags::from_name(&name)
+            .ok_or_else(|| D::Error::custom(format!("unknown boot flag \"{}\"", name)))?;
+    }
+    Ok(flags)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BootConfig {
+    /// Schema to check the boot parameters against
+    pub schema: Option<PathBuf>,
+
+    /// Boot parameters to pass to the kernel, in name order
+    #[serde(default)]
+    pub params: BTreeMap<String, ParamValue>,
+}
+
+/// A boot parameter value, which is always stored as a string but may be
+/// written as a number or a boolean in a manifest
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    String(String),
+    Integer(u32),
+    Bool(bool),
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamValue::String(s) => write!(f, "{}", s),
+            ParamValue::Integer(i) => write!(f, "{}", i),
+            ParamValue::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildConfig {
+    /// Source revision to record in the image, such as a git commit
+    pub revision: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SigningConfig {
+    /// Ed25519 private key to sign the image with
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionConfig {
+    /// Key-encryption key used to wrap the key of each encrypted payload
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+pub struct SecurityConfig {
+    /// Anti-rollback version, which must never decrease between releases
+    pub version: Option<u32>,
+
+    /// A previously shipped image whose version this one must not be below
+    pub previous_image: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Where to write the image
+    pub image: Option<PathBuf>,
+
+    /// Where to write a JSON description of the image
+    pub json: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    /// Couldn't read the manifest file
+    Io(PathBuf, std::io::Error),
+
+    /// The manifest wasn't valid
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Io(p, e) => write!(f, "{}: {}", p.display(), e),
+            ManifestError::Parse(p, e) => write!(f, "{}: {}", p.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+fn resolve(base: &Path, path: &mut PathBuf) {
+    if path.is_relative() {
+        *path = base.join(&path);
+    }
+}
+
+impl Manifest {
+    /// Read a manifest, resolving any relative paths against its directory.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Manifest, ManifestError> {
+        let path = path.as_ref();
+        let contents =
+            fs::read_to_string(path).map_err(|e| ManifestError::Io(path.to_path_buf(), e))?;
+        let mut manifest: Manifest =
+            toml::from_str(&contents).map_err(|e| ManifestError::Parse(path.to_path_buf(), e))?;
+
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        if let Some(kernel) = &mut manifest.kernel {
+            resolve(base, kernel);
+        }
+        for init in &mut manifest.init {
+            resolve(base, &mut init.path);
+        }
+        if let Some(csv) = &mut manifest.memory.csv {
+            resolve(base, csv);
+        }
+        if let Some(schema) = &mut manifest.boot.schema {
+            resolve(base, schema);
+        }
+        if let Some(key) = &mut manifest.signing.key {
+            resolve(base, key);
+        }
+        if let Some(key) = &mut manifest.encryption.key {
+            resolve(base, key);
+        }
+        if let Some(previous) = &mut manifest.security.previous_image {
+            resolve(base, previous);
+        }
+        if let Some(image) = &mut manifest.output.image {
+            resolve(base, image);
+        }
+        if let Some(json) = &mut manifest.output.json {
+            resolve(base, json);
+        }
+        Ok(manifest)
+    }
+
+    /// Create an `ImageBuilder` that will build the image described here.
+    pub fn builder(&self) -> ImageBuilder {
+        let mut builder = ImageBuilder::new().flags(self.flags.boot_flags());
+        let default_compression = self.compress.and_then(Compress::algorithm);
+        if let Some(kernel) = &self.kernel {
+            builder = builder.kernel(kernel);
+        }
+        if let Some(algorithm) = default_compression {
+            builder = builder.compress_kernel(algorithm);
+        }
+        for init in &self.init {
+            if init.debug_only && !self.flags.boot_flags().contains(BootFlags::DEBUG) {
+                continue;
+            }
+            let compression = match init.compress {
+                Some(compress) => compress.algorithm(),
+                None => default_compression,
+            };
+            builder = builder.init_with(&init.path, compression, init.process());
+        }
+        if let Some(csv) = &self.memory.csv {
+            builder = builder.csv(csv);
+        }
+        if let Some(ram) = &self.memory.ram {
+            builder = builder.ram(ram.start, ram.length);
+        }
+        if let Some(name) = &self.memory.main_ram {
+            builder = builder.main_ram(name);
+        }
+        for region in &self.memory.regions {
+            builder = builder.region(&region.name, region.start, region.length);
+        }
+        for name in &self.memory.extra_ram {
+            builder = builder.extra_ram(name);
+        }
+        for (name, code) in &self.memory.names {
+            builder = builder.region_name(name, code);
+        }
+        for (name, attributes) in &self.memory.attributes {
+            builder = builder.region_attributes(name, *attributes);
+        }
+        for (key, value) in &self.boot.params {
+            builder = builder.param(key, &value.to_string());
+        }
+        if let Some(schema) = &self.boot.schema {
+            builder = builder.param_schema(schema);
+        }
+        if let Some(revision) = &self.build.revision {
+            builder = builder.revision(revision);
+        }
+        if let Some(key) = &self.signing.key {
+            builder = builder.signing_key(key);
+        }
+        if let Some(key) = &self.encryption.key {
+            builder = builder.encryption_key(key);
+        }
+        if let Some(version) = self.security.version {
+            builder = builder.security_version(version);
+        }
+        if let Some(previous) = &self.security.previous_image {
+            builder = builder.previous_image(previous);
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::tags::proc::Processes;
+    use crate::tags::xram::RamBanks;
+    use crate::test_util::{write_elfs, TempDir};
+    use crate::xous_arguments::XousArguments;
+
+    const MANIFEST: &str = r#"
+kernel = "kernel.elf"
+compress = "lz4"
+
+[[init]]
+path = "init2.elf"
+name = "shell"
+stack-size = 0x20000
+priority = 2
+
+[[init]]
+path = "init2.elf"
+debug-only = true
+compress = "none"
+
+[memory]
+ram = { start = 0x40000000, length = 0x1000000 }
+regions = [{ name = "CSRs", start = 0xf0000000, length = 0x3000 }]
+
+[flags]
+set = ["no_copy"]
+
+[output]
+image = "out/args.bin"
+"#;
+
+    #[test]
+    fn load() {
+        let dir = TempDir::new();
+        let path = dir.write("image.toml", MANIFEST.as_bytes());
+        let base = path.parent().unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+
+        assert_eq!(manifest.kernel, Some(base.join("kernel.elf")));
+        assert_eq!(manifest.output.image, Some(base.join("out/args.bin")));
+        assert_eq!(manifest.init.len(), 2);
+        assert_eq!(manifest.init[0].path, base.join("init2.elf"));
+        let process = manifest.init[0].process();
+        assert_eq!(process.name, "shell");
+        assert_eq!(process.stack_size, Some(0x20000));
+        assert_eq!(process.priority, Some(2));
+        assert!(manifest.init[1].debug_only);
+        assert_eq!(manifest.init[1].compress.and_then(Compress::algorithm), None);
+        let ram = manifest.memory.ram.as_ref().unwrap();
+        assert_eq!((ram.start, ram.length), (0x4000_0000, 0x0100_0000));
+        assert_eq!(manifest.memory.regions[0].name, "CSRs");
+        assert_eq!(manifest.flags.boot_flags(), BootFlags::NO_COPY);
+    }
+
+    #[test]
+    fn build_from_manifest() {
+        let dir = TempDir::new();
+        write_elfs(&dir, 2);
+        let path = dir.write("image.toml", MANIFEST.as_bytes());
+        let built = Manifest::load(&path)
+            .unwrap()
+            .builder()
+            .build()
+            .unwrap();
+        let args = XousArguments::parse(&built.image).unwrap();
+        // The debug-only program is left out of a release image
+        let processes = args.get::<Processes>().unwrap().processes();
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].name, "shell");
+        assert_eq!(args.ram_start(), 0x4000_0000);
+    }
+
+    #[test]
+    fn replace_main_ram() {
+        let dir = TempDir::new();
+        write_elfs(&dir, 2);
+        let path = dir.write(
+            "image.toml",
+            b"kernel = \"kernel.elf\"\n\
+              [memory]\n\
+              csv = \"csr.csv\"\n\
+              main-ram = \"sram\"\n\
+              extra-ram = [\"spiram\"]\n",
+        );
+        let mut manifest = Manifest::load(&path).unwrap();
+        // The CSV file doesn't exist, so only a replaced main RAM can build
+        let err = manifest.builder().build();
+        assert!(matches!(err, Err(Error::Csv(..))));
+
+        let ram = RamConfig {
+            start: 0x5000_0000,
+            length: 0x0080_0000,
+        };
+        manifest.memory.replace_main_ram(Some(ram), None);
+        assert!(manifest.memory.csv.is_none());
+        assert!(manifest.memory.main_ram.is_none());
+        assert!(manifest.memory.extra_ram.is_empty());
+
+        let built = manifest.builder().build().unwrap();
+        let args = XousArguments::parse(&built.image).unwrap();
+        assert_eq!(args.ram_start(), 0x5000_0000);
+        assert_eq!(args.ram_length(), 0x0080_0000);
+        assert!(args.get::<RamBanks>().is_none());
+    }
+
+    #[test]
+    fn bad_manifests() {
+        let dir = TempDir::new();
+        let missing = dir.write("unused", b"").with_file_name("missing.toml");
+        assert!(matches!(Manifest::load(missing), Err(ManifestError::Io(..))));
+
+        for contents in [
+            "kernal = \"kernel.elf\"\n",
+            "[flags]\nset = [\"fast\"]\n",
+            "[memory.attributes]\nSpFl = [\"writable\"]\n",
+            "compress = \"zstd\"\n",
+        ] {
+            let path = dir.write("bad.toml", contents.as_bytes());
+            assert!(
+                matches!(Manifest::load(path), Err(ManifestError::Parse(..))),
+                "{}",
+                contents
+            );
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::fmt::Write;
+
+use crate::validate::{
+    address_spaces, validate_layout, AddressSpace, Item, ItemKind, LayoutProblem,
+};
+use crate::xous_arguments::XousArguments;
+
+/// Width of the bar drawn for each item in the text map, in characters.
+const TEXT_BAR_WIDTH: u64 = 48;
+
+const SVG_WIDTH: u64 = 960;
+const SVG_BAR_X: u64 = 480;
+const SVG_BAR_WIDTH: u64 = SVG_WIDTH - SVG_BAR_X - 20;
+const SVG_ROW_HEIGHT: u64 = 20;
+
+/// A picture of where everything in an image lives: main RAM and the
+/// memory regions, the kernel's sections and each init program's
+/// sections.  Items that collide with something are highlighted.
+pub struct MemoryMap {
+    spaces: Vec<AddressSpace>,
+    problems: Vec<LayoutProblem>,
+}
+
+fn format_size(size: u64) -> String {
+    if size >= 1 << 20 && size % (1 << 20) == 0 {
+        format!("{} MiB", size >> 20)
+    } else if size >= 1 << 10 && size % (1 << 10) == 0 {
+        format!("{} KiB", size >> 10)
+    } else {
+        format!("{} B", size)
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn svg_color(kind: ItemKind) -> &'static str {
+    match kind {
+        ItemKind::Ram => "#4e79a7",
+        ItemKind::Region => "#59a14f",
+        ItemKind::Text => "#f28e2b",
+        ItemKind::Data => "#edc948",
+        ItemKind::Bss => "#b07aa1",
+    }
+}
+
+/// The lowest and highest address used in a space, so that its items can
+/// be drawn to scale.
+fn span(space: &AddressSpace) -> (u64, u64) {
+    let start = space.items.iter().map(|i| i.start).min().unwrap_or(0);
+    let end = space.items.iter().map(|i| i.end).max().unwrap_or(0);
+    (start, end.max(start + 1))
+}
+
+/// Scale `item` to a run of `width` units within `span`, always at least
+/// one unit long so that small items are still visible.
+fn scale(item: &Item, (start, end): (u64, u64), width: u64) -> (u64, u64) {
+    let from = (item.start - start) * width / (end - start);
+    let to = (item.end - start) * width / (end - start);
+    let from = from.min(width - 1);
+    (from, to.max(from + 1).min(width))
+}
+
+/// The name of an item without the name of its space, which is already
+/// given in the heading.
+fn short_name<'a>(space: &AddressSpace, item: &'a Item) -> &'a str {
+    item.name
+        .strip_prefix(space.name.as_str())
+        .map(str::trim_start)
+        .unwrap_or(&item.name)
+}
+
+impl MemoryMap {
+    /// Describe the memory layout of `args`.
+    pub fn new(args: &XousArguments) -> MemoryMap {
+        let mut spaces = address_spaces(args);
+        for space in &mut spaces {
+            space.items.sort_by_key(|item| (item.start, item.end));
+        }
+        MemoryMap {
+            spaces,
+            problems: validate_layout(args),
+        }
+    }
+
+    /// Names of everything that `item` overlaps.
+    fn collisions(&self, item: &Item) -> Vec<&str> {
+        self.problems
+            .iter()
+            .filter_map(|problem| match problem {
+                LayoutProblem::Overlap(a, b) if a == item => Some(b.name.as_str()),
+                LayoutProblem::Overlap(a, b) if b == item => Some(a.name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Render the map as aligned text for a terminal.  Items that collide
+    /// with something are marked with `!` and drawn with `X`.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let name_width = self
+            .spaces
+            .iter()
+            .flat_map(|space| {
+                space
+                    .items
+                    .iter()
+                    .map(move |item| short_name(space, item).len())
+            })
+            .max()
+            .unwrap_or(0);
+        for space in &self.spaces {
+            let span = span(space);
+            // Writing to a String cannot fail
+            writeln!(out, "{}:", space.name).unwrap();
+            for item in &space.items {
+                let collisions = self.collisions(item);
+                let (from, to) = scale(item, span, TEXT_BAR_WIDTH);
+                let fill = if collisions.is_empty() { "#" } else { "X" };
+                write!(
+                    out,
+                    "  {} {:08x} - {:08x} {:>8}  {:<width$}  |{}{}{}|",
+                    if collisions.is_empty() { " " } else { "!" },
+                    item.start,
+                    item.end,
+                    format_size(item.end - item.start),
+                    short_name(space, item),
+                    " ".repeat(from as usize),
+                    fill.repeat((to - from) as usize),
+                    " ".repeat((TEXT_BAR_WIDTH - to) as usize),
+                    width = name_width,
+                )
+                .unwrap();
+                if !collisions.is_empty() {
+                    write!(out, "  overlaps {}", collisions.join(", ")).unwrap();
+                }
+                writeln!(out).unwrap();
+            }
+        }
+        out
+    }
+
+    /// Render the map as a standalone SVG image.  Items that collide with
+    /// something are drawn in red and named on hover.
+    pub fn to_svg(&self) -> String {
+        let rows: u64 = self
+            .spaces
+            .iter()
+            .map(|space| space.items.len() as u64 + 2)
+            .sum();
+        let height = rows * SVG_ROW_HEIGHT + 10;
+        let mut out = String::new();
+        writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="monospace" font-size="12" xml:space="preserve">"#,
+            w = SVG_WIDTH,
+            h = height
+        )
+        .unwrap();
+        writeln!(
+            out,
+            r#"<rect width="{}" height="{}" fill="white"/>"#,
+            SVG_WIDTH, height
+        )
+        .unwrap();
+
+        let mut y = SVG_ROW_HEIGHT;
+        for space in &self.spaces {
+            let span = span(space);
+            writeln!(
+                out,
+                r#"<text x="10" y="{}" font-weight="bold">{}</text>"#,
+                y,
+                xml_escape(&space.name)
+            )
+            .unwrap();
+            y += SVG_ROW_HEIGHT;
+            for item in &space.items {
+                let collisions = self.collisions(item);
+                let (from, to) = scale(item, span, SVG_BAR_WIDTH);
+                let top = y - SVG_ROW_HEIGHT + 6;
+                writeln!(
+                    out,
+                    r#"<text x="20" y="{}"{}>{:08x} - {:08x} {:>8}  {}</text>"#,
+                    y,
+                    if collisions.is_empty() {
+                        ""
+                    } else {
+                        r##" fill="#c00""##
+                    },
+                    item.start,
+                    item.end,
+                    format_size(item.end - item.start),
+                    xml_escape(short_name(space, item))
+                )
+                .unwrap();
+                writeln!(
+                    out,
+                    r##"<rect x="{}" y="{}" width="{}" height="{}" fill="#eee"/>"##,
+                    SVG_BAR_X,
+                    top,
+                    SVG_BAR_WIDTH,
+                    SVG_ROW_HEIGHT - 4
+                )
+                .unwrap();
+                let (fill, stroke) = if collisions.is_empty() {
+                    (svg_color(item.kind), "none")
+                } else {
+                    ("#e15759", "#900")
+                };
+                write!(
+                    out,
+                    r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" stroke="{}">"#,
+                    SVG_BAR_X + from,
+                    top,
+                    to - from,
+                    SVG_ROW_HEIGHT - 4,
+                    fill,
+                    stroke
+                )
+                .unwrap();
+                write!(out, "<title>{}", xml_escape(&item.name)).unwrap();
+                if !collisions.is_empty() {
+                    write!(out, " overlaps {}", xml_escape(&collisions.join(", "))).unwrap();
+                }
+                writeln!(out, "</title></rect>").unwrap();
+                y += SVG_ROW_HEIGHT;
+            }
+            y += SVG_ROW_HEIGHT;
+        }
+        writeln!(out, "</svg>").unwrap();
+        out
+    }
+}
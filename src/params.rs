@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::utils::parse_u32;
+
+/// The kinds of value a boot parameter can take.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamKind {
+    /// Any string
+    String,
+
+    /// `true` or `false`
+    Bool,
+
+    /// An unsigned 32-bit number, in decimal or with a `0x` prefix
+    Integer,
+
+    /// One of a fixed list of strings
+    Enum,
+}
+
+/// The description of a single boot parameter in a schema.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParamSpec {
+    #[serde(rename = "type")]
+    pub kind: ParamKind,
+
+    /// The image can't be built without this parameter
+    #[serde(default)]
+    pub required: bool,
+
+    /// Smallest value allowed for an integer
+    pub min: Option<u32>,
+
+    /// Largest value allowed for an integer
+    pub max: Option<u32>,
+
+    /// Values allowed for an enum
+    #[serde(default)]
+    pub values: Vec<String>,
+
+    pub description: Option<String>,
+}
+
+/// The boot parameters the kernel understands, normally read from a TOML
+/// file with a table for each parameter:
+///
+/// ```toml
+/// [log-level]
+/// type = "enum"
+/// values = ["error", "warn", "info", "debug", "trace"]
+///
+/// [console]
+/// type = "string"
+/// required = true
+///
+/// [test-mode]
+/// type = "bool"
+///
+/// [baud]
+/// type = "integer"
+/// min = 9600
+/// max = 3000000
+/// ```
+#[derive(Debug, Default)]
+pub struct ParamSchema {
+    params: BTreeMap<String, ParamSpec>,
+}
+
+#[derive(Debug)]
+pub enum ParamError {
+    /// Couldn't read the schema file
+    SchemaIo(PathBuf, std::io::Error),
+
+    /// The schema wasn't valid
+    SchemaParse(PathBuf, String),
+
+    /// A parameter name contained something other than letters, digits,
+    /// `_`, `-` or `.`
+    InvalidKey(String),
+
+    /// A parameter value contained a NUL byte
+    InvalidValue(String /* key */),
+
+    /// The schema doesn't describe this parameter
+    Unknown(String),
+
+    /// The value doesn't match the schema
+    BadValue(
+        String, /* key */
+        String, /* value */
+        String, /* reason */
+    ),
+
+    /// A parameter the schema requires was not given
+    Missing(String),
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ParamError::*;
+        match self {
+            SchemaIo(p, e) => write!(f, "{}: {}", p.display(), e),
+            SchemaParse(p, e) => write!(f, "{}: {}", p.display(), e),
+            InvalidKey(k) => write!(f, "boot parameter name \"{}\" is not valid", k),
+            InvalidValue(k) => write!(f, "boot parameter {} contains a NUL byte", k),
+            Unknown(k) => write!(f, "unknown boot parameter {}", k),
+            BadValue(k, v, r) => write!(f, "boot parameter {}={}: {}", k, v, r),
+            Missing(k) => write!(f, "required boot parameter {} was not given", k),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+fn valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Check that a parameter can be stored in a `BPar` tag.
+pub fn check_param(key: &str, value: &str) -> Result<(), ParamError> {
+    if !valid_key(key) {
+        return Err(ParamError::InvalidKey(key.to_owned()));
+    }
+    if value.contains('\0') {
+        return Err(ParamError::InvalidValue(key.to_owned()));
+    }
+    Ok(())
+}
+
+impl ParamSpec {
+    fn check(&self, key: &str, value: &str) -> Result<(), ParamError> {
+        let bad = |reason: String| ParamError::BadValue(key.to_owned(), value.to_owned(), reason);
+        match self.kind {
+            ParamKind::String => Ok(()),
+            ParamKind::Bool => match value {
+                "true" | "false" => Ok(()),
+                _ => Err(bad("expected true or false".to_owned())),
+            },
+            ParamKind::Integer => {
+                let number = parse_u32(value).map_err(|_| bad("expected a number".to_owned()))?;
+                if let Some(min) = self.min.filter(|&min| number < min) {
+                    return Err(bad(format!("must be at least {}", min)));
+                }
+                if let Some(max) = self.max.filter(|&max| number > max) {
+                    return Err(bad(format!("must be at most {}", max)));
+                }
+                Ok(())
+            }
+            ParamKind::Enum => {
+                if self.values.iter().any(|v| v == value) {
+                    Ok(())
+                } else {
+                    Err(bad(format!("expected one of {}", self.values.join(", "))))
+                }
+            }
+        }
+    }
+}
+
+impl ParamSchema {
+    /// Read a schema from a TOML file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ParamSchema, ParamError> {
+        let path = path.as_ref();
+        let contents =
+            fs::read_to_string(path).map_err(|e| ParamError::SchemaIo(path.to_path_buf(), e))?;
+        let params: BTreeMap<String, ParamSpec> = toml::from_str(&contents)
+            .map_err(|e| ParamError::SchemaParse(path.to_path_buf(), e.to_string()))?;
+        for key in params.keys() {
+            if !valid_key(key) {
+                return Err(ParamError::SchemaParse(
+                    path.to_path_buf(),
+                    format!("\"{}\" is not a valid parameter name", key),
+                ));
+            }
+        }
+        Ok(ParamSchema { params })
+    }
+
+    /// Look up the description of a parameter.
+    pub fn get(&self, key: &str) -> Option<&ParamSpec> {
+        self.params.get(key)
+    }
+
+    /// Check every parameter against the schema, and make sure that all
+    /// of the required ones are present.
+    pub fn validate(&self, params: &[(String, String)]) -> Result<(), ParamError> {
+        for (key, value) in params {
+            check_param(key, value)?;
+            let spec = self
+                .params
+                .get(key)
+                .ok_or_else(|| ParamError::Unknown(key.clone()))?;
+            spec.check(key, value)?;
+        }
+        for (key, spec) in &self.params {
+            if spec.required && !params.iter().any(|(k, _)| k == key) {
+                return Err(ParamError::Missing(key.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"
+[log-level]
+type = "enum"
+values = ["error", "warn", "info"]
+
+[console]
+type = "string"
+required = true
+
+[test-mode]
+type = "bool"
+
+[baud]
+type = "integer"
+min = 9600
+max = 3000000
+"#;
+
+    fn schema() -> ParamSchema {
+        ParamSchema {
+            params: toml::from_str(SCHEMA).unwrap(),
+        }
+    }
+
+    fn validate(params: &[(&str, &str)]) -> Result<(), ParamError> {
+        let mut all = vec![("console".to_owned(), "uart0".to_owned())];
+        all.extend(params.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        schema().validate(&all)
+    }
+
+    fn bad_value(result: Result<(), ParamError>) -> String {
+        match result {
+            Err(ParamError::BadValue(_, _, reason)) => reason,
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn valid_params() {
+        validate(&[]).unwrap();
+        validate(&[
+            ("log-level", "warn"),
+            ("test-mode", "true"),
+            ("baud", "0x1c200"),
+        ])
+        .unwrap();
+    }
+
+    #[test]
+    fn unknown_param() {
+        match validate(&[("colour", "blue")]) {
+            Err(ParamError::Unknown(key)) => assert_eq!(key, "colour"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_param() {
+        match schema().validate(&[]) {
+            Err(ParamError::Missing(key)) => assert_eq!(key, "console"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn integer_range() {
+        validate(&[("baud", "9600")]).unwrap();
+        validate(&[("baud", "3000000")]).unwrap();
+        assert_eq!(
+            bad_value(validate(&[("baud", "9599")])),
+            "must be at least 9600"
+        );
+        assert_eq!(
+            bad_value(validate(&[("baud", "3000001")])),
+            "must be at most 3000000"
+        );
+        assert_eq!(
+            bad_value(validate(&[("baud", "fast")])),
+            "expected a number"
+        );
+    }
+
+    #[test]
+    fn bad_bool() {
+        assert_eq!(
+            bad_value(validate(&[("test-mode", "yes")])),
+            "expected true or false"
+        );
+    }
+
+    #[test]
+    fn enum_mismatch() {
+        assert_eq!(
+            bad_value(validate(&[("log-level", "debug")])),
+            "expected one of error, warn, info"
+        );
+    }
+
+    #[test]
+    fn unstorable_params() {
+        check_param("log.level_2-a", "").unwrap();
+        for key in ["", "a=b", "a b", "caf\u{e9}"] {
+            match check_param(key, "x") {
+                Err(ParamError::InvalidKey(k)) => assert_eq!(k, key),
+                other => panic!("unexpected result for {:?}: {:?}", key, other),
+            }
+        }
+        match check_param("console", "uart\0") {
+            Err(ParamError::InvalidValue(k)) => assert_eq!(k, "console"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // The schema check stops bad parameters too
+        match validate(&[("console", "a\0b")]) {
+            Err(ParamError::InvalidValue(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
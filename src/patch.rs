@@ -0,0 +1,788 @@
+use std::fmt;
+
+use ed25519_dalek::SigningKey;
+
+use crate::elf::{MiniElf, ProgramDescription};
+use crate::encryption::KeyEncryptionKey;
+use crate::error::Error;
+use crate::seal::Sha256Hash;
+use crate::tags::bflg::{Bflg, BootFlags};
+use crate::tags::binf::BuildInfo;
+use crate::tags::cmpr::{Algorithm, Compression};
+use crate::tags::encr::Encryption;
+use crate::tags::inie::IniE;
+use crate::tags::memory::{MemoryRegion, MemoryRegions, RegionAttributes};
+use crate::tags::proc::{ProcessInfo, Processes, MAX_NAME_LENGTH};
+use crate::tags::sign::Signature;
+use crate::tags::sver::SecurityVersion;
+use crate::tags::xkrn::XousKernel;
+use crate::xous_arguments::{code_name, XousArgument, XousArgumentCode, XousArguments};
+
+/// A single modification to an existing image.
+///
+/// New programs come with the SHA-256 of the ELF file they were read from,
+/// which replaces the old program's hash in the `BInf` tag.
+pub enum Patch {
+    /// Replace the init program at this index
+    ReplaceInit(usize, MiniElf, Sha256Hash),
+
+    /// Add an init program after all the existing ones, with this name and
+    /// these loader hints
+    AddInit(MiniElf, ProcessInfo, Sha256Hash),
+
+    /// Remove the init program at this index
+    RemoveInit(usize),
+
+    /// Replace the kernel, adding one if there wasn't one already
+    ReplaceKernel(ProgramDescription, Sha256Hash),
+
+    /// Set or clear a named boot flag
+    SetFlag(String, bool),
+
+    /// Add an extra memory region
+    AddRegion(MemoryRegion),
+
+    /// Remove the extra memory region with this name
+    RemoveRegion(XousArgumentCode),
+
+    /// Replace the attributes of the extra memory region with this name
+    SetRegionAttributes(XousArgumentCode, RegionAttributes),
+
+    /// Set the security version, which may not go down
+    SetSecurityVersion(u32),
+
+    /// Sign the image with this key, replacing any existing signature
+    Sign(SigningKey),
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    /// There is no init program at this index
+    NoSuchInit(usize /* index */, usize /* count */),
+
+    /// The boot flag name wasn't recognised
+    UnknownFlag(String),
+
+    /// There is no extra region with this name
+    NoSuchRegion(XousArgumentCode),
+
+    /// A region with this name already exists
+    RegionExists(XousArgumentCode),
+
+    /// The program being added or replaced has to be encrypted like the
+    /// rest of the image, but no key was given
+    Encrypted(String),
+
+    /// The program being added or replaced couldn't be encrypted
+    EncryptionFailed(String /* program */, String /* reason */),
+
+    /// The new security version is lower than the existing one
+    Rollback(u32 /* existing */, u32 /* new */),
+
+    /// The process name doesn't fit in a `Proc` entry
+    NameTooLong(usize /* length */),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use PatchError::*;
+        match self {
+            NoSuchInit(i, c) => write!(f, "no init program {}, image has {}", i, c),
+            UnknownFlag(s) => write!(f, "unknown boot flag \"{}\"", s),
+            NoSuchRegion(n) => write!(f, "no memory region named {}", code_name(*n)),
+            RegionExists(n) => write!(f, "memory region {} already exists", code_name(*n)),
+            Encrypted(s) => write!(
+                f,
+                "{} has to be encrypted like the rest of the image, but no key was given",
+                s
+            ),
+            EncryptionFailed(s, e) => write!(f, "couldn't encrypt {}: {}", s, e),
+            Rollback(old, new) => write!(
+                f,
+                "security version {} is lower than the image's existing version {}",
+                new, old
+            ),
+            NameTooLong(len) => write!(
+                f,
+                "process name is {} bytes long, at most {} are allowed",
+                len, MAX_NAME_LENGTH
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+fn make_init(elf: MiniElf) -> IniE {
+    IniE::new(elf.entry_point, elf.sections, elf.program)
+}
+
+/// Index at which to insert a tag that must come before any payloads.
+fn before_payloads(args: &XousArguments) -> usize {
+    args.positions::<IniE>()
+        .into_iter()
+        .chain(args.positions::<XousKernel>())
+        .chain(args.positions::<Compression>())
+        .chain(args.positions::<Encryption>())
+        .min()
+        .unwrap_or(args.arguments.len())
+}
+
+/// Index of the first `Cmpr` or `Encr` tag describing the payload of the
+/// argument at `pos`, or `pos` itself if there are none.
+fn encoding_start(args: &XousArguments, mut pos: usize) -> usize {
+    while pos > 0 {
+        let prev = args.arguments[pos - 1].as_ref().as_any();
+        if !prev.is::<Compression>() && !prev.is::<Encryption>() {
+            break;
+        }
+        pos -= 1;
+    }
+    pos
+}
+
+/// How the payload of a program is stored in the image.
+#[derive(Clone, Copy, Default)]
+struct Storage {
+    compression: Option<Algorithm>,
+    encrypted: bool,
+}
+
+/// How the payload of the argument at `pos` is stored, going by the `Cmpr`
+/// and `Encr` tags before it.
+fn storage_of(args: &XousArguments, pos: usize) -> Storage {
+    let mut storage = Storage::default();
+    for arg in &args.arguments[encoding_start(args, pos)..pos] {
+        let arg = arg.as_ref().as_any();
+        if let Some(cmpr) = arg.downcast_ref::<Compression>() {
+            storage.compression = Some(cmpr.algorithm());
+        }
+        storage.encrypted |= arg.is::<Encryption>();
+    }
+    storage
+}
+
+/// How a new init program should be stored: compressed like the existing
+/// ones, and encrypted if any payload in the image is.
+fn storage_for_new_init(args: &XousArguments) -> Storage {
+    let inits = args.positions::<IniE>();
+    let compression = inits
+        .iter()
+        .rev()
+        .find_map(|&pos| storage_of(args, pos).compression);
+    let encrypted = inits
+        .into_iter()
+        .chain(args.positions::<XousKernel>())
+        .any(|pos| storage_of(args, pos).encrypted);
+    Storage {
+        compression,
+        encrypted,
+    }
+}
+
+/// A kernel or init program, whose payload can be compressed and encrypted.
+trait Program: XousArgument + 'static {
+    fn compress(&mut self, algorithm: Algorithm) -> Option<Compression>;
+    fn encrypt(&mut self, kek: &KeyEncryptionKey) -> Result<Encryption, Error>;
+}
+
+impl Program for IniE {
+    fn compress(&mut self, algorithm: Algorithm) -> Option<Compression> {
+        IniE::compress(self, algorithm)
+    }
+    fn encrypt(&mut self, kek: &KeyEncryptionKey) -> Result<Encryption, Error> {
+        IniE::encrypt(self, kek)
+    }
+}
+
+impl Program for XousKernel {
+    fn compress(&mut self, algorithm: Algorithm) -> Option<Compression> {
+        XousKernel::compress(self, algorithm)
+    }
+    fn encrypt(&mut self, kek: &KeyEncryptionKey) -> Result<Encryption, Error> {
+        XousKernel::encrypt(self, kek)
+    }
+}
+
+/// Insert `program` at `pos`, compressed and encrypted the same way the
+/// builder would, with its `Cmpr` and `Encr` tags before it.
+fn insert_program<T: Program>(
+    args: &mut XousArguments,
+    pos: usize,
+    mut program: T,
+    storage: Storage,
+    key: Option<&KeyEncryptionKey>,
+    what: String,
+) -> Result<(), PatchError> {
+    let mut tags: Vec<Box<dyn XousArgument>> = vec![];
+    if let Some(cmpr) = storage.compression.and_then(|a| program.compress(a)) {
+        tags.push(Box::new(cmpr));
+    }
+    if storage.encrypted {
+        let kek = match key {
+            Some(kek) => kek,
+            None => return Err(PatchError::Encrypted(what)),
+        };
+        let encryption = program
+            .encrypt(kek)
+            .map_err(|e| PatchError::EncryptionFailed(what, e.to_string()))?;
+        tags.push(Box::new(encryption));
+    }
+    tags.push(Box::new(program));
+    args.arguments.splice(pos..pos, tags);
+    Ok(())
+}
+
+/// Replace the argument at `pos`, along with its `Cmpr` and `Encr` tags,
+/// with `program` stored the same way.
+fn replace_program<T: Program>(
+    args: &mut XousArguments,
+    pos: usize,
+    program: T,
+    key: Option<&KeyEncryptionKey>,
+    what: String,
+) -> Result<(), PatchError> {
+    let storage = storage_of(args, pos);
+    let start = encoding_start(args, pos);
+    if storage.encrypted && key.is_none() {
+        return Err(PatchError::Encrypted(what));
+    }
+    args.arguments.drain(start..=pos);
+    insert_program(args, start, program, storage, key, what)
+}
+
+/// Update the ELF hashes in the `BInf` tag, if there is one.  The kernel's
+/// hash comes first, followed by each init program's.
+fn update_elf_hashes<F: FnOnce(&mut Vec<Sha256Hash>)>(args: &mut XousArguments, update: F) {
+    if let Some(binf) = args.get_mut::<BuildInfo>() {
+        update(binf.elf_hashes_mut());
+    }
+}
+
+fn init_position(args: &XousArguments, index: usize) -> Result<usize, PatchError> {
+    let positions = args.positions::<IniE>();
+    positions
+        .get(index)
+        .copied()
+        .ok_or(PatchError::NoSuchInit(index, positions.len()))
+}
+
+impl Patch {
+    /// Apply this patch to `args`.  New and replaced programs are stored the
+    /// same way as the rest of the image, so `key` is needed to encrypt them
+    /// if the image is encrypted.  Call `XousArguments::write()` afterwards
+    /// to recompute payload offsets.
+    pub fn apply(
+        self,
+        args: &mut XousArguments,
+        key: Option<&KeyEncryptionKey>,
+    ) -> Result<(), PatchError> {
+        match self {
+            Patch::ReplaceInit(index, elf, hash) => {
+                let pos = init_position(args, index)?;
+                let what = format!("init program {}", index);
+                replace_program(args, pos, make_init(elf), key, what)?;
+                update_elf_hashes(args, |hashes| {
+                    if let Some(old) = hashes.get_mut(index + 1) {
+                        *old = hash;
+                    }
+                });
+            }
+            Patch::AddInit(elf, process, hash) => {
+                if process.name.len() > MAX_NAME_LENGTH {
+                    return Err(PatchError::NameTooLong(process.name.len()));
+                }
+                let index = args.positions::<IniE>().len();
+                let storage = storage_for_new_init(args);
+                // Init programs go after any existing ones, but before the kernel
+                let pos = match args.positions::<IniE>().last() {
+                    Some(last) => last + 1,
+                    None => match args.positions::<XousKernel>().first() {
+                        Some(&pos) => encoding_start(args, pos),
+                        None => args.arguments.len(),
+                    },
+                };
+                let what = format!("init program {}", index);
+                insert_program(args, pos, make_init(elf), storage, key, what)?;
+                match args.get_mut::<Processes>() {
+                    Some(processes) => processes.insert(index, process),
+                    None => {
+                        // Older images have no names, so leave the existing
+                        // programs unnamed
+                        let mut processes = Processes::new();
+                        processes.insert(index, process);
+                        let pos = before_payloads(args);
+                        args.arguments.insert(pos, Box::new(processes));
+                    }
+                }
+                update_elf_hashes(args, |hashes| {
+                    if index < hashes.len() {
+                        hashes.insert(index + 1, hash);
+                    }
+                });
+            }
+            Patch::RemoveInit(index) => {
+                let pos = init_position(args, index)?;
+                let start = encoding_start(args, pos);
+                args.arguments.drain(start..=pos);
+                if let Some(processes) = args.get_mut::<Processes>() {
+                    processes.remove(index);
+                }
+                update_elf_hashes(args, |hashes| {
+                    if index + 1 < hashes.len() {
+                        hashes.remove(index + 1);
+                    }
+                });
+            }
+            Patch::ReplaceKernel(kernel, hash) => {
+                let xkrn = XousKernel::new(
+                    kernel.text_offset,
+                    kernel.text_size,
+                    kernel.data_offset,
+                    kernel.data_size,
+                    kernel.bss_size,
+                    kernel.entry_point,
+                    kernel.program,
+                );
+                match args.positions::<XousKernel>().first() {
+                    Some(&pos) => replace_program(args, pos, xkrn, key, "the kernel".to_owned())?,
+                    None => args.add(xkrn),
+                }
+                update_elf_hashes(args, |hashes| {
+                    if let Some(old) = hashes.first_mut() {
+                        *old = hash;
+                    }
+                });
+            }
+            Patch::SetFlag(name, value) => {
+                let flag = BootFlags::from_name(&name).ok_or(PatchError::UnknownFlag(name))?;
+                if args.get::<Bflg>().is_none() {
+                    let pos = before_payloads(args);
+                    args.arguments.insert(pos, Box::new(Bflg::new()));
+                }
+                args.get_mut::<Bflg>().unwrap().set(flag, value);
+            }
+            Patch::AddRegion(region) => {
+                if args.get::<MemoryRegions>().is_none() {
+                    args.arguments.insert(0, Box::new(MemoryRegions::new()));
+                }
+                let regions = args.get_mut::<MemoryRegions>().unwrap();
+                if regions.regions().iter().any(|r| r.name() == region.name()) {
+                    return Err(PatchError::RegionExists(region.name()));
+                }
+                regions.add(region);
+            }
+            Patch::RemoveRegion(name) => {
+                let removed = args
+                    .get_mut::<MemoryRegions>()
+                    .and_then(|regions| regions.remove(name));
+                if removed.is_none() {
+                    return Err(PatchError::NoSuchRegion(name));
+                }
+                // Don't leave an empty MREx tag behind
+                if let Some(pos) = args.positions::<MemoryRegions>().first().copied() {
+                    if args.get::<MemoryRegions>().unwrap().is_empty() {
+                        args.arguments.remove(pos);
+                    }
+                }
+            }
+            Patch::SetRegionAttributes(name, attributes) => {
+                let region = args
+                    .get_mut::<MemoryRegions>()
+                    .and_then(|regions| regions.get_mut(name))
+                    .ok_or(PatchError::NoSuchRegion(name))?;
+                region.set_attributes(attributes);
+            }
+            Patch::SetSecurityVersion(version) => match args.get_mut::<SecurityVersion>() {
+                Some(sver) if sver.version() > version => {
+                    return Err(PatchError::Rollback(sver.version(), version));
+                }
+                Some(sver) => *sver = SecurityVersion::new(version),
+                None => {
+                    let pos = before_payloads(args);
+                    args.arguments
+                        .insert(pos, Box::new(SecurityVersion::new(version)));
+                }
+            },
+            Patch::Sign(key) => {
+                let sign: Box<dyn XousArgument> = Box::new(Signature::new(key));
+                match args.positions::<Signature>().first() {
+                    Some(&pos) => args.arguments[pos] = sign,
+                    None => args.arguments.push(sign),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::MiniElfFlags;
+    use crate::test_util::{pattern, sample_args, section, write_image};
+    use crate::validate::{validate_layout, LayoutProblem};
+
+    const HASH: Sha256Hash = [9; 32];
+
+    /// A program with one text section at `virt` holding `program`.
+    fn elf(virt: u32, program: Vec<u8>) -> MiniElf {
+        MiniElf {
+            entry_point: virt,
+            sections: vec![section(virt, program.len() as u32, MiniElfFlags::EXECUTE)],
+            program,
+        }
+    }
+
+    /// A kernel whose text is `program`, with 64 bytes of bss.
+    fn kernel_program(program: Vec<u8>) -> ProgramDescription {
+        ProgramDescription {
+            text_offset: 0xff00_0000,
+            text_size: program.len() as u32,
+            data_offset: 0xff40_0000,
+            data_size: 0,
+            bss_size: 64,
+            entry_point: 0xff00_0010,
+            program,
+        }
+    }
+
+    /// An image whose init program and kernel are compressed and encrypted.
+    fn encoded_args(kek: &KeyEncryptionKey) -> XousArguments {
+        let mut args = XousArguments::new(0x4000_0000, 0x0100_0000, make_type!("SrEx"));
+        let mut inie = make_init(elf(0x2000_0000, vec![0; 4096]));
+        args.add(inie.compress(Algorithm::Lz4).unwrap());
+        args.add(inie.encrypt(kek).unwrap());
+        args.add(inie);
+        let mut xkrn = crate::test_util::kernel();
+        args.add(xkrn.encrypt(kek).unwrap());
+        args.add(xkrn);
+        args
+    }
+
+    /// Apply `patches`, then write the image out and read it back in.
+    fn patched(
+        mut args: XousArguments,
+        patches: Vec<Patch>,
+        key: Option<&KeyEncryptionKey>,
+    ) -> XousArguments {
+        for patch in patches {
+            patch.apply(&mut args, key).unwrap();
+        }
+        assert_eq!(validate_layout(&args), vec![]);
+        XousArguments::parse_with_key(&write_image(&mut args), key).unwrap()
+    }
+
+    fn inits(args: &XousArguments) -> Vec<&IniE> {
+        args.positions::<IniE>()
+            .into_iter()
+            .map(|pos| {
+                args.arguments[pos]
+                    .as_ref()
+                    .as_any()
+                    .downcast_ref()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replace_init() {
+        let args = patched(
+            sample_args(),
+            vec![Patch::ReplaceInit(
+                0,
+                elf(0x2000_0000, pattern(64, 9)),
+                HASH,
+            )],
+            None,
+        );
+        let inits = inits(&args);
+        assert_eq!(inits.len(), 1);
+        assert_eq!(inits[0].data(), &pattern(64, 9)[..]);
+
+        let err = Patch::ReplaceInit(1, elf(0x2000_0000, vec![]), HASH)
+            .apply(&mut sample_args(), None)
+            .unwrap_err();
+        assert!(matches!(err, PatchError::NoSuchInit(1, 1)));
+    }
+
+    #[test]
+    fn add_init() {
+        let args = patched(
+            sample_args(),
+            vec![Patch::AddInit(
+                elf(0x3000_0000, pattern(32, 9)),
+                ProcessInfo::new("shell"),
+                HASH,
+            )],
+            None,
+        );
+        let inits = inits(&args);
+        assert_eq!(inits.len(), 2);
+        assert_eq!(inits[1].data(), &pattern(32, 9)[..]);
+        let names: Vec<&str> = args
+            .get::<Processes>()
+            .unwrap()
+            .processes()
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(names, ["", "shell"]);
+    }
+
+    #[test]
+    fn add_init_with_short_proc_tag() {
+        // The Proc tag doesn't have an entry for the existing program
+        let mut args = sample_args();
+        args.add(Processes::new());
+        let args = patched(
+            args,
+            vec![Patch::AddInit(
+                elf(0x3000_0000, pattern(32, 9)),
+                ProcessInfo::new("shell"),
+                HASH,
+            )],
+            None,
+        );
+        assert_eq!(inits(&args).len(), 2);
+        let names: Vec<&str> = args
+            .get::<Processes>()
+            .unwrap()
+            .processes()
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(names, ["", "shell"]);
+    }
+
+    #[test]
+    fn add_overlapping_init() {
+        let mut args = sample_args();
+        // The data section starts halfway through the text
+        let mut init = elf(0x3000_0000, pattern(32, 9));
+        init.sections
+            .push(section(0x3000_0010, 16, MiniElfFlags::WRITE));
+        init.program.extend(pattern(16, 10));
+        Patch::AddInit(init, ProcessInfo::default(), HASH)
+            .apply(&mut args, None)
+            .unwrap();
+        let problems = validate_layout(&args);
+        assert!(matches!(problems[..], [LayoutProblem::Overlap(..)]));
+    }
+
+    #[test]
+    fn added_and_replaced_programs_are_encoded() {
+        let kek = KeyEncryptionKey::new([7; 32]);
+        let args = patched(
+            encoded_args(&kek),
+            vec![
+                Patch::AddInit(
+                    elf(0x3000_0000, vec![0; 4096]),
+                    ProcessInfo::default(),
+                    HASH,
+                ),
+                Patch::ReplaceInit(0, elf(0x2000_0000, vec![1; 4096]), HASH),
+            ],
+            Some(&kek),
+        );
+        let inits = inits(&args);
+        assert_eq!(inits.len(), 2);
+        for (init, fill) in inits.iter().zip([1, 0]) {
+            assert!(init.is_compressed());
+            assert!(init.is_encrypted());
+            assert_eq!(init.data(), &[fill; 4096][..]);
+        }
+
+        // Without the key, nothing can be added or replaced
+        let mut args = encoded_args(&kek);
+        let err = Patch::AddInit(elf(0x3000_0000, vec![]), ProcessInfo::default(), HASH)
+            .apply(&mut args, None)
+            .unwrap_err();
+        assert!(matches!(err, PatchError::Encrypted(_)));
+        let err = Patch::ReplaceKernel(kernel_program(pattern(32, 9)), HASH)
+            .apply(&mut args, None)
+            .unwrap_err();
+        assert!(matches!(err, PatchError::Encrypted(_)));
+    }
+
+    #[test]
+    fn remove_init() {
+        let kek = KeyEncryptionKey::new([7; 32]);
+        let args = patched(encoded_args(&kek), vec![Patch::RemoveInit(0)], Some(&kek));
+        assert!(inits(&args).is_empty());
+        // The init program's Cmpr and Encr tags went with it
+        assert!(args.positions::<Compression>().is_empty());
+        assert_eq!(args.positions::<Encryption>().len(), 1);
+    }
+
+    #[test]
+    fn replace_kernel() {
+        let kernel = kernel_program(pattern(32, 9));
+        let args = patched(
+            sample_args(),
+            vec![Patch::ReplaceKernel(kernel, HASH)],
+            None,
+        );
+        let xkrn = args.get::<XousKernel>().unwrap();
+        assert_eq!(xkrn.entrypoint(), 0xff00_0010);
+        assert_eq!(xkrn.bss_size(), 64);
+        assert_eq!(xkrn.program(), &pattern(32, 9)[..]);
+    }
+
+    #[test]
+    fn set_flag() {
+        let args = patched(
+            sample_args(),
+            vec![
+                Patch::SetFlag("no_copy".to_owned(), true),
+                Patch::SetFlag("debug".to_owned(), true),
+                Patch::SetFlag("debug".to_owned(), false),
+            ],
+            None,
+        );
+        let bflg = args.get::<Bflg>().unwrap();
+        assert_eq!(bflg.flags(), BootFlags::NO_COPY);
+
+        let err = Patch::SetFlag("fast".to_owned(), true)
+            .apply(&mut sample_args(), None)
+            .unwrap_err();
+        assert!(matches!(err, PatchError::UnknownFlag(_)));
+    }
+
+    #[test]
+    fn add_and_remove_regions() {
+        let csrs = make_type!("CSRs");
+        let sram = make_type!("SRAM");
+        let args = patched(
+            sample_args(),
+            vec![Patch::AddRegion(MemoryRegion::new(
+                0x1000_0000,
+                0x1000,
+                sram,
+            ))],
+            None,
+        );
+        let names: Vec<_> = args
+            .get::<MemoryRegions>()
+            .unwrap()
+            .regions()
+            .iter()
+            .map(MemoryRegion::name)
+            .collect();
+        assert_eq!(names, [csrs, sram]);
+
+        let err = Patch::AddRegion(MemoryRegion::new(0x1000_0000, 0x1000, csrs))
+            .apply(&mut sample_args(), None)
+            .unwrap_err();
+        assert!(matches!(err, PatchError::RegionExists(n) if n == csrs));
+
+        // Removing the last region removes the tag
+        let args = patched(sample_args(), vec![Patch::RemoveRegion(csrs)], None);
+        assert!(args.get::<MemoryRegions>().is_none());
+
+        let err = Patch::RemoveRegion(sram)
+            .apply(&mut sample_args(), None)
+            .unwrap_err();
+        assert!(matches!(err, PatchError::NoSuchRegion(n) if n == sram));
+    }
+
+    #[test]
+    fn set_region_attributes() {
+        let csrs = make_type!("CSRs");
+        let attributes = RegionAttributes::READ | RegionAttributes::DEVICE;
+        let args = patched(
+            sample_args(),
+            vec![Patch::SetRegionAttributes(csrs, attributes)],
+            None,
+        );
+        let regions = args.get::<MemoryRegions>().unwrap();
+        assert_eq!(regions.regions()[0].attributes(), attributes);
+
+        let sram = make_type!("SRAM");
+        let err = Patch::SetRegionAttributes(sram, attributes)
+            .apply(&mut sample_args(), None)
+            .unwrap_err();
+        assert!(matches!(err, PatchError::NoSuchRegion(n) if n == sram));
+    }
+
+    #[test]
+    fn sign() {
+        use crate::signing::{verify_signature, SignatureStatus};
+
+        let mut args = sample_args();
+        args.add(Signature::new(SigningKey::from_bytes(&[1; 32])));
+        let image = write_image(&mut args);
+
+        let key = SigningKey::from_bytes(&[2; 32]);
+        let mut args = XousArguments::parse(&image).unwrap();
+        Patch::SetFlag("debug".to_owned(), true)
+            .apply(&mut args, None)
+            .unwrap();
+        Patch::Sign(key.clone()).apply(&mut args, None).unwrap();
+        let image = write_image(&mut args);
+
+        // The old signature is replaced rather than added to
+        let args = XousArguments::parse(&image).unwrap();
+        assert_eq!(args.positions::<Signature>().len(), 1);
+        let status = verify_signature(&image, &key.verifying_key()).unwrap();
+        assert_eq!(status, SignatureStatus::Valid);
+    }
+
+    #[test]
+    fn set_security_version() {
+        let args = patched(sample_args(), vec![Patch::SetSecurityVersion(3)], None);
+        assert_eq!(args.get::<SecurityVersion>().unwrap().version(), 3);
+
+        let args = patched(args, vec![Patch::SetSecurityVersion(4)], None);
+        assert_eq!(args.get::<SecurityVersion>().unwrap().version(), 4);
+        assert_eq!(args.positions::<SecurityVersion>().len(), 1);
+
+        let mut args = args;
+        let err = Patch::SetSecurityVersion(2)
+            .apply(&mut args, None)
+            .unwrap_err();
+        assert!(matches!(err, PatchError::Rollback(4, 2)));
+    }
+
+    #[test]
+    fn elf_hashes_follow_programs() {
+        let hashes = |patches| {
+            let mut args = sample_args();
+            args.add(BuildInfo::new(0, "0.1", "", vec![[0; 32], [1; 32]]));
+            let args = patched(args, patches, None);
+            args.get::<BuildInfo>().unwrap().elf_hashes().to_vec()
+        };
+
+        let added = |hash| {
+            Patch::AddInit(
+                elf(0x3000_0000, pattern(32, 9)),
+                ProcessInfo::default(),
+                hash,
+            )
+        };
+        assert_eq!(
+            hashes(vec![added([2; 32]), added([3; 32])]),
+            [[0; 32], [1; 32], [2; 32], [3; 32]]
+        );
+        assert_eq!(
+            hashes(vec![
+                Patch::ReplaceKernel(kernel_program(pattern(32, 9)), [4; 32]),
+                Patch::ReplaceInit(0, elf(0x2000_0000, pattern(32, 9)), [5; 32]),
+            ]),
+            [[4; 32], [5; 32]]
+        );
+        assert_eq!(hashes(vec![Patch::RemoveInit(0)]), [[0; 32]]);
+    }
+
+    #[test]
+    fn name_too_long() {
+        let mut args = sample_args();
+        let process = ProcessInfo::new(&"x".repeat(MAX_NAME_LENGTH + 1));
+        let err = Patch::AddInit(elf(0x3000_0000, pattern(32, 9)), process, HASH)
+            .apply(&mut args, None)
+            .unwrap_err();
+        assert!(matches!(err, PatchError::NameTooLong(len) if len == MAX_NAME_LENGTH + 1));
+        assert_eq!(args.positions::<IniE>().len(), 1);
+    }
+}
@@ -0,0 +1,145 @@
+use sha2::{Digest as _, Sha256};
+
+use crate::tags::dgst::Digest;
+use crate::xous_arguments::{tag_crc, ParseError, TagIter, XousArgumentCode};
+
+/// A SHA-256 hash
+pub type Sha256Hash = [u8; 32];
+
+/// Returns `true` for tags whose contents are calculated over the finished
+/// image.  Their CRC16 and contents are treated as zero while hashing, so
+/// they can be filled in afterwards.
+pub fn is_seal(code: XousArgumentCode) -> bool {
+    code == make_type!("Dgst") || code == make_type!("Sign")
+}
+
+/// A copy of `image` with the CRC16 and contents of every seal tag zeroed.
+pub fn blank_seals(image: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let mut blanked = image.to_vec();
+    for tag in TagIter::new(image) {
+        let tag = tag?;
+        if is_seal(tag.code) {
+            let start = tag.offset + 8;
+            blanked[tag.offset + 4..tag.offset + 6].fill(0);
+            blanked[start..start + tag.data.len()].fill(0);
+        }
+    }
+    Ok(blanked)
+}
+
+/// SHA-256 of `image` with every seal tag blanked.
+pub fn image_digest(image: &[u8]) -> Result<Sha256Hash, ParseError> {
+    let blanked = blank_seals(image)?;
+    Ok(Sha256::digest(&blanked).into())
+}
+
+/// Offset of the `Dgst` tag, if there is one, after checking its length.
+fn find_digest(image: &[u8]) -> Result<Option<usize>, ParseError> {
+    for tag in TagIter::new(image) {
+        let tag = tag?;
+        if tag.code == make_type!("Dgst") {
+            Digest::decode(&tag)?;
+            return Ok(Some(tag.offset));
+        }
+    }
+    Ok(None)
+}
+
+/// Fill in the `Dgst` tag of a serialized image and update its CRC16.
+/// Returns the digest, or `None` if the image has no `Dgst` tag.
+pub fn seal(image: &mut [u8]) -> Result<Option<Sha256Hash>, ParseError> {
+    let offset = match find_digest(image)? {
+        Some(offset) => offset,
+        None => return Ok(None),
+    };
+    let digest = image_digest(image)?;
+
+    // Skip the algorithm word
+    let data = offset + 8;
+    image[data + 4..data + 36].copy_from_slice(&digest);
+    let crc = tag_crc(&image[data..data + 36]);
+    image[offset + 4..offset + 6].copy_from_slice(&crc.to_le_bytes());
+    Ok(Some(digest))
+}
+
+/// Compare the `Dgst` tag against the image contents.  Returns the stored
+/// and calculated digests, or `None` if the image has no `Dgst` tag.
+pub fn check_digest(image: &[u8]) -> Result<Option<(Sha256Hash, Sha256Hash)>, ParseError> {
+    let offset = match find_digest(image)? {
+        Some(offset) => offset,
+        None => return Ok(None),
+    };
+    let mut stored: Sha256Hash = [0u8; 32];
+    stored.copy_from_slice(&image[offset + 12..offset + 44]);
+    Ok(Some((stored, image_digest(image)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample_args, write_image};
+    use crate::xous_arguments::XousArguments;
+
+    fn sealed_image() -> Vec<u8> {
+        let mut args = sample_args();
+        args.add(Digest::new());
+        write_image(&mut args)
+    }
+
+    fn digest_offset(image: &[u8]) -> usize {
+        TagIter::new(image)
+            .map(Result::unwrap)
+            .find(|tag| tag.code == make_type!("Dgst"))
+            .unwrap()
+            .offset
+    }
+
+    #[test]
+    fn digest_covers_blanked_image() {
+        let image = sealed_image();
+        let offset = digest_offset(&image);
+
+        let mut blanked = image.clone();
+        blanked[offset + 4..offset + 6].fill(0);
+        blanked[offset + 8..offset + 44].fill(0);
+        assert_eq!(blank_seals(&image).unwrap(), blanked);
+
+        let expected: Sha256Hash = Sha256::digest(&blanked).into();
+        assert_eq!(image_digest(&image).unwrap(), expected);
+        let args = XousArguments::parse(&image).unwrap();
+        assert_eq!(args.get::<Digest>().unwrap().digest(), &expected);
+        assert_eq!(check_digest(&image).unwrap(), Some((expected, expected)));
+    }
+
+    #[test]
+    fn seal_fills_in_digest() {
+        let image = sealed_image();
+        let offset = digest_offset(&image);
+
+        // An empty digest, as written before sealing
+        let mut unsealed = image.clone();
+        unsealed[offset + 12..offset + 44].fill(0);
+        let crc = tag_crc(&unsealed[offset + 8..offset + 44]);
+        unsealed[offset + 4..offset + 6].copy_from_slice(&crc.to_le_bytes());
+
+        let digest = seal(&mut unsealed).unwrap();
+        assert_eq!(unsealed, image);
+        assert_eq!(digest, Some(image_digest(&image).unwrap()));
+    }
+
+    #[test]
+    fn changed_payload() {
+        let mut image = sealed_image();
+        let last = image.len() - 1;
+        image[last] ^= 1;
+        let (stored, calculated) = check_digest(&image).unwrap().unwrap();
+        assert_ne!(stored, calculated);
+    }
+
+    #[test]
+    fn unsealed_image() {
+        let mut image = write_image(&mut sample_args());
+        assert_eq!(check_digest(&image).unwrap(), None);
+        assert_eq!(seal(&mut image).unwrap(), None);
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+
+use crate::error::Error;
+use crate::seal::blank_seals;
+use crate::tags::sign::Signature;
+use crate::utils::hex;
+use crate::xous_arguments::{tag_crc, ParseError, TagIter};
+
+/// The contents of a key file.
+pub(crate) enum KeyFile {
+    /// PKCS#8 or SubjectPublicKeyInfo PEM text
+    Pem(String),
+
+    /// The 32 key bytes, read either directly or as hex
+    Raw([u8; 32]),
+}
+
+/// Read a key file, which may be PEM, 32 raw bytes, or 64 hex digits.
+pub(crate) fn read_key(path: &Path) -> Result<KeyFile, Error> {
+    let contents = fs::read(path).map_err(|e| Error::Io(Some(path.to_path_buf()), e))?;
+    let invalid = || {
+        Error::Key(
+            path.to_path_buf(),
+            "not a PEM, raw or hex key".to_owned(),
+        )
+    };
+
+    if contents.len() == 32 {
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&contents);
+        return Ok(KeyFile::Raw(key));
+    }
+    let text = String::from_utf8(contents).map_err(|_| invalid())?;
+    if text.trim_start().starts_with("-----BEGIN") {
+        return Ok(KeyFile::Pem(text));
+    }
+    let text = text.trim();
+    if text.len() != 64 {
+        return Err(invalid());
+    }
+    let mut key = [0u8; 32];
+    for (idx, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[idx * 2..idx * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(KeyFile::Raw(key))
+}
+
+/// Load an Ed25519 private key, such as one made by
+/// `openssl genpkey -algorithm ed25519`.
+pub fn load_signing_key<P: AsRef<Path>>(path: P) -> Result<SigningKey, Error> {
+    let path = path.as_ref();
+    match read_key(path)? {
+        KeyFile::Pem(pem) => SigningKey::from_pkcs8_pem(&pem)
+            .map_err(|e| Error::Key(path.to_path_buf(), e.to_string())),
+        KeyFile::Raw(raw) => Ok(SigningKey::from_bytes(&raw)),
+    }
+}
+
+/// Load an Ed25519 public key, such as one made by `openssl pkey -pubout`.
+pub fn load_verifying_key<P: AsRef<Path>>(path: P) -> Result<VerifyingKey, Error> {
+    let path = path.as_ref();
+    match read_key(path)? {
+        KeyFile::Pem(pem) => VerifyingKey::from_public_key_pem(&pem)
+            .map_err(|e| Error::Key(path.to_path_buf(), e.to_string())),
+        KeyFile::Raw(raw) => VerifyingKey::from_bytes(&raw)
+            .map_err(|e| Error::Key(path.to_path_buf(), e.to_string())),
+    }
+}
+
+/// Offset of the `Sign` tag and its decoded contents, if there is one.
+fn find_signature(image: &[u8]) -> Result<Option<(usize, Signature)>, ParseError> {
+    for tag in TagIter::new(image) {
+        let tag = tag?;
+        if tag.code == make_type!("Sign") {
+            return Ok(Some((tag.offset, Signature::decode(&tag)?)));
+        }
+    }
+    Ok(None)
+}
+
+/// Sign a serialized image with `key`, filling in its `Sign` tag and
+/// updating its CRC16.  Returns the signature, or `None` if the image has
+/// no `Sign` tag.
+pub fn sign(image: &mut [u8], key: &SigningKey) -> Result<Option<[u8; 64]>, Error> {
+    let offset = match find_signature(image)? {
+        Some((offset, _)) => offset,
+        None => return Ok(None),
+    };
+    let signature = key.sign(&blank_seals(image)?).to_bytes();
+
+    let data = offset + 8;
+    image[data + 4..data + 36].copy_from_slice(key.verifying_key().as_bytes());
+    image[data + 36..data + 100].copy_from_slice(&signature);
+    let crc = tag_crc(&image[data..data + 100]);
+    image[offset + 4..offset + 6].copy_from_slice(&crc.to_le_bytes());
+    Ok(Some(signature))
+}
+
+/// The result of checking an image's signature against a public key.
+#[derive(Debug, PartialEq)]
+pub enum SignatureStatus {
+    /// The image has no `Sign` tag
+    Unsigned,
+
+    /// The image was signed by the key
+    Valid,
+
+    /// The image was signed by a different key
+    WrongKey([u8; 32] /* key in the image */),
+
+    /// The signature doesn't match the image contents
+    Invalid,
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureStatus::Unsigned => write!(f, "image is not signed"),
+            SignatureStatus::Valid => write!(f, "signature is valid"),
+            SignatureStatus::WrongKey(k) => {
+                write!(f, "image was signed by a different key: {}", hex(k))
+            }
+            SignatureStatus::Invalid => write!(f, "signature does not match the image"),
+        }
+    }
+}
+
+/// Check the signature of `image` against `key`.
+pub fn verify_signature(image: &[u8], key: &VerifyingKey) -> Result<SignatureStatus, ParseError> {
+    let signature = match find_signature(image)? {
+        Some((_, signature)) => signature,
+        None => return Ok(SignatureStatus::Unsigned),
+    };
+    if signature.public_key() != key.as_bytes() {
+        return Ok(SignatureStatus::WrongKey(*signature.public_key()));
+    }
+    let blanked = blank_seals(image)?;
+    let signature = ed25519_dalek::Signature::from_bytes(signature.signature());
+    match key.verify_strict(&blanked, &signature) {
+        Ok(()) => Ok(SignatureStatus::Valid),
+        Err(_) => Ok(SignatureStatus::Invalid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample_args, write_image, TempDir};
+    use crate::verify::verify_image;
+
+    fn signed_image(key: &SigningKey) -> Vec<u8> {
+        let mut args = sample_args();
+        args.add(Signature::new(key.clone()));
+        write_image(&mut args)
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let image = signed_image(&key);
+        let status = verify_signature(&image, &key.verifying_key()).unwrap();
+        assert_eq!(status, SignatureStatus::Valid);
+        assert!(verify_image(&image).is_empty());
+
+        let other = SigningKey::from_bytes(&[2; 32]);
+        let status = verify_signature(&image, &other.verifying_key()).unwrap();
+        assert_eq!(status, SignatureStatus::WrongKey(*key.verifying_key().as_bytes()));
+
+        let unsigned = write_image(&mut sample_args());
+        let status = verify_signature(&unsigned, &key.verifying_key()).unwrap();
+        assert_eq!(status, SignatureStatus::Unsigned);
+    }
+
+    #[test]
+    fn tampered() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut image = signed_image(&key);
+        // The last byte belongs to the init program's payload
+        *image.last_mut().unwrap() ^= 1;
+        let status = verify_signature(&image, &key.verifying_key()).unwrap();
+        assert_eq!(status, SignatureStatus::Invalid);
+        assert!(!verify_image(&image).is_empty());
+    }
+
+    #[test]
+    fn key_files() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let dir = TempDir::new();
+        let raw = dir.write("key.raw", key.as_bytes());
+        let hex = dir.write("key.hex", format!("{}\n", hex(key.as_bytes())).as_bytes());
+        for path in [raw, hex] {
+            assert_eq!(load_signing_key(&path).unwrap().as_bytes(), key.as_bytes());
+        }
+
+        let bad = dir.write("key.bad", b"not a key");
+        assert!(matches!(load_signing_key(bad), Err(Error::Key(..))));
+    }
+}
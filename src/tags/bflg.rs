@@ -1,62 +1,153 @@
-use crate::xous_arguments::{XousArgument, XousArgumentCode, XousSize};
+use crate::xous_arguments::{ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
+use bitflags::bitflags;
 use std::fmt;
 use std::io;
 
-#[derive(Debug)]
-pub struct Bflg {
+/// Defines `BootFlags` along with the name of each flag, so that a new flag
+/// only has to be added in one place.
+macro_rules! boot_flags {
+    ($($(#[$($doc:tt)*])* $flag:ident = $bit:expr, $name:expr;)*) => {
+        bitflags! {
+            #[derive(Default)]
+            pub struct BootFlags: u32 {
+                $($(#[$($doc)*])* const $flag = 1 << $bit;)*
+            }
+        }
+
+        impl BootFlags {
+            /// Every named flag, in bit order, along with the name used on
+            /// the command line and in manifests.
+            pub const NAMES: &'static [(&'static str, BootFlags)] =
+                &[$(($name, BootFlags::$flag)),*];
+        }
+    };
+}
+
+boot_flags! {
     /// Disable copying data
-    no_copy_: bool,
+    NO_COPY = 0, "no_copy";
 
     /// Addresses are all absolute
-    absolute_: bool,
+    ABSOLUTE = 1, "absolute";
 
     /// Set the SUM bit in $mstatus to allow Supervisor to access User memory
-    debug_: bool,
+    DEBUG = 2, "debug";
+}
+
+impl BootFlags {
+    /// Look up a single flag by name.
+    pub fn from_name(name: &str) -> Option<BootFlags> {
+        BootFlags::NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, flag)| *flag)
+    }
+
+    /// Parse a comma-separated list of flag names, such as
+    /// `no_copy,absolute`.  Returns the first unknown name on failure.
+    pub fn parse_list(list: &str) -> Result<BootFlags, String> {
+        let mut flags = BootFlags::empty();
+        for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            flags |= BootFlags::from_name(name).ok_or_else(|| name.to_owned())?;
+        }
+        Ok(flags)
+    }
+
+    /// Names of every flag that is set.
+    pub fn names(self) -> Vec<&'static str> {
+        BootFlags::NAMES
+            .iter()
+            .filter(|(_, flag)| self.contains(*flag))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Bflg {
+    flags: BootFlags,
+
+    /// Bits set in an image that don't correspond to a named flag, which
+    /// are kept so that they survive being written back out
+    unknown: u32,
+}
+
+impl Default for Bflg {
+    fn default() -> Bflg {
+        Bflg::new()
+    }
 }
 
 impl fmt::Display for Bflg {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "    Bflg:")?;
-        if self.no_copy_ {
-            write!(f, " +NO_COPY")?;
-        } else {
-            write!(f, " -no_copy")?;
-        }
-        if self.absolute_ {
-            write!(f, " +ABSOLUTE")?;
-        } else {
-            write!(f, " -absolute")?;
+        for (name, flag) in BootFlags::NAMES {
+            if self.flags.contains(*flag) {
+                write!(f, " +{}", name.to_uppercase())?;
+            } else {
+                write!(f, " -{}", name)?;
+            }
         }
-
-        if self.debug_ {
-            write!(f, " +DEBUG")?;
-        } else {
-            write!(f, " -debug")?;
+        if self.unknown != 0 {
+            write!(f, " +unknown({:08x})", self.unknown)?;
         }
-        writeln!(f, "")
+        writeln!(f)
     }
 }
 
 impl Bflg {
     pub fn new() -> Bflg {
-        Bflg {
-            no_copy_: false,
-            absolute_: false,
-            debug_: false,
-        }
+        Bflg::from_flags(BootFlags::empty())
+    }
+
+    pub fn from_flags(flags: BootFlags) -> Bflg {
+        Bflg { flags, unknown: 0 }
     }
+
+    /// Disable copying data
     pub fn no_copy(mut self) -> Bflg {
-        self.no_copy_ = true;
+        self.set(BootFlags::NO_COPY, true);
         self
     }
+
+    /// Addresses are all absolute
     pub fn absolute(mut self) -> Bflg {
-        self.absolute_ = true;
+        self.set(BootFlags::ABSOLUTE, true);
         self
     }
+
+    /// Set the SUM bit in $mstatus to allow Supervisor to access User memory
     pub fn debug(mut self) -> Bflg {
-        self.debug_ = true;
+        self.set(BootFlags::DEBUG, true);
         self
     }
+
+    pub fn flags(&self) -> BootFlags {
+        self.flags
+    }
+
+    pub fn set(&mut self, flag: BootFlags, value: bool) {
+        self.flags.set(flag, value);
+    }
+
+    pub fn contains(&self, flag: BootFlags) -> bool {
+        self.flags.contains(flag)
+    }
+
+    /// Bits that were set in the image but aren't named flags.
+    pub fn unknown_bits(&self) -> u32 {
+        self.unknown
+    }
+
+    /// Decode a `Bflg` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<Bflg, ParseError> {
+        tag.expect_length(4)?;
+        let val = tag.word(0);
+        Ok(Bflg {
+            flags: BootFlags::from_bits_truncate(val),
+            unknown: val & !BootFlags::all().bits(),
+        })
+    }
 }
 
 impl XousArgument for Bflg {
@@ -67,18 +158,38 @@ impl XousArgument for Bflg {
         4
     }
     fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
-        let mut written = 0;
-        let mut val = 0u32;
-        if self.no_copy_ {
-            val |= 1 << 0;
-        }
-        if self.absolute_ {
-            val |= 1 << 1;
-        }
-        if self.debug_ {
-            val |= 1 << 2;
-        }
-        written += output.write(&val.to_le_bytes())?;
-        Ok(written)
+        let val = self.flags.bits() | self.unknown;
+        output.write(&val.to_le_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builders() {
+        let bflg = Bflg::new().debug().no_copy();
+        assert_eq!(bflg.flags(), BootFlags::DEBUG | BootFlags::NO_COPY);
+        assert!(!bflg.contains(BootFlags::ABSOLUTE));
+        assert!(Bflg::new().absolute().contains(BootFlags::ABSOLUTE));
+    }
+
+    #[test]
+    fn unknown_bits_survive() {
+        let data = 0x8000_0005u32.to_le_bytes();
+        let bflg = Bflg::decode(&Tag {
+            offset: 0,
+            code: make_type!("Bflg"),
+            crc: 0,
+            data: &data,
+        })
+        .unwrap();
+        assert_eq!(bflg.flags(), BootFlags::NO_COPY | BootFlags::DEBUG);
+        assert_eq!(bflg.unknown_bits(), 0x8000_0000);
+
+        let mut encoded = vec![];
+        bflg.serialize(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
     }
 }
@@ -0,0 +1,253 @@
+use crate::seal::Sha256Hash;
+use crate::utils::hex;
+use crate::xous_arguments::{ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// Records how and from what an image was built, so that an image found on
+/// a device can be traced back to its sources.
+///
+/// The contents are the build timestamp as a 64-bit count of seconds since
+/// the Unix epoch, the number of ELF hashes, the tool version and revision
+/// as length-prefixed strings padded to a multiple of four bytes, and then
+/// a SHA-256 of each input ELF file: the kernel first, then every init
+/// program in load order.
+///
+/// `patch-image` keeps the ELF hashes up to date as programs are replaced,
+/// added and removed, but leaves the rest as it was when the image was
+/// built.
+#[derive(Debug, Default)]
+pub struct BuildInfo {
+    timestamp: u64,
+    tool_version: String,
+    revision: String,
+    elf_hashes: Vec<Sha256Hash>,
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "    BInf: built by xous-tools {}", self.tool_version)?;
+        if self.timestamp != 0 {
+            write!(f, " at {}", format_timestamp(self.timestamp))?;
+        }
+        if !self.revision.is_empty() {
+            write!(f, " from {}", self.revision)?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Format seconds since the Unix epoch as a UTC date and time.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Convert days since the epoch into a proleptic Gregorian date
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn padded_len(s: &str) -> usize {
+    (s.len() + 3) & !3
+}
+
+fn write_string(output: &mut dyn io::Write, s: &str) -> io::Result<usize> {
+    let mut written = output.write(&(s.len() as u32).to_le_bytes())?;
+    written += output.write(s.as_bytes())?;
+    written += output.write(&[0u8; 3][..padded_len(s) - s.len()])?;
+    Ok(written)
+}
+
+/// Read a length-prefixed string from `tag` at byte offset `*pos`.
+fn read_string(tag: &Tag, pos: &mut usize) -> Result<String, ParseError> {
+    let bad_length = || ParseError::BadTagLength(tag.offset, tag.code, tag.data.len());
+    if *pos + 4 > tag.data.len() {
+        return Err(bad_length());
+    }
+    let len = tag.word(*pos / 4) as usize;
+    let start = *pos + 4;
+    let bytes = tag.data.get(start..start + len).ok_or_else(bad_length)?;
+    *pos = start + ((len + 3) & !3);
+    Ok(String::from_utf8_lossy(bytes).to_string())
+}
+
+impl BuildInfo {
+    pub fn new(
+        timestamp: u64,
+        tool_version: &str,
+        revision: &str,
+        elf_hashes: Vec<Sha256Hash>,
+    ) -> BuildInfo {
+        BuildInfo {
+            timestamp,
+            tool_version: tool_version.to_owned(),
+            revision: revision.to_owned(),
+            elf_hashes,
+        }
+    }
+
+    /// Seconds since the Unix epoch at which the image was built.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Version of xous-tools that built the image.
+    pub fn tool_version(&self) -> &str {
+        &self.tool_version
+    }
+
+    /// Source revision given when the image was built, which may be empty.
+    pub fn revision(&self) -> &str {
+        &self.revision
+    }
+
+    /// SHA-256 of the kernel ELF, followed by each init program's ELF.
+    pub fn elf_hashes(&self) -> &[Sha256Hash] {
+        &self.elf_hashes
+    }
+
+    /// The ELF hashes, for updating when a program is replaced.
+    pub fn elf_hashes_mut(&mut self) -> &mut Vec<Sha256Hash> {
+        &mut self.elf_hashes
+    }
+
+    /// Every ELF hash as a lowercase hex string.
+    pub fn elf_hashes_hex(&self) -> Vec<String> {
+        self.elf_hashes.iter().map(|hash| hex(hash)).collect()
+    }
+
+    /// Decode a `BInf` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<BuildInfo, ParseError> {
+        if tag.data.len() < 12 {
+            return Err(ParseError::BadTagLength(
+                tag.offset,
+                tag.code,
+                tag.data.len(),
+            ));
+        }
+        let timestamp = tag.word(0) as u64 | (tag.word(1) as u64) << 32;
+        let hash_count = tag.word(2) as usize;
+        let mut pos = 12;
+        let tool_version = read_string(tag, &mut pos)?;
+        let revision = read_string(tag, &mut pos)?;
+        if tag.data.len() - pos != hash_count.saturating_mul(32) {
+            return Err(ParseError::BadTagLength(
+                tag.offset,
+                tag.code,
+                tag.data.len(),
+            ));
+        }
+        let elf_hashes = tag.data[pos..]
+            .chunks(32)
+            .map(|chunk| {
+                let mut hash = [0u8; 32];
+                hash.copy_from_slice(chunk);
+                hash
+            })
+            .collect();
+        Ok(BuildInfo {
+            timestamp,
+            tool_version,
+            revision,
+            elf_hashes,
+        })
+    }
+}
+
+impl XousArgument for BuildInfo {
+    fn code(&self) -> XousArgumentCode {
+        make_type!("BInf")
+    }
+    fn length(&self) -> XousSize {
+        (12 + 4
+            + padded_len(&self.tool_version)
+            + 4
+            + padded_len(&self.revision)
+            + self.elf_hashes.len() * 32) as XousSize
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = 0;
+        written += output.write(&(self.timestamp as u32).to_le_bytes())?;
+        written += output.write(&((self.timestamp >> 32) as u32).to_le_bytes())?;
+        written += output.write(&(self.elf_hashes.len() as u32).to_le_bytes())?;
+        written += write_string(output, &self.tool_version)?;
+        written += write_string(output, &self.revision)?;
+        for hash in &self.elf_hashes {
+            written += output.write(hash)?;
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode() {
+        // "0.9.0" needs three bytes of padding and "abcd" needs none
+        let info = BuildInfo::new(0x1_2345_6789, "0.9.0", "abcd", vec![[1; 32], [2; 32]]);
+        let mut encoded = vec![];
+        let written = info.serialize(&mut encoded).unwrap();
+        assert_eq!(written, encoded.len());
+        assert_eq!(encoded.len(), info.length() as usize);
+
+        let decoded = BuildInfo::decode(&Tag {
+            offset: 0,
+            code: make_type!("BInf"),
+            crc: 0,
+            data: &encoded,
+        })
+        .unwrap();
+        assert_eq!(decoded.timestamp(), 0x1_2345_6789);
+        assert_eq!(decoded.tool_version(), "0.9.0");
+        assert_eq!(decoded.revision(), "abcd");
+        assert_eq!(decoded.elf_hashes(), &[[1; 32], [2; 32]]);
+    }
+
+    #[test]
+    fn truncated_hashes() {
+        let mut encoded = vec![];
+        BuildInfo::new(0, "0.9.0", "", vec![[1; 32]])
+            .serialize(&mut encoded)
+            .unwrap();
+        encoded.truncate(encoded.len() - 4);
+        match BuildInfo::decode(&Tag {
+            offset: 0,
+            code: make_type!("BInf"),
+            crc: 0,
+            data: &encoded,
+        }) {
+            Err(ParseError::BadTagLength(0, _, _)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(1_709_210_096), "2024-02-29 12:34:56 UTC");
+        assert_eq!(format_timestamp(1_709_251_199), "2024-02-29 23:59:59 UTC");
+        assert_eq!(format_timestamp(1_709_251_200), "2024-03-01 00:00:00 UTC");
+        // Past the end of a signed 32-bit time_t
+        assert_eq!(format_timestamp(2_147_483_648), "2038-01-19 03:14:08 UTC");
+        assert_eq!(format_timestamp(4_102_444_800), "2100-01-01 00:00:00 UTC");
+    }
+}
@@ -0,0 +1,170 @@
+use crate::xous_arguments::{ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// Boot parameters for the kernel, as `key=value` pairs.
+///
+/// The contents are the number of parameters, followed by each parameter
+/// as a NUL-terminated `key=value` string.  The whole thing is padded with
+/// NULs to a multiple of four bytes.
+#[derive(Clone, Debug, Default)]
+pub struct BootParams {
+    params: Vec<(String, String)>,
+}
+
+impl fmt::Display for BootParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "    BPar:")?;
+        for (key, value) in &self.params {
+            write!(f, " {}={}", key, value)?;
+        }
+        writeln!(f)
+    }
+}
+
+impl BootParams {
+    pub fn new() -> BootParams {
+        BootParams::default()
+    }
+
+    /// Set a parameter, replacing any existing value for `key`.
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.params.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_owned(),
+            None => self.params.push((key.to_owned(), value.to_owned())),
+        }
+    }
+
+    /// Look up the value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every parameter, in the order they were set.
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    fn strings_len(&self) -> usize {
+        self.params
+            .iter()
+            .map(|(k, v)| k.len() + 1 + v.len() + 1)
+            .sum()
+    }
+
+    /// Decode a `BPar` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<BootParams, ParseError> {
+        let bad_length = || ParseError::BadTagLength(tag.offset, tag.code, tag.data.len());
+        if tag.data.len() < 4 {
+            return Err(bad_length());
+        }
+        let count = tag.word(0) as usize;
+        let mut strings = tag.data[4..].split(|&b| b == 0);
+        let mut params = vec![];
+        for _ in 0..count {
+            let param = strings.next().ok_or_else(bad_length)?;
+            let param = String::from_utf8_lossy(param);
+            let mut parts = param.splitn(2, '=');
+            let key = parts.next().unwrap_or_default();
+            let value = parts.next().ok_or_else(bad_length)?;
+            params.push((key.to_owned(), value.to_owned()));
+        }
+        Ok(BootParams { params })
+    }
+}
+
+impl XousArgument for BootParams {
+    fn code(&self) -> XousArgumentCode {
+        make_type!("BPar")
+    }
+    fn length(&self) -> XousSize {
+        (4 + ((self.strings_len() + 3) & !3)) as XousSize
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = output.write(&(self.params.len() as u32).to_le_bytes())?;
+        for (key, value) in &self.params {
+            written += output.write(key.as_bytes())?;
+            written += output.write(b"=")?;
+            written += output.write(value.as_bytes())?;
+            written += output.write(&[0])?;
+        }
+        let len = self.strings_len();
+        written += output.write(&[0u8; 3][..((len + 3) & !3) - len])?;
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &[u8]) -> Result<BootParams, ParseError> {
+        BootParams::decode(&Tag {
+            offset: 0,
+            code: make_type!("BPar"),
+            crc: 0,
+            data,
+        })
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let mut bpar = BootParams::new();
+        bpar.set("console", "uart0");
+        bpar.set("cmdline", "root=flash quiet");
+        bpar.set("console", "uart1");
+
+        // "console=uart1\0cmdline=root=flash quiet\0" is 39 bytes, so it
+        // needs one byte of padding
+        let mut encoded = vec![];
+        let written = bpar.serialize(&mut encoded).unwrap();
+        assert_eq!(written, encoded.len());
+        assert_eq!(encoded.len(), bpar.length() as usize);
+        assert_eq!(encoded.len(), 4 + 40);
+        assert_eq!(&encoded[..4], &2u32.to_le_bytes());
+        assert_eq!(encoded[43], 0);
+
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(
+            decoded.params(),
+            &[
+                ("console".to_owned(), "uart1".to_owned()),
+                ("cmdline".to_owned(), "root=flash quiet".to_owned()),
+            ]
+        );
+        assert_eq!(decoded.get("cmdline"), Some("root=flash quiet"));
+        assert_eq!(decoded.get("baud"), None);
+    }
+
+    #[test]
+    fn empty() {
+        let mut encoded = vec![];
+        BootParams::new().serialize(&mut encoded).unwrap();
+        assert_eq!(encoded, [0, 0, 0, 0]);
+        assert!(decode(&encoded).unwrap().is_empty());
+    }
+
+    #[test]
+    fn too_many_params() {
+        let mut data = 3u32.to_le_bytes().to_vec();
+        data.extend_from_slice(b"a=1\0b=2\0");
+        match decode(&data) {
+            Err(ParseError::BadTagLength(0, _, 12)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_value() {
+        let mut data = 1u32.to_le_bytes().to_vec();
+        data.extend_from_slice(b"abc\0");
+        assert!(decode(&data).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+use crate::xous_arguments::{ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// A way of compressing a payload
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    /// LZ4 block format, without a frame header
+    Lz4,
+
+    /// Raw deflate, without a zlib or gzip header
+    Deflate,
+}
+
+impl Algorithm {
+    fn from_code(code: u32) -> Option<Algorithm> {
+        match code {
+            1 => Some(Algorithm::Lz4),
+            2 => Some(Algorithm::Deflate),
+            _ => None,
+        }
+    }
+
+    fn code(self) -> u32 {
+        match self {
+            Algorithm::Lz4 => 1,
+            Algorithm::Deflate => 2,
+        }
+    }
+
+    /// Look up an algorithm by the name used on the command line.
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name {
+            "lz4" => Some(Algorithm::Lz4),
+            "deflate" => Some(Algorithm::Deflate),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Lz4 => "lz4",
+            Algorithm::Deflate => "deflate",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::Lz4 => lz4_flex::block::compress(data),
+            Algorithm::Deflate => miniz_oxide::deflate::compress_to_vec(data, 10),
+        }
+    }
+
+    fn decompress(self, data: &[u8], size: usize) -> Result<Vec<u8>, String> {
+        match self {
+            Algorithm::Lz4 => lz4_flex::block::decompress(data, size).map_err(|e| e.to_string()),
+            Algorithm::Deflate => miniz_oxide::inflate::decompress_to_vec_with_limit(data, size)
+                .map_err(|e| format!("{:?}", e.status)),
+        }
+    }
+}
+
+/// Describes the compressed payload of the tag that immediately follows.
+///
+/// The payload in the image is padded to a multiple of four bytes, so that
+/// the payloads after it stay aligned.
+#[derive(Debug)]
+pub struct Compression {
+    algorithm: Algorithm,
+    compressed_size: u32,
+    uncompressed_size: u32,
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "    Cmpr: next payload is {} compressed, {} bytes -> {} bytes",
+            self.algorithm.name(),
+            self.uncompressed_size,
+            self.compressed_size
+        )
+    }
+}
+
+impl Compression {
+    /// Compress `data`.  Returns the tag describing it, along with the
+    /// padded payload to store in the image.
+    pub fn compress(algorithm: Algorithm, data: &[u8]) -> (Compression, Vec<u8>) {
+        let mut compressed = algorithm.compress(data);
+        let compression = Compression {
+            algorithm,
+            compressed_size: compressed.len() as u32,
+            uncompressed_size: data.len() as u32,
+        };
+        compressed.resize(compression.stored_size() as usize, 0);
+        (compression, compressed)
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn compressed_size(&self) -> u32 {
+        self.compressed_size
+    }
+
+    pub fn uncompressed_size(&self) -> u32 {
+        self.uncompressed_size
+    }
+
+    /// Number of bytes the payload occupies in the image, including padding.
+    pub fn stored_size(&self) -> u32 {
+        self.compressed_size.saturating_add(3) & !3
+    }
+
+    /// Decompress the stored payload of `tag`, including any padding.  The
+    /// payload is rejected without being decompressed if it would be larger
+    /// than `limit` bytes.
+    pub fn decompress(&self, tag: &Tag, stored: &[u8], limit: u64) -> Result<Vec<u8>, ParseError> {
+        if u64::from(self.uncompressed_size) > limit {
+            return Err(ParseError::BadCompression(
+                tag.offset,
+                tag.code,
+                format!(
+                    "Cmpr says it decompresses to {} bytes, more than the {} bytes of RAM",
+                    self.uncompressed_size, limit
+                ),
+            ));
+        }
+        let compressed = stored
+            .get(..self.compressed_size as usize)
+            .ok_or_else(|| {
+                ParseError::BadCompression(
+                    tag.offset,
+                    tag.code,
+                    format!(
+                        "payload is {} bytes, but Cmpr says it is {}",
+                        stored.len(),
+                        self.compressed_size
+                    ),
+                )
+            })?;
+        let data = self
+            .algorithm
+            .decompress(compressed, self.uncompressed_size as usize)
+            .map_err(|e| ParseError::BadCompression(tag.offset, tag.code, e))?;
+        if data.len() != self.uncompressed_size as usize {
+            return Err(ParseError::BadCompression(
+                tag.offset,
+                tag.code,
+                format!(
+                    "decompressed to {} bytes, expected {}",
+                    data.len(),
+                    self.uncompressed_size
+                ),
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Decode a `Cmpr` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<Compression, ParseError> {
+        tag.expect_length(12)?;
+        let algorithm = Algorithm::from_code(tag.word(0)).ok_or(
+            ParseError::UnsupportedAlgorithm(tag.offset, tag.code, tag.word(0)),
+        )?;
+        Ok(Compression {
+            algorithm,
+            compressed_size: tag.word(1),
+            uncompressed_size: tag.word(2),
+        })
+    }
+}
+
+impl XousArgument for Compression {
+    fn code(&self) -> XousArgumentCode {
+        make_type!("Cmpr")
+    }
+    fn length(&self) -> XousSize {
+        12
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = 0;
+        written += output.write(&self.algorithm.code().to_le_bytes())?;
+        written += output.write(&self.compressed_size.to_le_bytes())?;
+        written += output.write(&self.uncompressed_size.to_le_bytes())?;
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::MiniElfFlags;
+    use crate::tags::inie::IniE;
+    use crate::test_util::{pattern, section, write_image};
+    use crate::xous_arguments::XousArguments;
+
+    fn tag() -> Tag<'static> {
+        Tag {
+            offset: 0,
+            code: make_type!("IniE"),
+            crc: 0,
+            data: &[],
+        }
+    }
+
+    fn data() -> Vec<u8> {
+        [pattern(100, 1), vec![0; 4000]].concat()
+    }
+
+    #[test]
+    fn compress_and_decompress() {
+        for algorithm in [Algorithm::Lz4, Algorithm::Deflate] {
+            let (cmpr, stored) = Compression::compress(algorithm, &data());
+            assert_eq!(stored.len(), cmpr.stored_size() as usize);
+            assert_eq!(stored.len() % 4, 0);
+            assert!(cmpr.compressed_size() < 4100);
+            assert_eq!(cmpr.uncompressed_size(), 4100);
+            assert_eq!(cmpr.decompress(&tag(), &stored, 4100).unwrap(), data());
+
+            // A truncated payload is an error rather than a short program
+            let err = cmpr.decompress(&tag(), &stored[..stored.len() / 2], 4100);
+            assert!(matches!(err, Err(ParseError::BadCompression(..))));
+        }
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let (cmpr, _) = Compression::compress(Algorithm::Deflate, &data());
+        let mut encoded = vec![];
+        cmpr.serialize(&mut encoded).unwrap();
+        let decoded = Compression::decode(&Tag {
+            data: &encoded,
+            ..tag()
+        })
+        .unwrap();
+        assert_eq!(decoded.algorithm(), Algorithm::Deflate);
+        assert_eq!(decoded.compressed_size(), cmpr.compressed_size());
+        assert_eq!(decoded.uncompressed_size(), 4100);
+
+        encoded[0] = 9;
+        let err = Compression::decode(&Tag {
+            data: &encoded,
+            ..tag()
+        });
+        assert!(matches!(err, Err(ParseError::UnsupportedAlgorithm(0, _, 9))));
+    }
+
+    #[test]
+    fn larger_than_ram() {
+        let (cmpr, stored) = Compression::compress(Algorithm::Lz4, &data());
+        let err = cmpr.decompress(&tag(), &stored, 4096);
+        assert!(matches!(err, Err(ParseError::BadCompression(..))));
+
+        // An image can't hold a program bigger than its RAM
+        let mut args = XousArguments::new(0x4000_0000, 0x1000, make_type!("SrEx"));
+        let mut inie = IniE::new(
+            0x2000_0000,
+            vec![section(0x2000_0000, 4100, MiniElfFlags::EXECUTE)],
+            data(),
+        );
+        args.add(inie.compress(Algorithm::Lz4).unwrap());
+        args.add(inie);
+        let err = XousArguments::parse(&write_image(&mut args)).map(|_| ());
+        assert!(matches!(err, Err(ParseError::BadCompression(..))));
+    }
+}
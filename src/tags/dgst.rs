@@ -0,0 +1,120 @@
+use crate::utils::hex;
+use crate::xous_arguments::{ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// The only hash algorithm currently defined
+const ALGORITHM_SHA256: u32 = 1;
+
+/// A SHA-256 digest over the whole image, including every payload.
+///
+/// The digest is calculated with this tag's CRC16 and contents set to zero,
+/// and is filled in by `XousArguments::write()`.  See `crate::seal`.
+#[derive(Debug, Default)]
+pub struct Digest {
+    digest: [u8; 32],
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "    Dgst: sha256 {}", self.hex())
+    }
+}
+
+impl Digest {
+    pub fn new() -> Digest {
+        Digest::default()
+    }
+
+    pub fn digest(&self) -> &[u8; 32] {
+        &self.digest
+    }
+
+    pub fn set_digest(&mut self, digest: [u8; 32]) {
+        self.digest = digest;
+    }
+
+    /// The digest as a lowercase hex string.
+    pub fn hex(&self) -> String {
+        hex(&self.digest)
+    }
+
+    /// Decode a `Dgst` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<Digest, ParseError> {
+        tag.expect_length(36)?;
+        if tag.word(0) != ALGORITHM_SHA256 {
+            return Err(ParseError::UnsupportedAlgorithm(
+                tag.offset,
+                tag.code,
+                tag.word(0),
+            ));
+        }
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&tag.data[4..36]);
+        Ok(Digest { digest })
+    }
+}
+
+impl XousArgument for Digest {
+    fn code(&self) -> XousArgumentCode {
+        make_type!("Dgst")
+    }
+    fn length(&self) -> XousSize {
+        36
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = 0;
+        written += output.write(&ALGORITHM_SHA256.to_le_bytes())?;
+        written += output.write(&self.digest)?;
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &[u8]) -> Result<Digest, ParseError> {
+        Digest::decode(&Tag {
+            offset: 0,
+            code: make_type!("Dgst"),
+            crc: 0,
+            data,
+        })
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let mut dgst = Digest::new();
+        dgst.set_digest([0xa5; 32]);
+        let mut encoded = vec![];
+        dgst.serialize(&mut encoded).unwrap();
+        assert_eq!(encoded.len(), dgst.length() as usize);
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.digest(), &[0xa5; 32]);
+        assert_eq!(decoded.hex(), "a5".repeat(32));
+    }
+
+    #[test]
+    fn bad_length() {
+        let mut encoded = vec![];
+        Digest::new().serialize(&mut encoded).unwrap();
+        for len in [0, 32, 40] {
+            encoded.resize(len, 0);
+            let err = decode(&encoded);
+            assert!(matches!(err, Err(ParseError::BadTagLength(0, _, l)) if l == len));
+        }
+    }
+
+    #[test]
+    fn unknown_algorithm() {
+        let mut encoded = vec![];
+        Digest::new().serialize(&mut encoded).unwrap();
+        encoded[0] = 2;
+        let err = decode(&encoded);
+        assert!(matches!(
+            err,
+            Err(ParseError::UnsupportedAlgorithm(0, _, 2))
+        ));
+    }
+}
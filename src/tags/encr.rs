@@ -0,0 +1,273 @@
+use crate::encryption::{self, KeyEncryptionKey, Sealed};
+use crate::error::Error;
+use crate::utils::hex;
+use crate::xous_arguments::{ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// AES-256-GCM, with the key wrapped by AES-256-KW
+const ALGORITHM_AES256_GCM_KW: u32 = 1;
+
+/// Describes the encrypted payload of the tag that immediately follows.
+///
+/// The payload is encrypted after any compression, so a `Cmpr` tag comes
+/// before this one.  The fourcc and contents of the tag that owns the
+/// payload are used as associated data, apart from its load offset, so a
+/// payload can't be moved to another tag or given different sections
+/// without failing to decrypt.
+pub struct Encryption {
+    sealed: Sealed,
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Encryption {{ nonce: {} }}", hex(&self.sealed.nonce))
+    }
+}
+
+impl fmt::Display for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "    Encr: next payload is aes-256-gcm encrypted, nonce {}",
+            hex(&self.sealed.nonce)
+        )
+    }
+}
+
+/// The data authenticated along with a payload.  The first word of the
+/// owning tag is its load offset, which changes whenever the image is
+/// rewritten, so it is left out.
+fn associated_data(code: XousArgumentCode, header: &[u8]) -> Vec<u8> {
+    let mut aad = code.to_le_bytes().to_vec();
+    aad.extend_from_slice(header.get(4..).unwrap_or_default());
+    aad
+}
+
+impl Encryption {
+    /// Encrypt `data`, which is the payload of a tag with the fourcc `code`
+    /// and the contents `header`.  Returns the tag describing it.
+    pub fn encrypt(
+        kek: &KeyEncryptionKey,
+        code: XousArgumentCode,
+        header: &[u8],
+        data: &mut [u8],
+    ) -> Result<Encryption, Error> {
+        let aad = associated_data(code, header);
+        Ok(Encryption {
+            sealed: encryption::encrypt(kek, &aad, data)?,
+        })
+    }
+
+    /// Decrypt the stored payload of `tag`.
+    pub fn decrypt(
+        &self,
+        tag: &Tag,
+        kek: &KeyEncryptionKey,
+        stored: &[u8],
+    ) -> Result<Vec<u8>, ParseError> {
+        let mut data = stored.to_vec();
+        let aad = associated_data(tag.code, tag.data);
+        if !encryption::decrypt(kek, &aad, &self.sealed, &mut data) {
+            return Err(ParseError::DecryptionFailed(tag.offset, tag.code));
+        }
+        Ok(data)
+    }
+
+    pub fn nonce_hex(&self) -> String {
+        hex(&self.sealed.nonce)
+    }
+
+    pub fn tag_hex(&self) -> String {
+        hex(&self.sealed.tag)
+    }
+
+    pub fn wrapped_key_hex(&self) -> String {
+        hex(&self.sealed.wrapped_key)
+    }
+
+    /// Decode an `Encr` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<Encryption, ParseError> {
+        tag.expect_length(72)?;
+        if tag.word(0) != ALGORITHM_AES256_GCM_KW {
+            return Err(ParseError::UnsupportedAlgorithm(
+                tag.offset,
+                tag.code,
+                tag.word(0),
+            ));
+        }
+        let mut sealed = Sealed {
+            nonce: [0; 12],
+            tag: [0; 16],
+            wrapped_key: [0; 40],
+        };
+        sealed.nonce.copy_from_slice(&tag.data[4..16]);
+        sealed.tag.copy_from_slice(&tag.data[16..32]);
+        sealed.wrapped_key.copy_from_slice(&tag.data[32..72]);
+        Ok(Encryption { sealed })
+    }
+}
+
+impl XousArgument for Encryption {
+    fn code(&self) -> XousArgumentCode {
+        make_type!("Encr")
+    }
+    fn length(&self) -> XousSize {
+        72
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = 0;
+        written += output.write(&ALGORITHM_AES256_GCM_KW.to_le_bytes())?;
+        written += output.write(&self.sealed.nonce)?;
+        written += output.write(&self.sealed.tag)?;
+        written += output.write(&self.sealed.wrapped_key)?;
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::elf::MiniElfFlags;
+    use crate::encryption::KeyEncryptionKey;
+    use crate::tags::inie::IniE;
+    use crate::tags::memory::{MemoryRegion, MemoryRegions};
+    use crate::tags::xkrn::XousKernel;
+    use crate::test_util::{kernel, pattern, section, write_image};
+    use crate::xous_arguments::{tag_crc, ParseError, TagIter, XousArguments};
+
+    /// An init program whose 24 bytes of text are `pattern(24, seed)`.
+    fn init(entrypoint: u32, seed: u8) -> IniE {
+        IniE::new(
+            entrypoint,
+            vec![section(0x2000_0000, 24, MiniElfFlags::EXECUTE)],
+            pattern(24, seed),
+        )
+    }
+
+    /// An image with an encrypted kernel and two encrypted init programs,
+    /// which differ only in their entrypoints and data.
+    fn encrypted_image(kek: &KeyEncryptionKey) -> Vec<u8> {
+        let mut args = XousArguments::new(0x4000_0000, 0x0100_0000, make_type!("SrEx"));
+        let mut xkrn = kernel();
+        args.add(xkrn.encrypt(kek).unwrap());
+        args.add(xkrn);
+        for (entrypoint, seed) in [(0x2000_0000, 2), (0x2000_0004, 3)] {
+            let mut inie = init(entrypoint, seed);
+            args.add(inie.encrypt(kek).unwrap());
+            args.add(inie);
+        }
+        write_image(&mut args)
+    }
+
+    fn inits(args: &XousArguments) -> Vec<&IniE> {
+        args.positions::<IniE>()
+            .into_iter()
+            .map(|pos| args.arguments[pos].as_ref().as_any().downcast_ref().unwrap())
+            .collect()
+    }
+
+    fn decryption_failed(image: &[u8], kek: &KeyEncryptionKey) -> bool {
+        matches!(
+            XousArguments::parse_with_key(image, Some(kek)),
+            Err(ParseError::DecryptionFailed(..))
+        )
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let kek = KeyEncryptionKey::new([7; 32]);
+        let image = encrypted_image(&kek);
+        // Nothing is stored in the clear
+        assert!(!image
+            .windows(24)
+            .any(|w| w == pattern(24, 1) || w == pattern(24, 2)));
+
+        let args = XousArguments::parse_with_key(&image, Some(&kek)).unwrap();
+        let xkrn = args.get::<XousKernel>().unwrap();
+        assert!(xkrn.is_encrypted());
+        assert_eq!(xkrn.program(), kernel().program());
+        for (inie, seed) in inits(&args).into_iter().zip([2, 3]) {
+            assert!(inie.is_encrypted());
+            assert_eq!(inie.data(), &pattern(24, seed)[..]);
+        }
+    }
+
+    #[test]
+    fn without_a_key() {
+        let kek = KeyEncryptionKey::new([7; 32]);
+        let image = encrypted_image(&kek);
+        let mut args = XousArguments::parse(&image).unwrap();
+        let inie = inits(&args)[0];
+        assert!(inie.is_encrypted());
+        assert!(!inie.has_data());
+        assert_eq!(inie.data_len(), 24);
+
+        // The ciphertext survives being rewritten, even though the payloads
+        // have moved
+        let mut regions = MemoryRegions::new();
+        regions.add(MemoryRegion::new(0xf000_0000, 0x1000, make_type!("CSRs")));
+        args.arguments.insert(0, Box::new(regions));
+        let image = write_image(&mut args);
+        let args = XousArguments::parse_with_key(&image, Some(&kek)).unwrap();
+        assert_eq!(inits(&args)[1].data(), &pattern(24, 3)[..]);
+    }
+
+    #[test]
+    fn wrong_key() {
+        let image = encrypted_image(&KeyEncryptionKey::new([7; 32]));
+        assert!(decryption_failed(&image, &KeyEncryptionKey::new([8; 32])));
+    }
+
+    #[test]
+    fn tampered_header() {
+        let kek = KeyEncryptionKey::new([7; 32]);
+        let mut image = encrypted_image(&kek);
+        let offset = TagIter::new(&image)
+            .map(Result::unwrap)
+            .find(|tag| tag.code == make_type!("IniE"))
+            .unwrap()
+            .offset;
+        // Move the entrypoint, and fix up the CRC so only decryption notices
+        image[offset + 12] ^= 0x10;
+        let len = u16::from_le_bytes([image[offset + 6], image[offset + 7]]) as usize * 4;
+        let crc = tag_crc(&image[offset + 8..offset + 8 + len]);
+        image[offset + 4..offset + 6].copy_from_slice(&crc.to_le_bytes());
+        assert!(decryption_failed(&image, &kek));
+    }
+
+    #[test]
+    fn swapped_payloads() {
+        let kek = KeyEncryptionKey::new([7; 32]);
+        let image = encrypted_image(&kek);
+
+        // Reordering whole programs is fine
+        let mut args = XousArguments::parse(&image).unwrap();
+        let positions = args.positions::<IniE>();
+        args.arguments.swap(positions[0] - 1, positions[1] - 1);
+        args.arguments.swap(positions[0], positions[1]);
+        let reordered = write_image(&mut args);
+        let args = XousArguments::parse_with_key(&reordered, Some(&kek)).unwrap();
+        assert_eq!(inits(&args)[0].entrypoint(), 0x2000_0004);
+
+        // But moving one program's Encr tag and ciphertext under the other's
+        // IniE tag isn't, even though they are the same size
+        let tags: Vec<_> = TagIter::new(&image).map(Result::unwrap).collect();
+        let encr: Vec<usize> = tags
+            .iter()
+            .filter(|tag| tag.code == make_type!("Encr"))
+            .map(|tag| tag.offset)
+            .skip(1)
+            .collect();
+        let payloads: Vec<usize> = tags
+            .iter()
+            .filter(|tag| tag.code == make_type!("IniE"))
+            .map(|tag| tag.word(0) as usize)
+            .collect();
+        let mut swapped = image.clone();
+        for (a, b, len) in [(encr[0], encr[1], 80), (payloads[0], payloads[1], 24)] {
+            swapped[a..a + len].copy_from_slice(&image[b..b + len]);
+            swapped[b..b + len].copy_from_slice(&image[a..a + len]);
+        }
+        assert!(decryption_failed(&swapped, &kek));
+    }
+}
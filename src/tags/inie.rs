@@ -1,5 +1,10 @@
-use crate::elf::MiniElfSection;
-use crate::xous_arguments::{XousArgument, XousArgumentCode, XousSize};
+use crate::elf::{MiniElfFlags, MiniElfSection};
+use crate::encryption::KeyEncryptionKey;
+use crate::error::Error;
+use crate::tags::cmpr::{Algorithm, Compression};
+use crate::tags::encr::Encryption;
+use crate::tags::payload::{Encoding, Payload};
+use crate::xous_arguments::{ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
 use std::fmt;
 use std::io;
 
@@ -15,7 +20,7 @@ pub struct IniE {
     sections: Vec<MiniElfSection>,
 
     /// Actual program data
-    data: Vec<u8>,
+    data: Payload,
 }
 
 impl fmt::Display for IniE {
@@ -28,7 +33,7 @@ impl fmt::Display for IniE {
         let mut load_offset = self.load_offset;
         for section in &self.sections {
             writeln!(f, "        Loaded from {:08x} - {}", load_offset, section)?;
-            load_offset += section.size as u32;
+            load_offset = load_offset.saturating_add(section.size);
         }
         Ok(())
     }
@@ -40,9 +45,100 @@ impl IniE {
             load_offset: 0,
             entrypoint,
             sections,
-            data,
+            data: Payload::new(data),
         }
     }
+
+    /// Compress the program data when it is written out, unless that wouldn't
+    /// save any space.  Returns the `Cmpr` tag that must be placed
+    /// immediately before this one.
+    pub fn compress(&mut self, algorithm: Algorithm) -> Option<Compression> {
+        self.data.compress(algorithm)
+    }
+
+    /// Encrypt the program data when it is written out, after any
+    /// compression.  Returns the `Encr` tag that must be placed immediately
+    /// before this one.
+    pub fn encrypt(&mut self, kek: &KeyEncryptionKey) -> Result<Encryption, Error> {
+        let mut header = vec![];
+        self.serialize(&mut header)?;
+        self.data.encrypt(kek, self.code(), &header)
+    }
+
+    /// Returns `true` if the program data is stored compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.data.is_compressed()
+    }
+
+    /// Returns `true` if the program data is stored encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.data.is_encrypted()
+    }
+
+    /// Returns `false` if the program data is encrypted and couldn't be
+    /// decrypted, in which case `data()` is empty.
+    pub fn has_data(&self) -> bool {
+        self.data.has_data()
+    }
+
+    /// Decode an `IniE` tag read from an image, along with its payload.
+    /// `encoding` holds the tags immediately before this one, if any.
+    pub fn decode(tag: &Tag, image: &[u8], encoding: Encoding) -> Result<IniE, ParseError> {
+        if tag.data.len() < 8 || tag.data.len() & 7 != 0 {
+            return Err(ParseError::BadTagLength(tag.offset, tag.code, tag.data.len()));
+        }
+        let load_offset = tag.word(0);
+        let entrypoint = tag.word(1);
+        let mut sections = vec![];
+        let mut payload_len: u32 = 0;
+        for idx in 0..(tag.words() - 2) / 2 {
+            let word2 = tag.word(3 + idx * 2);
+            let section = MiniElfSection {
+                virt: tag.word(2 + idx * 2),
+                size: (word2 & 0x00ff_ffff) << 2,
+                flags: MiniElfFlags::from_bits_truncate((word2 >> 24) as u8),
+                name: String::new(),
+            };
+            if !section.flags.contains(MiniElfFlags::NOCOPY) {
+                // A corrupt tag can claim more than fits in an image
+                payload_len = payload_len.checked_add(section.size).ok_or(
+                    ParseError::BadTagLength(tag.offset, tag.code, tag.data.len()),
+                )?;
+            }
+            sections.push(section);
+        }
+        Ok(IniE {
+            load_offset,
+            entrypoint,
+            sections,
+            data: Payload::decode(tag, image, load_offset, payload_len, encoding)?,
+        })
+    }
+
+    /// Offset of the program data within the image.
+    pub fn load_offset(&self) -> u32 {
+        self.load_offset
+    }
+
+    /// Virtual address of the entrypoint
+    pub fn entrypoint(&self) -> u32 {
+        self.entrypoint
+    }
+
+    pub fn sections(&self) -> &[MiniElfSection] {
+        &self.sections
+    }
+
+    /// Size of the program data, even if it couldn't be decrypted.
+    pub fn data_len(&self) -> u32 {
+        self.data.length()
+    }
+
+    /// Program data, in the order described by `sections()`.  This is
+    /// always uncompressed and decrypted.
+    pub fn data(&self) -> &[u8] {
+        self.data.data()
+    }
 }
 
 impl XousArgument for IniE {
@@ -56,11 +152,11 @@ impl XousArgument for IniE {
 
     fn finalize(&mut self, offset: usize) -> usize {
         self.load_offset = offset as u32;
-        self.data.len()
+        self.last_data().len()
     }
 
     fn last_data(&self) -> &[u8] {
-        &self.data
+        self.data.stored()
     }
 
     fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
@@ -1,16 +1,141 @@
-use crate::xous_arguments::{XousArgument, XousArgumentCode, XousSize};
+use crate::xous_arguments::{code_name, ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
+use bitflags::bitflags;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::io;
 
 /// Convert a four-letter string into a 32-bit int.
 macro_rules! make_type {
     ($fcc:expr) => {{
-        let mut c: [u8; 4] = Default::default();
-        c.copy_from_slice($fcc.as_bytes());
+        // Pad or truncate by bytes rather than characters, so that names of
+        // any length or encoding give a code instead of panicking
+        let mut c: [u8; 4] = *b"    ";
+        for (dest, src) in c.iter_mut().zip($fcc.as_bytes()) {
+            *dest = *src;
+        }
         u32::from_le_bytes(c)
     }};
 }
 
+bitflags! {
+    /// How a memory region may be used.  A region with no attributes at
+    /// all comes from an image made before attributes existed.
+    #[derive(Default)]
+    pub struct RegionAttributes: u32 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXECUTE = 1 << 2;
+
+        /// Normal memory that may be cached
+        const CACHEABLE = 1 << 3;
+
+        /// Device I/O, which must not be cached
+        const DEVICE = 1 << 4;
+
+        /// The kernel may hand out pages from this region
+        const ALLOCATE = 1 << 5;
+    }
+}
+
+impl RegionAttributes {
+    /// Every named attribute, in bit order, along with the name used on
+    /// the command line and in manifests.
+    pub const NAMES: &'static [(&'static str, RegionAttributes)] = &[
+        ("read", RegionAttributes::READ),
+        ("write", RegionAttributes::WRITE),
+        ("execute", RegionAttributes::EXECUTE),
+        ("cacheable", RegionAttributes::CACHEABLE),
+        ("device", RegionAttributes::DEVICE),
+        ("allocate", RegionAttributes::ALLOCATE),
+    ];
+
+    /// Device memory, such as CSRs or a framebuffer
+    pub const IO: RegionAttributes = RegionAttributes::from_bits_truncate(
+        RegionAttributes::DEVICE.bits | RegionAttributes::READ.bits | RegionAttributes::WRITE.bits,
+    );
+
+    /// RAM that the kernel can allocate from
+    pub const RAM: RegionAttributes = RegionAttributes::from_bits_truncate(
+        RegionAttributes::CACHEABLE.bits
+            | RegionAttributes::READ.bits
+            | RegionAttributes::WRITE.bits
+            | RegionAttributes::EXECUTE.bits
+            | RegionAttributes::ALLOCATE.bits,
+    );
+
+    /// Read-only memory, such as ROM or memory-mapped flash
+    pub const ROM: RegionAttributes = RegionAttributes::from_bits_truncate(
+        RegionAttributes::CACHEABLE.bits
+            | RegionAttributes::READ.bits
+            | RegionAttributes::EXECUTE.bits,
+    );
+
+    /// Look up a single attribute by name.
+    pub fn from_name(name: &str) -> Option<RegionAttributes> {
+        RegionAttributes::NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, attr)| *attr)
+    }
+
+    /// Parse a comma-separated list of attribute names, such as
+    /// `read,write,device`.  Returns the first unknown name on failure.
+    pub fn parse_list(list: &str) -> Result<RegionAttributes, String> {
+        let mut attrs = RegionAttributes::empty();
+        for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            attrs |= RegionAttributes::from_name(name).ok_or_else(|| name.to_owned())?;
+        }
+        Ok(attrs)
+    }
+
+    /// Names of every attribute that is set.
+    pub fn names(self) -> Vec<&'static str> {
+        RegionAttributes::NAMES
+            .iter()
+            .filter(|(_, attr)| self.contains(*attr))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// Sensible attributes for a region, going by its name and whether the
+    /// LiteX CSV file says it is I/O.
+    pub fn default_for(name: &str, io: bool) -> RegionAttributes {
+        let lower = name.to_lowercase();
+        if io {
+            return RegionAttributes::IO;
+        }
+        match MemoryRegion::make_name(name) {
+            code if code == make_type!("CSRs")
+                || code == make_type!("Disp")
+                || code == make_type!("VexD")
+                || code == make_type!("Audi") =>
+            {
+                RegionAttributes::IO
+            }
+            code if code == make_type!("Boot") || code == make_type!("SpFl") => {
+                RegionAttributes::ROM
+            }
+            code if code == make_type!("SrEx") || code == make_type!("SrIn") => {
+                RegionAttributes::RAM
+            }
+            _ if lower.contains("csr") => RegionAttributes::IO,
+            _ if lower.contains("rom") || lower.contains("flash") => RegionAttributes::ROM,
+            _ if lower.contains("ram") => RegionAttributes::RAM,
+            _ => RegionAttributes::CACHEABLE | RegionAttributes::READ | RegionAttributes::WRITE,
+        }
+    }
+}
+
+impl fmt::Display for RegionAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            write!(f, "(none)")
+        } else {
+            write!(f, "{}", self.names().join(","))
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MemoryRegion {
     /// Starting offset (in bytes)
@@ -22,10 +147,12 @@ pub struct MemoryRegion {
     /// Region name (as a type)
     name: XousArgumentCode,
 
-    /// Unused
-    padding: u32,
+    /// `RegionAttributes`, kept as the raw word so that bits these tools
+    /// don't know about survive being written back out
+    attributes: u32,
 }
 
+#[derive(Debug, Default)]
 pub struct MemoryRegions {
     regions: Vec<MemoryRegion>,
 }
@@ -38,11 +165,12 @@ impl fmt::Display for MemoryRegions {
             let tag_name_str = String::from_utf8_lossy(&tag_name_bytes);
             writeln!(
                 f,
-                "        {} ({:08x}): {:08x} - {:08x}",
+                "        {} ({:08x}): {:08x} - {:08x} {}",
                 tag_name_str,
                 region.name,
                 region.start,
-                region.start + region.length
+                region.start as u64 + region.length as u64,
+                region.attributes()
             )?;
         }
         Ok(())
@@ -50,15 +178,51 @@ impl fmt::Display for MemoryRegions {
 }
 
 impl MemoryRegion {
+    /// Describe a region, with attributes chosen from its name.
     pub fn new(start: XousSize, length: XousSize, name: u32) -> MemoryRegion {
+        let attributes = RegionAttributes::default_for(&code_name(name), false);
         MemoryRegion {
             start,
             length,
             name,
-            padding: 0,
+            attributes: attributes.bits(),
         }
     }
 
+    pub fn with_attributes(mut self, attributes: RegionAttributes) -> MemoryRegion {
+        self.attributes = attributes.bits();
+        self
+    }
+
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    pub fn name(&self) -> XousArgumentCode {
+        self.name
+    }
+
+    pub fn attributes(&self) -> RegionAttributes {
+        RegionAttributes::from_bits_truncate(self.attributes)
+    }
+
+    /// Attribute bits that aren't named attributes.
+    pub fn unknown_attributes(&self) -> u32 {
+        self.attributes & !RegionAttributes::all().bits()
+    }
+
+    pub fn set_attributes(&mut self, attributes: RegionAttributes) {
+        self.attributes = attributes.bits() | self.unknown_attributes();
+    }
+
+    /// The code for a region named `name` in a LiteX CSV file.  Well-known
+    /// LiteX regions have their own codes, and anything else uses the first
+    /// four characters of its name, padded with spaces.  Characters that
+    /// aren't ASCII become `_`.
     pub fn make_name(name: &str) -> u32 {
         match name {
             "sram_ext" => make_type!("SrEx"),
@@ -70,13 +234,52 @@ impl MemoryRegion {
             "rom" => make_type!("Boot"),
             "spiflash" => make_type!("SpFl"),
             other => {
-                let mut region_name = other.to_owned();
-                region_name.push_str("    ");
-                region_name.truncate(4);
+                let region_name: String = other
+                    .chars()
+                    .take(4)
+                    .map(|c| if c.is_ascii() { c } else { '_' })
+                    .collect();
                 make_type!(region_name)
             }
         }
     }
+
+    /// Turn a code given by the user, such as `SrE2`, into a region name.
+    /// It must be exactly four printable ASCII characters.
+    pub fn parse_code(code: &str) -> Option<XousArgumentCode> {
+        if code.len() == 4 && code.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+            Some(make_type!(code))
+        } else {
+            None
+        }
+    }
+}
+
+/// Codes for memory regions, chosen by the user, by the name the region has
+/// in the CSV file or on the command line.  Regions that aren't listed get
+/// the code from `MemoryRegion::make_name`.
+#[derive(Clone, Debug, Default)]
+pub struct RegionNames {
+    codes: BTreeMap<String, XousArgumentCode>,
+}
+
+impl RegionNames {
+    pub fn new() -> RegionNames {
+        RegionNames::default()
+    }
+
+    /// Give the region named `name` this code, replacing any earlier one.
+    pub fn set(&mut self, name: &str, code: XousArgumentCode) {
+        self.codes.insert(name.to_owned(), code);
+    }
+
+    /// The code for the region named `name`.
+    pub fn code(&self, name: &str) -> XousArgumentCode {
+        self.codes
+            .get(name)
+            .copied()
+            .unwrap_or_else(|| MemoryRegion::make_name(name))
+    }
 }
 
 impl MemoryRegions {
@@ -89,6 +292,37 @@ impl MemoryRegions {
     pub fn len(&self) -> usize {
         self.regions.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+    /// Remove the region with the given name, returning it if it existed.
+    pub fn remove(&mut self, name: XousArgumentCode) -> Option<MemoryRegion> {
+        let idx = self.regions.iter().position(|r| r.name == name)?;
+        Some(self.regions.remove(idx))
+    }
+    pub fn regions(&self) -> &[MemoryRegion] {
+        &self.regions
+    }
+    pub fn get_mut(&mut self, name: XousArgumentCode) -> Option<&mut MemoryRegion> {
+        self.regions.iter_mut().find(|r| r.name == name)
+    }
+
+    /// Decode an `MREx` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<MemoryRegions, ParseError> {
+        if tag.data.len() & 15 != 0 {
+            return Err(ParseError::BadTagLength(tag.offset, tag.code, tag.data.len()));
+        }
+        let mut regions = MemoryRegions::new();
+        for idx in 0..tag.words() / 4 {
+            regions.add(MemoryRegion {
+                start: tag.word(idx * 4),
+                length: tag.word(idx * 4 + 1),
+                name: tag.word(idx * 4 + 2),
+                attributes: tag.word(idx * 4 + 3),
+            });
+        }
+        Ok(regions)
+    }
 }
 
 impl XousArgument for MemoryRegions {
@@ -101,11 +335,24 @@ impl XousArgument for MemoryRegions {
     fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
         let mut written = 0;
         for region in &self.regions {
-            written = written + output.write(&region.start.to_le_bytes())?;
-            written = written + output.write(&region.length.to_le_bytes())?;
-            written = written + output.write(&region.name.to_le_bytes())?;
-            written = written + output.write(&0u32.to_le_bytes())?;
+            written += output.write(&region.start.to_le_bytes())?;
+            written += output.write(&region.length.to_le_bytes())?;
+            written += output.write(&region.name.to_le_bytes())?;
+            written += output.write(&region.attributes.to_le_bytes())?;
         }
         Ok(written)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_region_at_top_of_memory() {
+        let mut regions = MemoryRegions::new();
+        regions.add(MemoryRegion::new(0xffff_f000, 0x1000, make_type!("Boot")));
+        let shown = regions.to_string();
+        assert!(shown.contains("fffff000 - 100000000"));
+    }
+}
@@ -1,4 +1,15 @@
 pub mod bflg;
+pub mod binf;
+pub mod bpar;
+pub mod cmpr;
+pub mod dgst;
+pub mod encr;
 pub mod inie;
 pub mod memory;
+pub mod payload;
+pub mod proc;
+pub mod raw;
+pub mod sign;
+pub mod sver;
 pub mod xkrn;
+pub mod xram;
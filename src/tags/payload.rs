@@ -0,0 +1,161 @@
+use crate::encryption::KeyEncryptionKey;
+use crate::error::Error;
+use crate::tags::cmpr::{Algorithm, Compression};
+use crate::tags::encr::Encryption;
+use crate::xous_arguments::{ParseError, Tag, XousArgumentCode};
+
+/// The tags describing how a payload is stored.  These come immediately
+/// before the tag that owns the payload, `Cmpr` first.
+#[derive(Clone, Copy)]
+pub struct Encoding<'a> {
+    pub compression: Option<&'a Compression>,
+    pub encryption: Option<&'a Encryption>,
+
+    /// Key to decrypt the payload with.  Without it, an encrypted payload
+    /// is kept as it is stored.
+    pub key: Option<&'a KeyEncryptionKey>,
+
+    /// Total RAM in the image.  No payload can be bigger than this once
+    /// decompressed, so it bounds what a corrupt `Cmpr` tag can allocate.
+    pub ram_size: u64,
+}
+
+/// The contents of a kernel or init program, along with how they are
+/// stored in the image.
+#[derive(Debug)]
+pub struct Payload {
+    /// Size of the data, which is known even if it can't be decrypted
+    length: u32,
+
+    /// The data, or `None` if it is encrypted and no key was given
+    data: Option<Vec<u8>>,
+
+    /// The data as stored in the image, if it is compressed or encrypted
+    stored: Option<Vec<u8>>,
+
+    compressed: bool,
+    encrypted: bool,
+}
+
+impl Payload {
+    pub fn new(data: Vec<u8>) -> Payload {
+        Payload {
+            length: data.len() as u32,
+            data: Some(data),
+            stored: None,
+            compressed: false,
+            encrypted: false,
+        }
+    }
+
+    /// Compress the data when it is written out, unless that wouldn't save
+    /// any space.  This must be done before encrypting it.
+    pub fn compress(&mut self, algorithm: Algorithm) -> Option<Compression> {
+        if self.encrypted {
+            return None;
+        }
+        let (compression, compressed) = Compression::compress(algorithm, self.data.as_ref()?);
+        if compressed.len() >= self.length as usize {
+            return None;
+        }
+        self.stored = Some(compressed);
+        self.compressed = true;
+        Some(compression)
+    }
+
+    /// Encrypt the data as it will be stored, after any compression.  `code`
+    /// and `header` are the fourcc and contents of the tag that owns the
+    /// payload.
+    pub fn encrypt(
+        &mut self,
+        kek: &KeyEncryptionKey,
+        code: XousArgumentCode,
+        header: &[u8],
+    ) -> Result<Encryption, Error> {
+        let mut stored = self.stored().to_vec();
+        let encryption = Encryption::encrypt(kek, code, header, &mut stored)?;
+        self.stored = Some(stored);
+        self.encrypted = true;
+        Ok(encryption)
+    }
+
+    /// Size of the uncompressed, unencrypted data.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// The uncompressed, unencrypted data.  This is empty if the payload
+    /// couldn't be decrypted.
+    pub fn data(&self) -> &[u8] {
+        self.data.as_deref().unwrap_or(&[])
+    }
+
+    /// Returns `false` if the payload is encrypted and no key was given.
+    pub fn has_data(&self) -> bool {
+        self.data.is_some()
+    }
+
+    /// The payload as it is stored in the image.
+    pub fn stored(&self) -> &[u8] {
+        self.stored
+            .as_deref()
+            .or(self.data.as_deref())
+            .unwrap_or(&[])
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Read the payload of `tag`, which is `length` bytes once decoded and
+    /// is stored at `offset` in `image`.
+    pub fn decode(
+        tag: &Tag,
+        image: &[u8],
+        offset: u32,
+        length: u32,
+        encoding: Encoding,
+    ) -> Result<Payload, ParseError> {
+        let stored_len = match encoding.compression {
+            Some(compression) => {
+                if compression.uncompressed_size() != length {
+                    return Err(ParseError::BadCompression(
+                        tag.offset,
+                        tag.code,
+                        format!(
+                            "Cmpr says it decompresses to {} bytes, but the tag describes {}",
+                            compression.uncompressed_size(),
+                            length
+                        ),
+                    ));
+                }
+                compression.stored_size()
+            }
+            None => length,
+        };
+        let stored = tag.payload(image, offset, stored_len)?;
+
+        let plain = match (encoding.encryption, encoding.key) {
+            (Some(encryption), Some(key)) => Some(encryption.decrypt(tag, key, stored)?),
+            (Some(_), None) => None,
+            (None, _) => Some(stored.to_vec()),
+        };
+        let data = match (plain, encoding.compression) {
+            (Some(plain), Some(compression)) => Some(compression.decompress(tag, &plain, encoding.ram_size)?),
+            (plain, _) => plain,
+        };
+
+        let encoded = encoding.compression.is_some() || encoding.encryption.is_some();
+        Ok(Payload {
+            length,
+            data,
+            stored: if encoded { Some(stored.to_vec()) } else { None },
+            compressed: encoding.compression.is_some(),
+            encrypted: encoding.encryption.is_some(),
+        })
+    }
+}
@@ -0,0 +1,279 @@
+use crate::xous_arguments::{ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// The priority byte is meaningful
+const HAS_PRIORITY: u8 = 1 << 0;
+
+/// The process should be started with debugging enabled
+const DEBUG: u8 = 1 << 1;
+
+/// The longest name that fits in the 16-bit length field
+pub const MAX_NAME_LENGTH: usize = u16::MAX as usize;
+
+/// Name and loader hints for a single init program.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessInfo {
+    /// Human-readable name, or empty if the program has none
+    pub name: String,
+
+    /// Stack size in bytes, if the loader's default isn't wanted
+    pub stack_size: Option<u32>,
+
+    /// Scheduling priority, if the loader's default isn't wanted
+    pub priority: Option<u8>,
+
+    /// Start this process with debugging enabled
+    pub debug: bool,
+}
+
+impl ProcessInfo {
+    pub fn new(name: &str) -> ProcessInfo {
+        ProcessInfo {
+            name: name.to_owned(),
+            ..ProcessInfo::default()
+        }
+    }
+
+    fn length(&self) -> usize {
+        8 + ((self.name.len() + 3) & !3)
+    }
+}
+
+impl fmt::Display for ProcessInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "(unnamed)")?;
+        } else {
+            write!(f, "{}", self.name)?;
+        }
+        if let Some(stack_size) = self.stack_size {
+            write!(f, ", stack {} bytes", stack_size)?;
+        }
+        if let Some(priority) = self.priority {
+            write!(f, ", priority {}", priority)?;
+        }
+        if self.debug {
+            write!(f, ", debug")?;
+        }
+        Ok(())
+    }
+}
+
+/// Names and metadata for the init programs, one entry per `IniE` tag in
+/// the same order.
+///
+/// The contents are the number of entries, followed by each entry as a
+/// word of stack size (zero for the default), a word made up of the
+/// priority, a flags byte and the length of the name, and then the name
+/// itself padded with NULs to a multiple of four bytes.
+#[derive(Clone, Debug, Default)]
+pub struct Processes {
+    processes: Vec<ProcessInfo>,
+}
+
+impl fmt::Display for Processes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "    Proc:")?;
+        for (idx, process) in self.processes.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ";")?;
+            }
+            write!(f, " {}", process)?;
+        }
+        writeln!(f)
+    }
+}
+
+impl Processes {
+    pub fn new() -> Processes {
+        Processes::default()
+    }
+
+    /// Add the entry for the next init program.
+    pub fn add(&mut self, process: ProcessInfo) {
+        self.processes.push(process);
+    }
+
+    /// Add the entry for the init program at `index`.  A `Proc` tag may
+    /// have fewer entries than there are programs, so any missing ones
+    /// before `index` are filled in as unnamed.
+    pub fn insert(&mut self, index: usize, process: ProcessInfo) {
+        if index > self.processes.len() {
+            self.processes.resize_with(index, ProcessInfo::default);
+        }
+        self.processes.insert(index, process);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<ProcessInfo> {
+        if index < self.processes.len() {
+            Some(self.processes.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// The entry for the init program at `index`, if there is one.
+    pub fn get(&self, index: usize) -> Option<&ProcessInfo> {
+        self.processes.get(index)
+    }
+
+    pub fn processes(&self) -> &[ProcessInfo] {
+        &self.processes
+    }
+
+    pub fn len(&self) -> usize {
+        self.processes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+
+    /// Decode a `Proc` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<Processes, ParseError> {
+        let bad_length = || ParseError::BadTagLength(tag.offset, tag.code, tag.data.len());
+        if tag.data.len() < 4 {
+            return Err(bad_length());
+        }
+        let count = tag.word(0) as usize;
+        let mut offset = 4;
+        let mut processes = vec![];
+        for _ in 0..count {
+            if offset + 8 > tag.data.len() {
+                return Err(bad_length());
+            }
+            let stack_size = tag.word(offset / 4);
+            let word = tag.word(offset / 4 + 1);
+            let priority = word as u8;
+            let flags = (word >> 8) as u8;
+            let name_len = (word >> 16) as usize;
+            offset += 8;
+            let name = tag
+                .data
+                .get(offset..offset + name_len)
+                .ok_or_else(bad_length)?;
+            offset += (name_len + 3) & !3;
+            processes.push(ProcessInfo {
+                name: String::from_utf8_lossy(name).into_owned(),
+                stack_size: if stack_size != 0 {
+                    Some(stack_size)
+                } else {
+                    None
+                },
+                priority: if flags & HAS_PRIORITY != 0 {
+                    Some(priority)
+                } else {
+                    None
+                },
+                debug: flags & DEBUG != 0,
+            });
+        }
+        if offset > tag.data.len() {
+            return Err(bad_length());
+        }
+        Ok(Processes { processes })
+    }
+}
+
+impl XousArgument for Processes {
+    fn code(&self) -> XousArgumentCode {
+        make_type!("Proc")
+    }
+    fn length(&self) -> XousSize {
+        (4 + self
+            .processes
+            .iter()
+            .map(ProcessInfo::length)
+            .sum::<usize>()) as XousSize
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = output.write(&(self.processes.len() as u32).to_le_bytes())?;
+        for process in &self.processes {
+            if process.name.len() > MAX_NAME_LENGTH {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("process name is {} bytes long", process.name.len()),
+                ));
+            }
+            let mut flags = 0;
+            if process.priority.is_some() {
+                flags |= HAS_PRIORITY;
+            }
+            if process.debug {
+                flags |= DEBUG;
+            }
+            let word = process.priority.unwrap_or(0) as u32
+                | (flags as u32) << 8
+                | (process.name.len() as u32) << 16;
+            written += output.write(&process.stack_size.unwrap_or(0).to_le_bytes())?;
+            written += output.write(&word.to_le_bytes())?;
+            written += output.write(process.name.as_bytes())?;
+            let len = process.name.len();
+            written += output.write(&[0u8; 3][..((len + 3) & !3) - len])?;
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &[u8]) -> Result<Processes, ParseError> {
+        Processes::decode(&Tag {
+            offset: 0,
+            code: make_type!("Proc"),
+            crc: 0,
+            data,
+        })
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let mut processes = Processes::new();
+        processes.add(ProcessInfo::new("shell"));
+        processes.add(ProcessInfo::default());
+        processes.add(ProcessInfo {
+            name: "log-server".to_owned(),
+            stack_size: Some(0x4000),
+            priority: Some(3),
+            debug: true,
+        });
+        let mut encoded = vec![];
+        let written = processes.serialize(&mut encoded).unwrap();
+        assert_eq!(written, encoded.len());
+        assert_eq!(written, processes.length() as usize);
+
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.processes(), processes.processes());
+
+        let err = decode(&encoded[..encoded.len() - 4]);
+        assert!(matches!(err, Err(ParseError::BadTagLength(..))));
+    }
+
+    #[test]
+    fn name_too_long() {
+        let mut processes = Processes::new();
+        processes.add(ProcessInfo::new(&"x".repeat(MAX_NAME_LENGTH)));
+        assert!(processes.serialize(&mut vec![]).is_ok());
+
+        processes.add(ProcessInfo::new(&"x".repeat(MAX_NAME_LENGTH + 1)));
+        let err = processes.serialize(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn insert_pads_missing_entries() {
+        let mut processes = Processes::new();
+        processes.add(ProcessInfo::new("shell"));
+        processes.insert(3, ProcessInfo::new("log-server"));
+        processes.insert(1, ProcessInfo::new("ticktimer"));
+        let names: Vec<&str> = processes
+            .processes()
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(names, ["shell", "ticktimer", "", "", "log-server"]);
+    }
+}
@@ -0,0 +1,48 @@
+use crate::xous_arguments::{XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// A tag whose contents we don't understand, kept verbatim.
+#[derive(Debug)]
+pub struct RawTag {
+    /// Fourcc code of the tag
+    code: XousArgumentCode,
+
+    /// Tag contents, not including the header
+    data: Vec<u8>,
+}
+
+impl fmt::Display for RawTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "    {}:", self.name())?;
+        for word in self.data.chunks(4) {
+            let mut w = [0u8; 4];
+            w[..word.len()].copy_from_slice(word);
+            write!(f, " {:08x}", u32::from_le_bytes(w))?;
+        }
+        writeln!(f)
+    }
+}
+
+impl RawTag {
+    pub fn new(code: XousArgumentCode, data: Vec<u8>) -> RawTag {
+        RawTag { code, data }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl XousArgument for RawTag {
+    fn code(&self) -> XousArgumentCode {
+        self.code
+    }
+    fn length(&self) -> XousSize {
+        self.data.len() as XousSize
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        output.write_all(&self.data)?;
+        Ok(self.data.len())
+    }
+}
@@ -0,0 +1,100 @@
+use crate::utils::hex;
+use crate::xous_arguments::{ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
+use ed25519_dalek::SigningKey;
+use std::fmt;
+use std::io;
+
+/// The only signature algorithm currently defined
+const ALGORITHM_ED25519: u32 = 1;
+
+/// An Ed25519 signature over the whole image, including every payload.
+///
+/// The signature is calculated with the CRC16 and contents of this tag and
+/// of the `Dgst` tag set to zero.  If the tag holds a signing key, the
+/// signature is filled in by `XousArguments::write()`.  See `crate::signing`.
+#[derive(Debug)]
+pub struct Signature {
+    public_key: [u8; 32],
+    signature: [u8; 64],
+    key: Option<SigningKey>,
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "    Sign: ed25519 with key {}", hex(&self.public_key))
+    }
+}
+
+impl Signature {
+    /// A signature that will be made with `key` when the image is written.
+    pub fn new(key: SigningKey) -> Signature {
+        Signature {
+            public_key: key.verifying_key().to_bytes(),
+            signature: [0; 64],
+            key: Some(key),
+        }
+    }
+
+    pub fn public_key(&self) -> &[u8; 32] {
+        &self.public_key
+    }
+
+    pub fn signature(&self) -> &[u8; 64] {
+        &self.signature
+    }
+
+    pub fn set_signature(&mut self, signature: [u8; 64]) {
+        self.signature = signature;
+    }
+
+    /// The key this image will be signed with, if it is known.  Signatures
+    /// decoded from an image never have one.
+    pub fn key(&self) -> Option<&SigningKey> {
+        self.key.as_ref()
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        hex(&self.public_key)
+    }
+
+    pub fn signature_hex(&self) -> String {
+        hex(&self.signature)
+    }
+
+    /// Decode a `Sign` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<Signature, ParseError> {
+        tag.expect_length(100)?;
+        if tag.word(0) != ALGORITHM_ED25519 {
+            return Err(ParseError::UnsupportedAlgorithm(
+                tag.offset,
+                tag.code,
+                tag.word(0),
+            ));
+        }
+        let mut public_key = [0u8; 32];
+        public_key.copy_from_slice(&tag.data[4..36]);
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(&tag.data[36..100]);
+        Ok(Signature {
+            public_key,
+            signature,
+            key: None,
+        })
+    }
+}
+
+impl XousArgument for Signature {
+    fn code(&self) -> XousArgumentCode {
+        make_type!("Sign")
+    }
+    fn length(&self) -> XousSize {
+        100
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = 0;
+        written += output.write(&ALGORITHM_ED25519.to_le_bytes())?;
+        written += output.write(&self.public_key)?;
+        written += output.write(&self.signature)?;
+        Ok(written)
+    }
+}
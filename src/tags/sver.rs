@@ -0,0 +1,103 @@
+use crate::xous_arguments::{ParseError, Tag, TagIter, XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// The security version of the image, used to prevent rollback to older
+/// images with known vulnerabilities.
+///
+/// This only ever increases.  A device should refuse to boot an image with
+/// a lower version than one it has already booted.  The tag is covered by
+/// the `Dgst` and `Sign` tags like everything else in the image.
+#[derive(Debug)]
+pub struct SecurityVersion {
+    version: u32,
+}
+
+impl fmt::Display for SecurityVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "    SVer: security version {}", self.version)
+    }
+}
+
+impl SecurityVersion {
+    pub fn new(version: u32) -> SecurityVersion {
+        SecurityVersion { version }
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Decode an `SVer` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<SecurityVersion, ParseError> {
+        tag.expect_length(4)?;
+        Ok(SecurityVersion {
+            version: tag.word(0),
+        })
+    }
+}
+
+/// Find the security version of `image`, which is 0 if it doesn't have an
+/// `SVer` tag.  Only the tags are read, so this works on encrypted images.
+pub fn image_security_version(image: &[u8]) -> Result<u32, ParseError> {
+    for tag in TagIter::new(image) {
+        let tag = tag?;
+        if tag.code == make_type!("SVer") {
+            return Ok(SecurityVersion::decode(&tag)?.version);
+        }
+    }
+    Ok(0)
+}
+
+impl XousArgument for SecurityVersion {
+    fn code(&self) -> XousArgumentCode {
+        make_type!("SVer")
+    }
+    fn length(&self) -> XousSize {
+        4
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        output.write(&self.version.to_le_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample_args, write_image};
+
+    fn decode(data: &[u8]) -> Result<SecurityVersion, ParseError> {
+        SecurityVersion::decode(&Tag {
+            offset: 0,
+            code: make_type!("SVer"),
+            crc: 0,
+            data,
+        })
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let mut encoded = vec![];
+        SecurityVersion::new(0x1234_5678)
+            .serialize(&mut encoded)
+            .unwrap();
+        assert_eq!(encoded, [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(decode(&encoded).unwrap().version(), 0x1234_5678);
+    }
+
+    #[test]
+    fn bad_length() {
+        match decode(&[1, 0, 0, 0, 0, 0, 0, 0]) {
+            Err(ParseError::BadTagLength(0, _, 8)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn image_version() {
+        let mut args = sample_args();
+        assert_eq!(image_security_version(&write_image(&mut args)).unwrap(), 0);
+        args.add(SecurityVersion::new(7));
+        assert_eq!(image_security_version(&write_image(&mut args)).unwrap(), 7);
+    }
+}
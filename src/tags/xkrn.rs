@@ -1,4 +1,10 @@
-use crate::xous_arguments::{XousArgument, XousArgumentCode, XousSize};
+use crate::elf::{MiniElfFlags, MiniElfSection};
+use crate::encryption::KeyEncryptionKey;
+use crate::error::Error;
+use crate::tags::cmpr::{Algorithm, Compression};
+use crate::tags::encr::Encryption;
+use crate::tags::payload::{Encoding, Payload};
+use crate::xous_arguments::{ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
 use std::fmt;
 use std::io;
 
@@ -26,7 +32,7 @@ pub struct XousKernel {
     entrypoint: u32,
 
     /// Actual program contents
-    program: Vec<u8>,
+    program: Payload,
 }
 
 impl fmt::Display for XousKernel {
@@ -55,9 +61,127 @@ impl XousKernel {
             data_size,
             bss_size,
             entrypoint,
-            program,
+            program: Payload::new(program),
         }
     }
+
+    /// Compress the program when it is written out, unless that wouldn't
+    /// save any space.  Returns the `Cmpr` tag that must be placed
+    /// immediately before this one.
+    pub fn compress(&mut self, algorithm: Algorithm) -> Option<Compression> {
+        self.program.compress(algorithm)
+    }
+
+    /// Encrypt the program when it is written out, after any compression.
+    /// Returns the `Encr` tag that must be placed immediately before this
+    /// one.
+    pub fn encrypt(&mut self, kek: &KeyEncryptionKey) -> Result<Encryption, Error> {
+        let mut header = vec![];
+        self.serialize(&mut header)?;
+        self.program.encrypt(kek, self.code(), &header)
+    }
+
+    /// Returns `true` if the program is stored compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.program.is_compressed()
+    }
+
+    /// Returns `true` if the program is stored encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.program.is_encrypted()
+    }
+
+    /// Returns `false` if the program is encrypted and couldn't be
+    /// decrypted, in which case `program()` is empty.
+    pub fn has_data(&self) -> bool {
+        self.program.has_data()
+    }
+
+    /// Decode an `XKrn` tag read from an image, along with its payload.
+    /// `encoding` holds the tags immediately before this one, if any.
+    pub fn decode(tag: &Tag, image: &[u8], encoding: Encoding) -> Result<XousKernel, ParseError> {
+        tag.expect_length(28)?;
+        let load_offset = tag.word(0);
+        let text_size = tag.word(2);
+        let data_size = tag.word(4);
+        let program_len = text_size.saturating_add(data_size);
+        Ok(XousKernel {
+            load_offset,
+            text_offset: tag.word(1),
+            text_size,
+            data_offset: tag.word(3),
+            data_size,
+            bss_size: tag.word(5),
+            entrypoint: tag.word(6),
+            program: Payload::decode(tag, image, load_offset, program_len, encoding)?,
+        })
+    }
+
+    /// Offset of the kernel program within the image.
+    pub fn load_offset(&self) -> u32 {
+        self.load_offset
+    }
+
+    pub fn text_offset(&self) -> u32 {
+        self.text_offset
+    }
+
+    pub fn text_size(&self) -> u32 {
+        self.text_size
+    }
+
+    pub fn data_offset(&self) -> u32 {
+        self.data_offset
+    }
+
+    pub fn data_size(&self) -> u32 {
+        self.data_size
+    }
+
+    pub fn bss_size(&self) -> u32 {
+        self.bss_size
+    }
+
+    pub fn entrypoint(&self) -> u32 {
+        self.entrypoint
+    }
+
+    /// The `.text` and `.data` contents.  This is always uncompressed and
+    /// decrypted.
+    pub fn program(&self) -> &[u8] {
+        self.program.data()
+    }
+
+    /// Describe the kernel as `.text`, `.data` and `.bss` sections, laid
+    /// out the same way as `program()`.
+    ///
+    /// This can't be exactly what the kernel ELF had.  Every section that
+    /// wasn't `.data` or `.bss` was merged into `.text`, with any gaps
+    /// between them filled with zeros.  The image doesn't record where
+    /// `.bss` goes, so it is placed right after `.data`, which is where the
+    /// loader puts it.
+    pub fn sections(&self) -> Vec<MiniElfSection> {
+        vec![
+            MiniElfSection {
+                virt: self.text_offset,
+                size: self.text_size,
+                flags: MiniElfFlags::EXECUTE,
+                name: ".text".to_owned(),
+            },
+            MiniElfSection {
+                virt: self.data_offset,
+                size: self.data_size,
+                flags: MiniElfFlags::WRITE,
+                name: ".data".to_owned(),
+            },
+            MiniElfSection {
+                virt: self.data_offset.saturating_add(self.data_size),
+                size: self.bss_size,
+                flags: MiniElfFlags::WRITE | MiniElfFlags::NOCOPY,
+                name: ".bss".to_owned(),
+            },
+        ]
+    }
 }
 
 impl XousArgument for XousKernel {
@@ -66,16 +190,16 @@ impl XousArgument for XousKernel {
     }
 
     fn length(&self) -> XousSize {
-        28 as XousSize
+        28
     }
 
     fn finalize(&mut self, offset: usize) -> usize {
         self.load_offset = offset as u32;
-        self.program.len()
+        self.last_data().len()
     }
 
     fn last_data(&self) -> &[u8] {
-        &self.program
+        self.program.stored()
     }
 
     fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
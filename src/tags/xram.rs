@@ -0,0 +1,151 @@
+use crate::xous_arguments::{code_name, ParseError, Tag, XousArgument, XousArgumentCode, XousSize};
+use std::fmt;
+use std::io;
+
+/// A bank of RAM that the kernel may allocate from, in addition to main RAM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RamBank {
+    start: XousSize,
+    length: XousSize,
+
+    /// Bank name (as a type)
+    name: XousArgumentCode,
+}
+
+impl RamBank {
+    pub fn new(start: XousSize, length: XousSize, name: XousArgumentCode) -> RamBank {
+        RamBank {
+            start,
+            length,
+            name,
+        }
+    }
+
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    pub fn name(&self) -> XousArgumentCode {
+        self.name
+    }
+}
+
+/// Banks of RAM beyond main RAM, for SoCs with more than one.  Main RAM is
+/// still the one in the `XArg` tag, and these are in addition to it.
+///
+/// Each bank is three words: its start, its length and its name.
+#[derive(Debug, Default)]
+pub struct RamBanks {
+    banks: Vec<RamBank>,
+}
+
+impl fmt::Display for RamBanks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "    Additional RAM:")?;
+        for bank in &self.banks {
+            writeln!(
+                f,
+                "        {} ({:08x}): {:08x} - {:08x}",
+                code_name(bank.name),
+                bank.name,
+                bank.start,
+                bank.start as u64 + bank.length as u64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl RamBanks {
+    pub fn new() -> RamBanks {
+        RamBanks::default()
+    }
+
+    pub fn add(&mut self, bank: RamBank) {
+        self.banks.push(bank);
+    }
+
+    pub fn banks(&self) -> &[RamBank] {
+        &self.banks
+    }
+
+    pub fn len(&self) -> usize {
+        self.banks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.banks.is_empty()
+    }
+
+    /// Decode an `XRam` tag read from an image.
+    pub fn decode(tag: &Tag) -> Result<RamBanks, ParseError> {
+        if tag.data.len() % 12 != 0 {
+            return Err(ParseError::BadTagLength(tag.offset, tag.code, tag.data.len()));
+        }
+        let banks = (0..tag.words() / 3)
+            .map(|idx| RamBank {
+                start: tag.word(idx * 3),
+                length: tag.word(idx * 3 + 1),
+                name: tag.word(idx * 3 + 2),
+            })
+            .collect();
+        Ok(RamBanks { banks })
+    }
+}
+
+impl XousArgument for RamBanks {
+    fn code(&self) -> XousArgumentCode {
+        make_type!("XRam")
+    }
+    fn length(&self) -> XousSize {
+        (self.banks.len() * 12) as XousSize
+    }
+    fn serialize(&self, output: &mut dyn io::Write) -> io::Result<usize> {
+        let mut written = 0;
+        for bank in &self.banks {
+            written += output.write(&bank.start.to_le_bytes())?;
+            written += output.write(&bank.length.to_le_bytes())?;
+            written += output.write(&bank.name.to_le_bytes())?;
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &[u8]) -> Result<RamBanks, ParseError> {
+        RamBanks::decode(&Tag {
+            offset: 0,
+            code: make_type!("XRam"),
+            crc: 0,
+            data,
+        })
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let mut banks = RamBanks::new();
+        banks.add(RamBank::new(0x6000_0000, 0x0080_0000, make_type!("Bnk1")));
+        banks.add(RamBank::new(0xffff_0000, 0x0001_0000, make_type!("Bnk2")));
+        let mut encoded = vec![];
+        banks.serialize(&mut encoded).unwrap();
+        assert_eq!(encoded.len(), banks.length() as usize);
+        assert_eq!(decode(&encoded).unwrap().banks(), banks.banks());
+
+        let err = decode(&encoded[..20]);
+        assert!(matches!(err, Err(ParseError::BadTagLength(0, _, 20))));
+    }
+
+    #[test]
+    fn display_bank_at_top_of_memory() {
+        let mut banks = RamBanks::new();
+        banks.add(RamBank::new(0xffff_0000, 0x0001_0000, make_type!("Bnk2")));
+        assert!(banks.to_string().contains("ffff0000 - 100000000"));
+    }
+}
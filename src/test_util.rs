@@ -0,0 +1,123 @@
+//! Fixtures shared by the unit tests.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::elf::{write_elf, MiniElfFlags, MiniElfSection};
+use crate::tags::inie::IniE;
+use crate::tags::memory::{MemoryRegion, MemoryRegions};
+use crate::tags::xkrn::XousKernel;
+use crate::xous_arguments::XousArguments;
+
+/// A directory that is removed when it goes out of scope.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "xous-tools-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    /// Write `data` to `name` in this directory, returning its path.
+    pub fn write(&self, name: &str, data: &[u8]) -> PathBuf {
+        let path = self.0.join(name);
+        fs::write(&path, data).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+pub fn section(virt: u32, size: u32, flags: MiniElfFlags) -> MiniElfSection {
+    MiniElfSection {
+        virt,
+        size,
+        flags,
+        name: String::new(),
+    }
+}
+
+/// `len` bytes that don't repeat for a while, so they don't compress.
+pub fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed) ^ (i >> 8) as u8)
+        .collect()
+}
+
+/// A kernel with 16 bytes of text, 8 bytes of data and 32 bytes of bss.
+pub fn kernel() -> XousKernel {
+    XousKernel::new(0xff00_0000, 16, 0xff40_0000, 8, 32, 0xff00_0000, pattern(24, 1))
+}
+
+/// An init program with a text, a data and a bss section.
+pub fn init(seed: u8) -> IniE {
+    IniE::new(
+        0x2000_0000,
+        vec![
+            section(0x2000_0000, 16, MiniElfFlags::EXECUTE),
+            section(0x2001_0000, 8, MiniElfFlags::WRITE),
+            section(0x2001_0008, 32, MiniElfFlags::WRITE | MiniElfFlags::NOCOPY),
+        ],
+        pattern(24, seed),
+    )
+}
+
+/// Write ELF files matching `kernel()` and `init(seed)` into `dir`, returning
+/// the path of the kernel and of the init program.
+pub fn write_elfs(dir: &TempDir, seed: u8) -> (PathBuf, PathBuf) {
+    let named = |name: &str, section: MiniElfSection| MiniElfSection {
+        name: name.to_owned(),
+        ..section
+    };
+    let kernel = dir.write(
+        "kernel.elf",
+        &write_elf(
+            0xff00_0000,
+            &[
+                named(".text", section(0xff00_0000, 16, MiniElfFlags::EXECUTE)),
+                named(".data", section(0xff40_0000, 8, MiniElfFlags::WRITE)),
+                named(
+                    ".bss",
+                    section(0xff40_0008, 32, MiniElfFlags::WRITE | MiniElfFlags::NOCOPY),
+                ),
+            ],
+            &pattern(24, 1),
+        ),
+    );
+    let inie = init(seed);
+    let init = dir.write(
+        &format!("init{}.elf", seed),
+        &write_elf(inie.entrypoint(), inie.sections(), inie.data()),
+    );
+    (kernel, init)
+}
+
+/// An image with main RAM, one memory region, a kernel and one init
+/// program, which passes every check.
+pub fn sample_args() -> XousArguments {
+    let mut args = XousArguments::new(0x4000_0000, 0x0100_0000, make_type!("SrEx"));
+    let mut regions = MemoryRegions::new();
+    regions.add(MemoryRegion::new(0xf000_0000, 0x3000, make_type!("CSRs")));
+    args.add(regions);
+    args.add(kernel());
+    args.add(init(2));
+    args
+}
+
+/// Serialize `args` into an image.
+pub fn write_image(args: &mut XousArguments) -> Vec<u8> {
+    let mut image = vec![];
+    args.write(&mut image).unwrap();
+    image
+}
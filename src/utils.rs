@@ -6,10 +6,16 @@ use std::io;
 pub struct CsrMemoryRegion {
     pub start: u32,
     pub length: u32,
+
+    /// The CSV file marks this region as `io` rather than `cached`
+    pub io: bool,
 }
 
 pub struct CsrConfig {
     pub regions: BTreeMap<String, CsrMemoryRegion>,
+
+    /// Problems with the file that didn't stop it from being parsed
+    pub warnings: Vec<String>,
 }
 
 const PAGE_SIZE: u32 = 4096;
@@ -23,6 +29,17 @@ pub enum ConfigError {
     IoError(io::Error),
 }
 
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::NumberParseError(s, e) => write!(f, "couldn't parse \"{}\": {}", s, e),
+            ConfigError::IoError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 impl std::convert::From<io::Error> for ConfigError {
     fn from(e: io::Error) -> ConfigError {
         ConfigError::IoError(e)
@@ -47,8 +64,13 @@ pub fn get_base(value: &str) -> (&str, u32) {
 
 pub fn parse_u32(value: &str) -> Result<u32, ConfigError> {
     let (value, base) = get_base(value);
-    u32::from_str_radix(value, base)
-        .or_else(|e| Err(ConfigError::NumberParseError(value.to_owned(), e)))
+    u32::from_str_radix(value, base).map_err(|e| ConfigError::NumberParseError(value.to_owned(), e))
+}
+
+/// Format bytes as a lowercase hex string, the way hashes, keys and nonces
+/// are shown.
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
 pub fn parse_csr_csv(filename: &str) -> Result<CsrConfig, ConfigError> {
@@ -58,49 +80,56 @@ pub fn parse_csr_csv(filename: &str) -> Result<CsrConfig, ConfigError> {
     let mut csr_base = 0;
     let mut csr_top = 0;
 
+    let mut warnings = vec![];
+
     let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(file);
-    for result in rdr.records() {
-        if let Ok(r) = result {
-            if r.len() < 1 {
-                eprintln!("csv: ignoring blank line");
-                continue;
+    for r in rdr.records().flatten() {
+        if r.is_empty() {
+            warnings.push("csv: ignoring blank line".to_owned());
+            continue;
+        }
+        match &r[0] {
+            "csr_base" => {
+                if r.len() < 3 {
+                    warnings.push("csv: found csr_base entry, but entry was short".to_owned());
+                    continue;
+                }
+                let base_addr = parse_u32(&r[2])?;
+                if base_addr > csr_top {
+                    // println!("csv: increasing csr top: {:08x} -> {:08x}", csr_top, base_addr);
+                    csr_top = base_addr;
+                }
             }
-            match &r[0] {
-                "csr_base" => {
-                    if r.len() < 3 {
-                        eprintln!("csv: found csr_base entry, but entry was short");
-                        continue;
-                    }
-                    let base_addr = parse_u32(&r[2])?;
-                    if base_addr > csr_top {
-                        // println!("csv: increasing csr top: {:08x} -> {:08x}", csr_top, base_addr);
-                        csr_top = base_addr;
-                    }
+            "memory_region" => {
+                if r.len() < 4 {
+                    warnings.push("csv: found memory_region entry, but entry was short".to_owned());
+                    continue;
                 }
-                "memory_region" => {
-                    if r.len() < 4 {
-                        eprintln!("csv: found memory_region entry, but entry was short");
-                        continue;
-                    }
-                    let region_name = &r[1];
-                    let base_addr = parse_u32(&r[2])?;
-                    let length = parse_u32(&r[3])?;
-
-                    if region_name == "csr" {
-                        csr_base = base_addr;
-                    } else {
-                        map.insert(
-                            region_name.to_string().to_lowercase(),
-                            CsrMemoryRegion {
-                                start: base_addr,
-                                length,
-                            },
-                        );
+                let region_name = &r[1];
+                let base_addr = parse_u32(&r[2])?;
+                let length = parse_u32(&r[3])?;
+
+                if region_name == "csr" {
+                    csr_base = base_addr;
+                } else {
+                    let previous = map.insert(
+                        region_name.to_string().to_lowercase(),
+                        CsrMemoryRegion {
+                            start: base_addr,
+                            length,
+                            io: r.get(4) == Some("io"),
+                        },
+                    );
+                    if previous.is_some() {
+                        warnings.push(format!(
+                            "csv: memory region {} appears more than once, using the last one",
+                            region_name
+                        ));
                     }
                 }
-                _ => (),
-            };
-        }
+            }
+            _ => (),
+        };
     }
 
     if csr_base != 0 && csr_top != 0 {
@@ -111,8 +140,12 @@ pub fn parse_csr_csv(filename: &str) -> Result<CsrConfig, ConfigError> {
             CsrMemoryRegion {
                 start: csr_base,
                 length: csr_top - csr_base,
+                io: true,
             },
         );
     }
-    Ok(CsrConfig { regions: map })
+    Ok(CsrConfig {
+        regions: map,
+        warnings,
+    })
 }
@@ -0,0 +1,417 @@
+use std::fmt;
+
+use crate::elf::MiniElfFlags;
+use crate::tags::inie::IniE;
+use crate::tags::memory::MemoryRegions;
+use crate::tags::proc::Processes;
+use crate::tags::xkrn::XousKernel;
+use crate::tags::xram::RamBanks;
+use crate::xous_arguments::{code_name, XousArguments};
+
+/// Memory regions must start and end on a page boundary.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The kernel is mapped at and above this address in every process, so
+/// init programs must stay below it.
+pub const USER_AREA_END: u64 = 0xff00_0000;
+
+/// One end past the highest 32-bit address.
+const ADDRESS_SPACE_END: u64 = 1 << 32;
+
+/// Something in the image that occupies a range of addresses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    pub name: String,
+    pub kind: ItemKind,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Item {
+    fn new(name: String, kind: ItemKind, start: u64, length: u64) -> Item {
+        Item {
+            name,
+            kind,
+            start,
+            end: start + length,
+        }
+    }
+
+    pub fn overlaps(&self, other: &Item) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:08x} - {:08x}", self.name, self.start, self.end)
+    }
+}
+
+/// A problem with where something in an image is placed in memory.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutProblem {
+    /// Two items share some addresses
+    Overlap(Item, Item),
+
+    /// An item extends past the end of the space it has to fit in
+    OutOfRange(Item, u64 /* limit */),
+
+    /// A memory region doesn't start or end on a page boundary
+    Unaligned(Item),
+}
+
+impl fmt::Display for LayoutProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutProblem::Overlap(a, b) => write!(f, "{} overlaps {}", a, b),
+            LayoutProblem::OutOfRange(item, limit) => {
+                write!(f, "{} extends past {:08x}", item, limit)
+            }
+            LayoutProblem::Unaligned(item) => {
+                write!(f, "{} is not aligned to a {}-byte page", item, PAGE_SIZE)
+            }
+        }
+    }
+}
+
+/// What an item in an address space is, for rendering memory maps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ItemKind {
+    Ram,
+    Region,
+    Text,
+    Data,
+    Bss,
+}
+
+/// What an address space describes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpaceKind {
+    /// Main RAM and the memory regions
+    Physical,
+
+    /// The kernel's virtual addresses, which are mapped into every process
+    Kernel,
+
+    /// One init program's virtual addresses
+    Process,
+}
+
+/// A set of addresses that items are placed in.  Items in one space may
+/// not overlap, but items in different spaces can.
+#[derive(Clone, Debug)]
+pub struct AddressSpace {
+    pub kind: SpaceKind,
+    pub name: String,
+
+    /// Every item must end at or below this address
+    pub limit: u64,
+    pub items: Vec<Item>,
+}
+
+fn section_kind(flags: MiniElfFlags) -> ItemKind {
+    if flags.contains(MiniElfFlags::EXECUTE) {
+        ItemKind::Text
+    } else if flags.contains(MiniElfFlags::NOCOPY) {
+        ItemKind::Bss
+    } else {
+        ItemKind::Data
+    }
+}
+
+fn physical_space(args: &XousArguments) -> AddressSpace {
+    let mut items = vec![Item::new(
+        format!("main RAM {}", code_name(args.ram_name())),
+        ItemKind::Ram,
+        args.ram_start() as u64,
+        args.ram_length() as u64,
+    )];
+    for xram in args.iter::<RamBanks>() {
+        for bank in xram.banks() {
+            items.push(Item::new(
+                format!("RAM bank {}", code_name(bank.name())),
+                ItemKind::Ram,
+                bank.start() as u64,
+                bank.length() as u64,
+            ));
+        }
+    }
+    for mrex in args.iter::<MemoryRegions>() {
+        for region in mrex.regions() {
+            items.push(Item::new(
+                format!("memory region {}", code_name(region.name())),
+                ItemKind::Region,
+                region.start() as u64,
+                region.length() as u64,
+            ));
+        }
+    }
+    AddressSpace {
+        kind: SpaceKind::Physical,
+        name: "physical memory".to_owned(),
+        limit: ADDRESS_SPACE_END,
+        items,
+    }
+}
+
+fn kernel_space(xkrn: &XousKernel) -> AddressSpace {
+    let data_offset = xkrn.data_offset() as u64;
+    let data_size = xkrn.data_size() as u64;
+    AddressSpace {
+        kind: SpaceKind::Kernel,
+        name: "kernel".to_owned(),
+        limit: ADDRESS_SPACE_END,
+        items: vec![
+            Item::new(
+                "kernel .text".to_owned(),
+                ItemKind::Text,
+                xkrn.text_offset() as u64,
+                xkrn.text_size() as u64,
+            ),
+            Item::new(
+                "kernel .data".to_owned(),
+                ItemKind::Data,
+                data_offset,
+                data_size,
+            ),
+            Item::new(
+                "kernel .bss".to_owned(),
+                ItemKind::Bss,
+                data_offset + data_size,
+                xkrn.bss_size() as u64,
+            ),
+        ],
+    }
+}
+
+fn process_space(name: String, inie: &IniE) -> AddressSpace {
+    let items = inie
+        .sections()
+        .iter()
+        .enumerate()
+        .map(|(idx, section)| {
+            let kind = section_kind(section.flags);
+            let kind_name = match kind {
+                ItemKind::Text => "text",
+                ItemKind::Bss => "bss",
+                _ => "data",
+            };
+            Item::new(
+                format!("{} section {} ({})", name, idx, kind_name),
+                kind,
+                section.virt as u64,
+                section.size as u64,
+            )
+        })
+        .collect();
+    AddressSpace {
+        kind: SpaceKind::Process,
+        name,
+        limit: USER_AREA_END,
+        items,
+    }
+}
+
+/// Every address space in the image: physical memory, then the kernel if
+/// there is one, then each init program in load order.
+pub fn address_spaces(args: &XousArguments) -> Vec<AddressSpace> {
+    let mut spaces = vec![physical_space(args)];
+    if let Some(xkrn) = args.get::<XousKernel>() {
+        spaces.push(kernel_space(xkrn));
+    }
+    let processes = args.get::<Processes>();
+    for (idx, inie) in args.iter::<IniE>().enumerate() {
+        let name = match processes.and_then(|p| p.get(idx)) {
+            Some(process) if !process.name.is_empty() => {
+                format!("init {} ({})", idx, process.name)
+            }
+            _ => format!("init {}", idx),
+        };
+        spaces.push(process_space(name, inie));
+    }
+    spaces
+}
+
+#[derive(Default)]
+struct Validator {
+    problems: Vec<LayoutProblem>,
+}
+
+impl Validator {
+    fn in_range(&mut self, item: &Item, limit: u64) {
+        if item.end > limit {
+            self.problems
+                .push(LayoutProblem::OutOfRange(item.clone(), limit));
+        }
+    }
+
+    fn aligned(&mut self, item: &Item) {
+        if item.start % PAGE_SIZE != 0 || item.end % PAGE_SIZE != 0 {
+            self.problems.push(LayoutProblem::Unaligned(item.clone()));
+        }
+    }
+
+    /// Report every pair of overlapping items.  Empty items never overlap.
+    fn disjoint(&mut self, items: &[Item]) {
+        for (idx, a) in items.iter().enumerate() {
+            for b in &items[idx + 1..] {
+                if a.overlaps(b) {
+                    self.problems
+                        .push(LayoutProblem::Overlap(a.clone(), b.clone()));
+                }
+            }
+        }
+    }
+
+    fn space(&mut self, space: &AddressSpace, kernel: &[Item]) {
+        for item in &space.items {
+            if space.kind == SpaceKind::Physical {
+                self.aligned(item);
+            }
+            self.in_range(item, space.limit);
+        }
+        self.disjoint(&space.items);
+        // The kernel is mapped into every process
+        if space.kind == SpaceKind::Process {
+            for item in &space.items {
+                for k in kernel {
+                    if item.overlaps(k) {
+                        self.problems
+                            .push(LayoutProblem::Overlap(item.clone(), k.clone()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Check that main RAM, the extra RAM banks, the memory regions, the kernel and every init
+/// program's sections fit in the address space without colliding.
+/// Returns every problem found, or an empty list if there are none.
+pub fn validate_layout(args: &XousArguments) -> Vec<LayoutProblem> {
+    let spaces = address_spaces(args);
+    let kernel = spaces
+        .iter()
+        .find(|space| space.kind == SpaceKind::Kernel)
+        .map(|space| space.items.as_slice())
+        .unwrap_or_default();
+    let mut validator = Validator::default();
+    for space in &spaces {
+        validator.space(space, kernel);
+    }
+    validator.problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tags::memory::MemoryRegion;
+    use crate::tags::xram::RamBank;
+    use crate::test_util::{pattern, sample_args, section};
+
+    fn add_region(args: &mut XousArguments, start: u32, length: u32) {
+        let region = MemoryRegion::new(start, length, make_type!("Oops"));
+        args.get_mut::<MemoryRegions>().unwrap().add(region);
+    }
+
+    fn names(problem: &LayoutProblem) -> Vec<&str> {
+        match problem {
+            LayoutProblem::Overlap(a, b) => vec![&a.name, &b.name],
+            LayoutProblem::OutOfRange(item, _) | LayoutProblem::Unaligned(item) => {
+                vec![&item.name]
+            }
+        }
+    }
+
+    #[test]
+    fn valid() {
+        assert!(validate_layout(&sample_args()).is_empty());
+    }
+
+    #[test]
+    fn overlapping_ram() {
+        let mut args = sample_args();
+        let mut xram = RamBanks::new();
+        xram.add(RamBank::new(0x40ff_0000, 0x0001_0000, make_type!("Bnk1")));
+        xram.add(RamBank::new(0x40ff_0000, 0x0001_0000, make_type!("Bnk2")));
+        args.add(xram);
+        let problems = validate_layout(&args);
+        assert_eq!(problems.len(), 3);
+        assert!(problems
+            .iter()
+            .all(|p| matches!(p, LayoutProblem::Overlap(..))));
+        assert_eq!(names(&problems[0]), ["main RAM SrEx", "RAM bank Bnk1"]);
+        assert_eq!(
+            problems[0].to_string(),
+            "main RAM SrEx 40000000 - 41000000 overlaps RAM bank Bnk1 40ff0000 - 41000000"
+        );
+    }
+
+    #[test]
+    fn overlapping_sections() {
+        let mut args = sample_args();
+        args.add(IniE::new(
+            0x3000_0000,
+            vec![
+                section(0x3000_0000, 32, MiniElfFlags::EXECUTE),
+                section(0x3000_0010, 16, MiniElfFlags::WRITE),
+            ],
+            pattern(48, 3),
+        ));
+        let problems = validate_layout(&args);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            names(&problems[0]),
+            ["init 1 section 0 (text)", "init 1 section 1 (data)"]
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut args = sample_args();
+        add_region(&mut args, 0xffff_f000, 0x2000);
+        args.add(IniE::new(
+            0xfeff_f000,
+            vec![section(0xfeff_f000, 0x2000, MiniElfFlags::EXECUTE)],
+            pattern(0x2000, 3),
+        ));
+        let problems = validate_layout(&args);
+        // The init also runs into the kernel
+        assert_eq!(problems.len(), 3);
+        assert_eq!(
+            problems[0],
+            LayoutProblem::OutOfRange(
+                Item::new(
+                    "memory region Oops".to_owned(),
+                    ItemKind::Region,
+                    0xffff_f000,
+                    0x2000
+                ),
+                ADDRESS_SPACE_END
+            )
+        );
+        assert_eq!(
+            problems[1].to_string(),
+            "init 1 section 0 (text) fefff000 - ff001000 extends past ff000000"
+        );
+        assert!(matches!(&problems[2], LayoutProblem::Overlap(..)));
+        assert_eq!(
+            names(&problems[2]),
+            ["init 1 section 0 (text)", "kernel .text"]
+        );
+    }
+
+    #[test]
+    fn unaligned() {
+        let mut args = sample_args();
+        add_region(&mut args, 0xf000_4000, 0x100);
+        let problems = validate_layout(&args);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(&problems[0], LayoutProblem::Unaligned(_)));
+        assert_eq!(
+            problems[0].to_string(),
+            "memory region Oops f0004000 - f0004100 is not aligned to a 4096-byte page"
+        );
+    }
+}
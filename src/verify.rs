@@ -0,0 +1,582 @@
+use std::fmt;
+
+use ed25519_dalek::VerifyingKey;
+
+use crate::elf::MiniElfFlags;
+use crate::seal;
+use crate::signing::{self, SignatureStatus};
+use crate::tags::cmpr::Compression;
+use crate::tags::encr::Encryption;
+use crate::tags::proc::Processes;
+use crate::tags::sign::Signature;
+use crate::utils::hex;
+use crate::xous_arguments::{code_name, ParseError, Tag, TagIter, XousArguments};
+
+/// A single broken invariant found in an image.
+#[derive(Debug)]
+pub enum Violation {
+    /// The tag stream itself could not be decoded
+    Parse(ParseError),
+
+    /// A tag decoded correctly, but describes an invalid layout
+    Layout(usize /* tag offset */, String),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Parse(e) => write!(f, "{}", e),
+            Violation::Layout(offset, description) => {
+                write!(f, "offset {}: {}", offset, description)
+            }
+        }
+    }
+}
+
+/// A payload referenced by a tag, as a range of bytes in the image.
+struct Payload {
+    tag_offset: usize,
+    name: String,
+    start: u64,
+    end: u64,
+}
+
+/// Checks the layout invariants of an image beyond the per-tag CRC16.
+#[derive(Default)]
+pub struct Verifier {
+    violations: Vec<Violation>,
+    payloads: Vec<Payload>,
+    minimum_version: Option<u32>,
+}
+
+impl Verifier {
+    pub fn new() -> Verifier {
+        Verifier::default()
+    }
+
+    /// Also require the security version of the image to be at least
+    /// `version`, such as that of a previously shipped image.
+    pub fn minimum_version(mut self, version: u32) -> Verifier {
+        self.minimum_version = Some(version);
+        self
+    }
+
+    fn violation(&mut self, offset: usize, description: String) {
+        self.violations.push(Violation::Layout(offset, description));
+    }
+
+    /// Record a payload, making sure that it lies within the image.
+    fn payload(&mut self, tag: &Tag, image: &[u8], start: u32, length: u64) {
+        let start = start as u64;
+        let end = start + length;
+        if end > image.len() as u64 {
+            self.violation(
+                tag.offset,
+                format!(
+                    "{} payload {:08x} - {:08x} extends past the end of the {} byte image",
+                    code_name(tag.code),
+                    start,
+                    end,
+                    image.len()
+                ),
+            );
+        }
+        self.payloads.push(Payload {
+            tag_offset: tag.offset,
+            name: code_name(tag.code),
+            start,
+            end,
+        });
+    }
+
+    /// A `Cmpr` or `Encr` tag must be followed by a kernel or init program.
+    fn orphan(&mut self, offset: usize, name: &str) {
+        self.violation(
+            offset,
+            format!("{} tag is not followed by a kernel or init program", name),
+        );
+    }
+
+    /// Record a payload that may be compressed.  `length` is the size of
+    /// the uncompressed data the tag describes.
+    fn stored_payload(
+        &mut self,
+        tag: &Tag,
+        image: &[u8],
+        start: u32,
+        length: u64,
+        compression: Option<&Compression>,
+    ) {
+        match compression {
+            Some(compression) => {
+                if compression.uncompressed_size() as u64 != length {
+                    self.violation(
+                        tag.offset,
+                        format!(
+                            "{} payload is {} bytes, but Cmpr says it decompresses to {}",
+                            code_name(tag.code),
+                            length,
+                            compression.uncompressed_size()
+                        ),
+                    );
+                }
+                self.payload(tag, image, start, compression.stored_size() as u64);
+            }
+            None => self.payload(tag, image, start, length),
+        }
+    }
+
+    fn check_kernel(&mut self, tag: &Tag, image: &[u8], compression: Option<&Compression>) {
+        if tag.data.len() != 28 {
+            self.violation(
+                tag.offset,
+                format!("XKrn tag is {} bytes, expected 28", tag.data.len()),
+            );
+            return;
+        }
+        let text_offset = tag.word(1) as u64;
+        let text_size = tag.word(2) as u64;
+        let data_size = tag.word(4) as u64;
+        let entrypoint = tag.word(6) as u64;
+        self.stored_payload(tag, image, tag.word(0), text_size + data_size, compression);
+        if entrypoint < text_offset || entrypoint >= text_offset + text_size {
+            self.violation(
+                tag.offset,
+                format!(
+                    "kernel entrypoint {:08x} is outside of .text {:08x} - {:08x}",
+                    entrypoint,
+                    text_offset,
+                    text_offset + text_size
+                ),
+            );
+        }
+    }
+
+    fn check_init(&mut self, tag: &Tag, image: &[u8], compression: Option<&Compression>) {
+        if tag.data.len() < 8 || tag.data.len() & 7 != 0 {
+            self.violation(
+                tag.offset,
+                format!("IniE tag has invalid length {}", tag.data.len()),
+            );
+            return;
+        }
+        let entrypoint = tag.word(1) as u64;
+        let mut payload_len = 0u64;
+        let mut entry_ok = false;
+        for idx in 0..(tag.words() - 2) / 2 {
+            let virt = tag.word(2 + idx * 2) as u64;
+            let word2 = tag.word(3 + idx * 2);
+            let size = ((word2 & 0x00ff_ffff) << 2) as u64;
+            let flags = MiniElfFlags::from_bits_truncate((word2 >> 24) as u8);
+            if !flags.contains(MiniElfFlags::NOCOPY) {
+                payload_len += size;
+            }
+            if flags.contains(MiniElfFlags::EXECUTE)
+                && entrypoint >= virt
+                && entrypoint < virt + size
+            {
+                entry_ok = true;
+            }
+        }
+        self.stored_payload(tag, image, tag.word(0), payload_len, compression);
+        if !entry_ok {
+            self.violation(
+                tag.offset,
+                format!(
+                    "init entrypoint {:08x} is not inside an executable section",
+                    entrypoint
+                ),
+            );
+        }
+    }
+
+    fn check_regions(&mut self, tag: &Tag, ram_start: u64, ram_end: u64) {
+        if tag.data.len() & 15 != 0 {
+            self.violation(
+                tag.offset,
+                format!("MREx tag has invalid length {}", tag.data.len()),
+            );
+            return;
+        }
+        for idx in 0..tag.words() / 4 {
+            let start = tag.word(idx * 4) as u64;
+            let end = start + tag.word(idx * 4 + 1) as u64;
+            let name = code_name(tag.word(idx * 4 + 2));
+            if start < ram_end && ram_start < end {
+                self.violation(
+                    tag.offset,
+                    format!(
+                        "memory region {} {:08x} - {:08x} overlaps main RAM {:08x} - {:08x}",
+                        name, start, end, ram_start, ram_end
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_ram_banks(&mut self, tag: &Tag, ram_start: u64, ram_end: u64) {
+        if tag.data.len() % 12 != 0 {
+            self.violation(
+                tag.offset,
+                format!("XRam tag has invalid length {}", tag.data.len()),
+            );
+            return;
+        }
+        for idx in 0..tag.words() / 3 {
+            let start = tag.word(idx * 3) as u64;
+            let end = start + tag.word(idx * 3 + 1) as u64;
+            let name = code_name(tag.word(idx * 3 + 2));
+            if start < ram_end && ram_start < end {
+                self.violation(
+                    tag.offset,
+                    format!(
+                        "RAM bank {} {:08x} - {:08x} overlaps main RAM {:08x} - {:08x}",
+                        name, start, end, ram_start, ram_end
+                    ),
+                );
+            }
+        }
+    }
+
+    /// There may only be one `SVer` tag, and its version mustn't be below
+    /// the minimum.  An image without one has version 0.
+    fn check_security_version(&mut self, tags: &[Tag]) {
+        if let Some(extra) = tags.get(1) {
+            self.violation(extra.offset, "image has more than one SVer tag".to_owned());
+        }
+        let version = match tags.first() {
+            Some(tag) if tag.data.len() != 4 => {
+                self.violation(
+                    tag.offset,
+                    format!("SVer tag is {} bytes, expected 4", tag.data.len()),
+                );
+                return;
+            }
+            Some(tag) => tag.word(0),
+            None => 0,
+        };
+        if let Some(minimum) = self.minimum_version {
+            if version < minimum {
+                self.violation(
+                    tags.first().map_or(0, |tag| tag.offset),
+                    format!(
+                        "security version {} is lower than the minimum of {}",
+                        version, minimum
+                    ),
+                );
+            }
+        }
+    }
+
+    /// There may only be one `Proc` tag, with exactly one entry for each
+    /// init program.
+    fn check_processes(&mut self, tags: &[Tag], init_count: usize) {
+        if let Some(extra) = tags.get(1) {
+            self.violation(extra.offset, "image has more than one Proc tag".to_owned());
+        }
+        let tag = match tags.first() {
+            Some(tag) => tag,
+            None => return,
+        };
+        match Processes::decode(tag) {
+            Ok(processes) if processes.len() != init_count => self.violation(
+                tag.offset,
+                format!(
+                    "Proc tag describes {} process(es), but there are {} init program(s)",
+                    processes.len(),
+                    init_count
+                ),
+            ),
+            Ok(_) => (),
+            Err(e) => self.violations.push(Violation::Parse(e)),
+        }
+    }
+
+    /// The `Dgst` tag, if present, must match the image contents.
+    fn check_digest(&mut self, tag_offset: usize, image: &[u8]) {
+        match seal::check_digest(image) {
+            Ok(Some((stored, calculated))) if stored != calculated => self.violation(
+                tag_offset,
+                format!(
+                    "image digest {} does not match the contents, which hash to {}",
+                    hex(&stored),
+                    hex(&calculated)
+                ),
+            ),
+            Ok(_) => {}
+            Err(e) => self.violations.push(Violation::Parse(e)),
+        }
+    }
+
+    /// The `Sign` tag, if present, must match the image contents using the
+    /// public key stored alongside it.  This doesn't say anything about
+    /// whether that key is trusted.
+    fn check_signature(&mut self, tag: &Tag, image: &[u8]) {
+        let signature = match Signature::decode(tag) {
+            Ok(signature) => signature,
+            Err(e) => {
+                self.violations.push(Violation::Parse(e));
+                return;
+            }
+        };
+        let key = match VerifyingKey::from_bytes(signature.public_key()) {
+            Ok(key) => key,
+            Err(_) => {
+                self.violation(
+                    tag.offset,
+                    format!("signing key {} is invalid", signature.public_key_hex()),
+                );
+                return;
+            }
+        };
+        match signing::verify_signature(image, &key) {
+            Ok(SignatureStatus::Valid) => {}
+            Ok(status) => self.violation(tag.offset, status.to_string()),
+            Err(e) => self.violations.push(Violation::Parse(e)),
+        }
+    }
+
+    /// Payloads must directly follow the tags, in tag order, with no gaps,
+    /// overlaps, or trailing data.
+    fn check_contiguous(&mut self, tags_end: usize, image_len: usize) {
+        let mut expected = tags_end as u64;
+        let mut violations = vec![];
+        for payload in &self.payloads {
+            if payload.start != expected {
+                violations.push((
+                    payload.tag_offset,
+                    format!(
+                        "{} payload starts at {:08x}, but the previous data ended at {:08x}",
+                        payload.name, payload.start, expected
+                    ),
+                ));
+            }
+            expected = payload.end;
+        }
+        if expected != image_len as u64 {
+            violations.push((
+                tags_end,
+                format!(
+                    "payloads end at {:08x}, but the image is {} bytes long",
+                    expected, image_len
+                ),
+            ));
+        }
+        for (offset, description) in violations {
+            self.violation(offset, description);
+        }
+    }
+
+    /// Check every invariant of `image`, returning all of the violations found.
+    pub fn verify(mut self, image: &[u8]) -> Vec<Violation> {
+        let mut tags = TagIter::new(image);
+        let xarg = match tags.next() {
+            Some(Ok(xarg)) => xarg,
+            Some(Err(e)) => {
+                self.violations.push(Violation::Parse(e));
+                return self.violations;
+            }
+            None => unreachable!("TagIter always returns XArg or an error first"),
+        };
+        let ram_start = xarg.word(2) as u64;
+        let ram_end = ram_start + xarg.word(3) as u64;
+        let mut digest_offset = None;
+        let mut signature_tag = None;
+        let mut pending_compression: Option<(usize, Compression)> = None;
+        let mut pending_encryption = None;
+        let mut security_versions = vec![];
+        let mut process_tags = vec![];
+        let mut init_count = 0;
+
+        for tag in &mut tags {
+            let tag = match tag {
+                Ok(tag) => tag,
+                Err(e) => {
+                    // Without a valid tag stream nothing else can be trusted
+                    self.violations.push(Violation::Parse(e));
+                    return self.violations;
+                }
+            };
+            // `Cmpr` and `Encr` tags describe the payload of the tag after
+            // them.  Encryption doesn't change the size of the payload, so
+            // only compression matters for the layout.
+            let is_payload = tag.code == make_type!("XKrn") || tag.code == make_type!("IniE");
+            if !is_payload {
+                if let Some(offset) = pending_encryption.take() {
+                    self.orphan(offset, "Encr");
+                }
+                if tag.code != make_type!("Encr") {
+                    if let Some((offset, _)) = pending_compression.take() {
+                        self.orphan(offset, "Cmpr");
+                    }
+                }
+            }
+            let compression = if is_payload {
+                pending_encryption = None;
+                pending_compression.take().map(|(_, c)| c)
+            } else {
+                None
+            };
+
+            if tag.code == make_type!("XKrn") {
+                self.check_kernel(&tag, image, compression.as_ref());
+            } else if tag.code == make_type!("IniE") {
+                self.check_init(&tag, image, compression.as_ref());
+                init_count += 1;
+            } else if tag.code == make_type!("Cmpr") {
+                match Compression::decode(&tag) {
+                    Ok(c) => pending_compression = Some((tag.offset, c)),
+                    Err(e) => self.violations.push(Violation::Parse(e)),
+                }
+            } else if tag.code == make_type!("Encr") {
+                match Encryption::decode(&tag) {
+                    Ok(_) => pending_encryption = Some(tag.offset),
+                    Err(e) => self.violations.push(Violation::Parse(e)),
+                }
+            } else if tag.code == make_type!("MREx") {
+                self.check_regions(&tag, ram_start, ram_end);
+            } else if tag.code == make_type!("XRam") {
+                self.check_ram_banks(&tag, ram_start, ram_end);
+            } else if tag.code == make_type!("Dgst") && digest_offset.is_none() {
+                digest_offset = Some(tag.offset);
+            } else if tag.code == make_type!("Sign") && signature_tag.is_none() {
+                signature_tag = Some(tag);
+            } else if tag.code == make_type!("SVer") {
+                security_versions.push(tag);
+            } else if tag.code == make_type!("Proc") {
+                process_tags.push(tag);
+            }
+        }
+
+        if let Some((offset, _)) = pending_compression {
+            self.orphan(offset, "Cmpr");
+        }
+        if let Some(offset) = pending_encryption {
+            self.orphan(offset, "Encr");
+        }
+
+        let tags_end = tags.end().expect("XArg tag was read");
+        self.check_contiguous(tags_end, image.len());
+        self.check_security_version(&security_versions);
+        self.check_processes(&process_tags, init_count);
+        if let Some(offset) = digest_offset {
+            self.check_digest(offset, image);
+        }
+        if let Some(tag) = signature_tag {
+            self.check_signature(&tag, image);
+        }
+
+        // Anything the typed decoders object to that wasn't caught above
+        if self.violations.is_empty() {
+            if let Err(e) = XousArguments::parse(image) {
+                self.violations.push(Violation::Parse(e));
+            }
+        }
+        self.violations
+    }
+}
+
+/// Check `image` for layout problems.  Returns an empty list if it is valid.
+pub fn verify_image(image: &[u8]) -> Vec<Violation> {
+    Verifier::new().verify(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tags::cmpr::{Algorithm, Compression};
+    use crate::tags::memory::{MemoryRegion, MemoryRegions};
+    use crate::test_util::{sample_args, write_image};
+
+    /// Descriptions of layout violations, failing on any other kind.
+    fn problems(violations: Vec<Violation>) -> Vec<String> {
+        violations
+            .into_iter()
+            .map(|violation| match violation {
+                Violation::Layout(_, description) => description,
+                Violation::Parse(e) => panic!("unexpected parse error: {}", e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_image() {
+        let image = write_image(&mut sample_args());
+        assert!(verify_image(&image).is_empty());
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut image = write_image(&mut sample_args());
+        image.extend_from_slice(&[0; 4]);
+        let problems = problems(verify_image(&image));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("payloads end at"));
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let mut image = write_image(&mut sample_args());
+        image.truncate(image.len() - 4);
+        let violations = verify_image(&image);
+        assert!(violations
+            .iter()
+            .any(|v| v.to_string().contains("extends past the end")));
+    }
+
+    #[test]
+    fn region_overlaps_main_ram() {
+        let mut args = sample_args();
+        args.get_mut::<MemoryRegions>()
+            .unwrap()
+            .add(MemoryRegion::new(0x40ff_f000, 0x2000, make_type!("Oops")));
+        let problems = problems(verify_image(&write_image(&mut args)));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("memory region Oops"));
+        assert!(problems[0].contains("overlaps main RAM"));
+    }
+
+    #[test]
+    fn orphan_compression() {
+        let mut args = sample_args();
+        args.add(Compression::compress(Algorithm::Lz4, &[0; 64]).0);
+        let problems = problems(verify_image(&write_image(&mut args)));
+        assert_eq!(
+            problems,
+            vec!["Cmpr tag is not followed by a kernel or init program".to_owned()]
+        );
+    }
+
+    #[test]
+    fn changed_payload() {
+        let mut args = sample_args();
+        args.add(crate::tags::dgst::Digest::new());
+        let mut image = write_image(&mut args);
+        assert!(verify_image(&image).is_empty());
+
+        // Payloads have no CRC of their own, so only the digest notices
+        let last = image.len() - 1;
+        image[last] ^= 1;
+        let problems = problems(verify_image(&image));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("does not match the contents"));
+    }
+
+    #[test]
+    fn minimum_version() {
+        let image = write_image(&mut sample_args());
+        assert!(Verifier::new().minimum_version(0).verify(&image).is_empty());
+        assert_eq!(Verifier::new().minimum_version(3).verify(&image).len(), 1);
+    }
+
+    #[test]
+    fn bad_crc() {
+        let mut image = write_image(&mut sample_args());
+        image[36] ^= 1;
+        let violations = verify_image(&image);
+        assert!(matches!(
+            violations.as_slice(),
+            [Violation::Parse(ParseError::CrcMismatch(28, _, _, _))]
+        ));
+    }
+}
@@ -1,19 +1,57 @@
+use std::any::Any;
 use std::fmt;
 use std::io::{Cursor, Result, Write};
 pub type XousArgumentCode = u32;
 pub type XousSize = u32;
 use crc::{crc16, Hasher16};
 
+use crate::encryption::KeyEncryptionKey;
+use crate::error::Error;
+use crate::tags::bflg::Bflg;
+use crate::tags::binf::BuildInfo;
+use crate::tags::bpar::BootParams;
+use crate::tags::cmpr::Compression;
+use crate::tags::dgst::Digest;
+use crate::tags::encr::Encryption;
+use crate::tags::inie::IniE;
+use crate::tags::memory::MemoryRegions;
+use crate::tags::payload::Encoding;
+use crate::tags::proc::Processes;
+use crate::tags::raw::RawTag;
+use crate::tags::sign::Signature;
+use crate::tags::sver::SecurityVersion;
+use crate::tags::xkrn::XousKernel;
+use crate::tags::xram::RamBanks;
+
 #[macro_export]
 macro_rules! make_type {
     ($fcc:expr) => {{
-        let mut c: [u8; 4] = Default::default();
-        c.copy_from_slice($fcc.as_bytes());
+        // Pad or truncate by bytes rather than characters, so that names of
+        // any length or encoding give a code instead of panicking
+        let mut c: [u8; 4] = *b"    ";
+        for (dest, src) in c.iter_mut().zip($fcc.as_bytes()) {
+            *dest = *src;
+        }
         u32::from_le_bytes(c)
     }};
 }
 
-pub trait XousArgument: fmt::Display {
+/// Allows a boxed `XousArgument` to be turned back into its concrete type.
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub trait XousArgument: fmt::Display + AsAny {
     /// A fourcc code of this tag
     fn code(&self) -> XousArgumentCode;
 
@@ -40,6 +78,325 @@ pub trait XousArgument: fmt::Display {
     fn last_data(&self) -> &[u8] { &[] }
 }
 
+/// Calculate the CRC16 that goes into a tag header.
+pub fn tag_crc(data: &[u8]) -> u16 {
+    let mut digest = crc16::Digest::new(crc16::X25);
+    digest.write(data);
+    digest.sum16()
+}
+
+/// Convert a fourcc code back into a printable string.
+pub fn code_name(code: XousArgumentCode) -> String {
+    String::from_utf8_lossy(&code.to_le_bytes()).to_string()
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// The image ended before the structure at this offset was complete
+    Truncated(usize /* offset */, usize /* needed */, usize /* available */),
+
+    /// The image did not start with an `XArg` tag
+    MissingXArg(XousArgumentCode),
+
+    /// The `XArg` tag was not the expected size
+    BadXArgSize(usize /* size in bytes */),
+
+    /// The `XArg` tag had a version we don't understand
+    UnsupportedVersion(u32),
+
+    /// The tag stream was smaller than the `XArg` tag itself
+    BadTotalLength(usize /* total bytes */),
+
+    /// The CRC16 stored in a tag header did not match its contents
+    CrcMismatch(
+        usize, /* offset */
+        XousArgumentCode,
+        u16, /* stored */
+        u16, /* calculated */
+    ),
+
+    /// A tag ran past the end of the tag stream advertised by `XArg`
+    TagOverrun(usize /* offset */, XousArgumentCode, usize /* end of tags */),
+
+    /// A known tag was not a valid length for its type
+    BadTagLength(usize /* offset */, XousArgumentCode, usize /* length */),
+
+    /// A tag used an algorithm we don't understand
+    UnsupportedAlgorithm(usize /* offset */, XousArgumentCode, u32),
+
+    /// A compressed payload couldn't be decompressed
+    BadCompression(usize /* offset */, XousArgumentCode, String),
+
+    /// A `Cmpr` tag wasn't followed by a tag with a payload
+    OrphanCompression(usize /* offset */),
+
+    /// An encrypted payload couldn't be decrypted with the key given
+    DecryptionFailed(usize /* offset */, XousArgumentCode),
+
+    /// An `Encr` tag wasn't followed by a tag with a payload
+    OrphanEncryption(usize /* offset */),
+
+    /// A tag's payload fell outside of the image
+    PayloadOutOfRange(
+        usize, /* offset */
+        XousArgumentCode,
+        u32, /* payload offset */
+        u32, /* payload length */
+    ),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ParseError::*;
+        match self {
+            Truncated(o, n, a) => write!(
+                f,
+                "offset {}: image truncated, needed {} bytes but only {} were available",
+                o, n, a
+            ),
+            MissingXArg(c) => write!(
+                f,
+                "offset 0: expected an XArg tag, found {:08x} ({})",
+                c,
+                code_name(*c)
+            ),
+            BadXArgSize(s) => write!(f, "offset 8: XArg tag was {} bytes, expected 20", s),
+            UnsupportedVersion(v) => write!(f, "offset 12: unsupported XArg version {}", v),
+            BadTotalLength(t) => write!(
+                f,
+                "offset 8: XArg says the tags are {} bytes long, which is too short",
+                t
+            ),
+            CrcMismatch(o, c, s, calc) => write!(
+                f,
+                "offset {}: {} tag has CRC {:04x}, but its contents have CRC {:04x}",
+                o,
+                code_name(*c),
+                s,
+                calc
+            ),
+            TagOverrun(o, c, e) => write!(
+                f,
+                "offset {}: {} tag runs past the end of the tags at offset {}",
+                o,
+                code_name(*c),
+                e
+            ),
+            BadTagLength(o, c, l) => write!(
+                f,
+                "offset {}: {} tag has an invalid length of {} bytes",
+                o,
+                code_name(*c),
+                l
+            ),
+            UnsupportedAlgorithm(o, c, a) => write!(
+                f,
+                "offset {}: {} tag uses unknown algorithm {}",
+                o,
+                code_name(*c),
+                a
+            ),
+            BadCompression(o, c, e) => write!(
+                f,
+                "offset {}: {} payload couldn't be decompressed: {}",
+                o,
+                code_name(*c),
+                e
+            ),
+            OrphanCompression(o) => write!(
+                f,
+                "offset {}: Cmpr tag is not followed by a kernel or init program",
+                o
+            ),
+            DecryptionFailed(o, c) => write!(
+                f,
+                "offset {}: {} payload couldn't be decrypted, either the key is wrong or the image was modified",
+                o,
+                code_name(*c)
+            ),
+            OrphanEncryption(o) => write!(
+                f,
+                "offset {}: Encr tag is not followed by a kernel or init program",
+                o
+            ),
+            PayloadOutOfRange(o, c, po, pl) => write!(
+                f,
+                "offset {}: {} payload of {} bytes at offset {} lies outside the image",
+                o,
+                code_name(*c),
+                pl,
+                po
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A single undecoded tag as it appears in an image.
+#[derive(Debug)]
+pub struct Tag<'a> {
+    /// Byte offset of the tag header within the image
+    pub offset: usize,
+
+    /// The fourcc code of this tag
+    pub code: XousArgumentCode,
+
+    /// CRC16 stored in the tag header
+    pub crc: u16,
+
+    /// The tag contents, not including the header
+    pub data: &'a [u8],
+}
+
+impl<'a> Tag<'a> {
+    /// Return the `index`th 32-bit word of the tag contents.
+    pub fn word(&self, index: usize) -> u32 {
+        let mut w = [0u8; 4];
+        w.copy_from_slice(&self.data[index * 4..index * 4 + 4]);
+        u32::from_le_bytes(w)
+    }
+
+    /// Number of 32-bit words in the tag contents.
+    pub fn words(&self) -> usize {
+        self.data.len() / 4
+    }
+
+    /// CRC16 calculated over the tag contents.
+    pub fn calculated_crc(&self) -> u16 {
+        tag_crc(self.data)
+    }
+
+    /// Ensure the tag contents are exactly `len` bytes long.
+    pub fn expect_length(&self, len: usize) -> std::result::Result<(), ParseError> {
+        if self.data.len() != len {
+            return Err(ParseError::BadTagLength(self.offset, self.code, self.data.len()));
+        }
+        Ok(())
+    }
+
+    /// Return the `len` bytes at `offset` within `image`, which this tag refers to.
+    pub fn payload<'b>(
+        &self,
+        image: &'b [u8],
+        offset: u32,
+        len: u32,
+    ) -> std::result::Result<&'b [u8], ParseError> {
+        let start = offset as usize;
+        let end = start + len as usize;
+        if end > image.len() {
+            return Err(ParseError::PayloadOutOfRange(self.offset, self.code, offset, len));
+        }
+        Ok(&image[start..end])
+    }
+}
+
+/// Iterates over the raw tags of an image, without interpreting them.
+///
+/// The first tag returned is always the `XArg` tag.  Iteration stops at
+/// the end of the tag stream advertised by `XArg`, or at the first error.
+pub struct TagIter<'a> {
+    image: &'a [u8],
+    offset: usize,
+    end: Option<usize>,
+    failed: bool,
+}
+
+impl<'a> TagIter<'a> {
+    pub fn new(image: &'a [u8]) -> TagIter<'a> {
+        TagIter {
+            image,
+            offset: 0,
+            end: None,
+            failed: false,
+        }
+    }
+
+    /// The offset of the end of the tag stream, once the `XArg` tag has been read.
+    pub fn end(&self) -> Option<usize> {
+        self.end
+    }
+
+    fn read_tag(&mut self) -> std::result::Result<Tag<'a>, ParseError> {
+        let offset = self.offset;
+        let limit = self.end.unwrap_or(self.image.len());
+        if offset + 8 > self.image.len() {
+            return Err(ParseError::Truncated(offset, 8, self.image.len() - offset));
+        }
+        let header = &self.image[offset..offset + 8];
+        let code = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let crc = u16::from_le_bytes([header[4], header[5]]);
+        let size = u16::from_le_bytes([header[6], header[7]]) as usize * 4;
+        if self.end.is_some() && offset + 8 + size > limit {
+            return Err(ParseError::TagOverrun(offset, code, limit));
+        }
+        if offset + 8 + size > self.image.len() {
+            return Err(ParseError::Truncated(
+                offset + 8,
+                size,
+                self.image.len() - offset - 8,
+            ));
+        }
+        let tag = Tag {
+            offset,
+            code,
+            crc,
+            data: &self.image[offset + 8..offset + 8 + size],
+        };
+        if tag.calculated_crc() != crc {
+            return Err(ParseError::CrcMismatch(
+                offset,
+                code,
+                crc,
+                tag.calculated_crc(),
+            ));
+        }
+        self.offset += 8 + size;
+        Ok(tag)
+    }
+
+    fn read_xarg(&mut self) -> std::result::Result<Tag<'a>, ParseError> {
+        let tag = self.read_tag()?;
+        if tag.code != make_type!("XArg") {
+            return Err(ParseError::MissingXArg(tag.code));
+        }
+        if tag.data.len() != 20 {
+            return Err(ParseError::BadXArgSize(tag.data.len()));
+        }
+        if tag.word(1) != 1 {
+            return Err(ParseError::UnsupportedVersion(tag.word(1)));
+        }
+        let end = tag.word(0) as usize * 4;
+        if end < self.offset {
+            return Err(ParseError::BadTotalLength(end));
+        }
+        if end > self.image.len() {
+            return Err(ParseError::Truncated(0, end, self.image.len()));
+        }
+        self.end = Some(end);
+        Ok(tag)
+    }
+}
+
+impl<'a> Iterator for TagIter<'a> {
+    type Item = std::result::Result<Tag<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || Some(self.offset) == self.end {
+            return None;
+        }
+        let result = if self.end.is_none() {
+            self.read_xarg()
+        } else {
+            self.read_tag()
+        };
+        if result.is_err() {
+            self.failed = true;
+        }
+        Some(result)
+    }
+}
+
 pub struct XousArguments {
     ram_start: XousSize,
     ram_length: XousSize,
@@ -59,7 +416,7 @@ impl fmt::Display for XousArguments {
             tag_name,
             self.ram_name,
             self.ram_start,
-            self.ram_start + self.ram_length
+            self.ram_start as u64 + self.ram_length as u64
         )?;
 
         for arg in &self.arguments {
@@ -79,6 +436,172 @@ impl XousArguments {
         }
     }
 
+    /// Decode an image previously produced by `write()`.
+    ///
+    /// Every tag is CRC-checked.  Known tags are decoded into their typed
+    /// structs along with their payloads, and unknown tags are kept as a
+    /// `RawTag` so they survive being written back out.  Encrypted payloads
+    /// are kept as they are stored.
+    pub fn parse(image: &[u8]) -> std::result::Result<XousArguments, ParseError> {
+        XousArguments::parse_with_key(image, None)
+    }
+
+    /// Decode an image like `parse()`, decrypting any encrypted payloads
+    /// with `key`.
+    pub fn parse_with_key(
+        image: &[u8],
+        key: Option<&KeyEncryptionKey>,
+    ) -> std::result::Result<XousArguments, ParseError> {
+        let mut tags = TagIter::new(image);
+        let xarg = match tags.next() {
+            Some(tag) => tag?,
+            None => return Err(ParseError::Truncated(0, 8, image.len())),
+        };
+        let mut args = XousArguments::new(xarg.word(2), xarg.word(3), xarg.word(4));
+
+        // `Cmpr` and `Encr` tags apply to the payload of the tag after them,
+        // and are recorded as their tag offset and index into `arguments`
+        let mut cmpr: Option<(usize, usize)> = None;
+        let mut encr: Option<(usize, usize)> = None;
+        for tag in tags {
+            let tag = tag?;
+            let code = tag.code;
+            let is_payload = code == make_type!("IniE") || code == make_type!("XKrn");
+            if !is_payload {
+                if let Some((offset, _)) = encr {
+                    return Err(ParseError::OrphanEncryption(offset));
+                }
+                // Only an `Encr` tag may come between `Cmpr` and its payload
+                if let Some((offset, _)) = cmpr.filter(|_| code != make_type!("Encr")) {
+                    return Err(ParseError::OrphanCompression(offset));
+                }
+            }
+            let (cmpr_idx, encr_idx) = if is_payload {
+                (cmpr.take().map(|(_, idx)| idx), encr.take().map(|(_, idx)| idx))
+            } else {
+                (None, None)
+            };
+            let encoding = Encoding {
+                compression: cmpr_idx
+                    .and_then(|idx| args.arguments[idx].as_ref().as_any().downcast_ref()),
+                encryption: encr_idx
+                    .and_then(|idx| args.arguments[idx].as_ref().as_any().downcast_ref()),
+                key,
+                ram_size: args.total_ram(),
+            };
+
+            if code == make_type!("BInf") {
+                args.add(BuildInfo::decode(&tag)?);
+            } else if code == make_type!("Bflg") {
+                args.add(Bflg::decode(&tag)?);
+            } else if code == make_type!("BPar") {
+                args.add(BootParams::decode(&tag)?);
+            } else if code == make_type!("Cmpr") {
+                cmpr = Some((tag.offset, args.arguments.len()));
+                args.add(Compression::decode(&tag)?);
+            } else if code == make_type!("Dgst") {
+                args.add(Digest::decode(&tag)?);
+            } else if code == make_type!("Encr") {
+                encr = Some((tag.offset, args.arguments.len()));
+                args.add(Encryption::decode(&tag)?);
+            } else if code == make_type!("IniE") {
+                let inie = IniE::decode(&tag, image, encoding)?;
+                args.add(inie);
+            } else if code == make_type!("MREx") {
+                args.add(MemoryRegions::decode(&tag)?);
+            } else if code == make_type!("Proc") {
+                args.add(Processes::decode(&tag)?);
+            } else if code == make_type!("Sign") {
+                args.add(Signature::decode(&tag)?);
+            } else if code == make_type!("SVer") {
+                args.add(SecurityVersion::decode(&tag)?);
+            } else if code == make_type!("XKrn") {
+                let xkrn = XousKernel::decode(&tag, image, encoding)?;
+                args.add(xkrn);
+            } else if code == make_type!("XRam") {
+                args.add(RamBanks::decode(&tag)?);
+            } else {
+                args.add(RawTag::new(code, tag.data.to_vec()));
+            }
+        }
+        if let Some((offset, _)) = encr {
+            return Err(ParseError::OrphanEncryption(offset));
+        }
+        if let Some((offset, _)) = cmpr {
+            return Err(ParseError::OrphanCompression(offset));
+        }
+        Ok(args)
+    }
+
+    pub fn ram_start(&self) -> XousSize {
+        self.ram_start
+    }
+
+    pub fn ram_length(&self) -> XousSize {
+        self.ram_length
+    }
+
+    pub fn ram_name(&self) -> u32 {
+        self.ram_name
+    }
+
+    /// Number of bytes the runtime needs to track memory allocations, which
+    /// is one byte per page of main RAM, of every additional RAM bank and
+    /// of every additional region.
+    pub fn memory_required(&self) -> u32 {
+        let mut pages = self.ram_length / 4096;
+        for banks in self.iter::<RamBanks>() {
+            for bank in banks.banks() {
+                pages += bank.length() / 4096;
+            }
+        }
+        for regions in self.iter::<MemoryRegions>() {
+            for region in regions.regions() {
+                pages += region.length() / 4096;
+            }
+        }
+        pages
+    }
+
+    /// Size of main RAM and every additional RAM bank, in bytes.
+    pub fn total_ram(&self) -> u64 {
+        let banks: u64 = self
+            .iter::<RamBanks>()
+            .flat_map(RamBanks::banks)
+            .map(|bank| u64::from(bank.length()))
+            .sum();
+        u64::from(self.ram_length) + banks
+    }
+
+    /// Return the first argument of type `T`, if any.
+    pub fn get<T: XousArgument + 'static>(&self) -> Option<&T> {
+        self.iter::<T>().next()
+    }
+
+    /// Return the first argument of type `T` for modification, if any.
+    pub fn get_mut<T: XousArgument + 'static>(&mut self) -> Option<&mut T> {
+        self.arguments
+            .iter_mut()
+            .find_map(|arg| arg.as_mut().as_any_mut().downcast_mut::<T>())
+    }
+
+    /// Indices into `arguments` of every argument of type `T`.
+    pub fn positions<T: XousArgument + 'static>(&self) -> Vec<usize> {
+        self.arguments
+            .iter()
+            .enumerate()
+            .filter(|(_, arg)| arg.as_ref().as_any().is::<T>())
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Iterate over every argument of type `T`, in image order.
+    pub fn iter<T: XousArgument + 'static>(&self) -> impl Iterator<Item = &T> {
+        self.arguments
+            .iter()
+            .filter_map(|arg| arg.as_ref().as_any().downcast_ref::<T>())
+    }
+
     pub fn finalize(&mut self) {
         let mut running_offset = self.len() as usize;
         for arg in &mut self.arguments {
@@ -86,77 +609,98 @@ impl XousArguments {
         }
     }
 
-    pub fn add<T: 'static>(&mut self, arg: T)
-    where
-        T: XousArgument + Sized,
-    {
+    pub fn add<T: XousArgument + 'static>(&mut self, arg: T) {
         self.arguments.push(Box::new(arg));
     }
 
-    pub fn write<T>(&mut self, mut w: T) -> Result<()>
+    /// Contents of the `XArg` tag that starts the image.
+    fn xarg_data(&self) -> Vec<u8> {
+        let mut tag_data = vec![];
+        tag_data.extend_from_slice(&(self.len() / 4).to_le_bytes());
+        tag_data.extend_from_slice(&1u32.to_le_bytes()); // Version
+        tag_data.extend_from_slice(&self.ram_start.to_le_bytes());
+        tag_data.extend_from_slice(&self.ram_length.to_le_bytes());
+        tag_data.extend_from_slice(&self.ram_name.to_le_bytes());
+        tag_data
+    }
+
+    /// CRC16 of the `XArg` tag that starts the image.
+    pub fn xarg_crc(&self) -> u16 {
+        tag_crc(&self.xarg_data())
+    }
+
+    pub fn write<T>(&mut self, mut w: T) -> crate::error::Result<()>
     where
         T: Write,
     {
-        let total_length = self.len();
-
         // Finalize the arguments.  This lets any tags update their offsets
         // based on the size of the entire array.
         self.finalize();
 
+        // The image is assembled in memory so that any seal tags can be
+        // calculated over the finished image before it is written out.
+        let mut image = vec![];
+
         // XArg tag contents
-        let mut tag_data = Cursor::new(Vec::new());
-        tag_data.write(&((total_length / 4) as u32).to_le_bytes())?;
-        tag_data.write(&1u32.to_le_bytes())?; // Version
-        tag_data.write(&(self.ram_start as u32).to_le_bytes())?;
-        tag_data.write(&(self.ram_length as u32).to_le_bytes())?;
-        tag_data.write(&(self.ram_name as u32).to_le_bytes())?;
-
-        assert!(
-            (tag_data.get_ref().len() & 3) == 0,
-            "tag data was not a multiple of 4 bytes!"
-        );
-
-        let mut digest = crc16::Digest::new(crc16::X25);
+        let tag_data = self.xarg_data();
+
         // XArg tag header
-        w.write(&make_type!("XArg").to_le_bytes())?;
-        digest.write(tag_data.get_ref());
-        w.write(&digest.sum16().to_le_bytes())?; // CRC16
-        w.write(&((tag_data.get_ref().len() / 4) as u16).to_le_bytes())?; // Size (in words)
-        w.write(tag_data.get_ref())?;
+        image.write_all(&make_type!("XArg").to_le_bytes())?;
+        image.write_all(&tag_crc(&tag_data).to_le_bytes())?; // CRC16
+        image.write_all(&((tag_data.len() / 4) as u16).to_le_bytes())?; // Size (in words)
+        image.write_all(&tag_data)?;
 
         // Write out each subsequent argument
         for arg in &self.arguments {
             let mut tag_data = Cursor::new(Vec::new());
-            let advertised_len = arg.length() as u32;
+            let advertised_len = arg.length();
             let actual_len = arg.serialize(&mut tag_data)? as u32;
-            assert_eq!(
-                advertised_len, actual_len,
-                "argument {} advertised it would write {} bytes, but it wrote {} bytes",
-                arg.name(), advertised_len, actual_len
-            );
-            assert_eq!(
-                tag_data.get_ref().len() as u32,
-                actual_len,
-                "argument {} said it wrote {} bytes, but it actually wrote {} bytes",
-                arg.name(),
-                actual_len,
-                tag_data.get_ref().len()
-            );
-
-            let mut digest = crc16::Digest::new(crc16::X25);
+            if advertised_len != actual_len {
+                return Err(Error::TagLength(arg.name().to_owned(), advertised_len, actual_len));
+            }
+            let tag_data = tag_data.into_inner();
+            if tag_data.len() as u32 != actual_len {
+                return Err(Error::TagLength(
+                    arg.name().to_owned(),
+                    actual_len,
+                    tag_data.len() as u32,
+                ));
+            }
+            if tag_data.len() & 3 != 0 {
+                return Err(Error::TagNotAligned(arg.name().to_owned(), tag_data.len()));
+            }
+            if tag_data.len() / 4 > u16::MAX as usize {
+                return Err(Error::TagTooLarge(arg.name().to_owned(), tag_data.len()));
+            }
+
             // XArg tag header
-            w.write(&arg.code().to_le_bytes())?;
-            digest.write(tag_data.get_ref());
-            w.write(&digest.sum16().to_le_bytes())?; // CRC16
-            w.write(&((tag_data.get_ref().len() / 4) as u16).to_le_bytes())?; // Size (in words)
-            w.write(tag_data.get_ref())?;
+            image.write_all(&arg.code().to_le_bytes())?;
+            image.write_all(&tag_crc(&tag_data).to_le_bytes())?; // CRC16
+            image.write_all(&((tag_data.len() / 4) as u16).to_le_bytes())?; // Size (in words)
+            image.write_all(&tag_data)?;
         }
 
         // Write any pending data, such as payloads
         for arg in &self.arguments {
-            w.write(arg.last_data()).expect("couldn't write extra arg data");
+            image.write_all(arg.last_data())?;
+        }
+
+        if let Some(digest) = crate::seal::seal(&mut image)? {
+            if let Some(dgst) = self.get_mut::<Digest>() {
+                dgst.set_digest(digest);
+            }
+        }
+
+        // Signatures decoded from an existing image are written back as-is
+        if let Some(key) = self.get::<Signature>().and_then(Signature::key).cloned() {
+            if let Some(signature) = crate::signing::sign(&mut image, &key)? {
+                if let Some(sign) = self.get_mut::<Signature>() {
+                    sign.set_signature(signature);
+                }
+            }
         }
 
+        w.write_all(&image)?;
         Ok(())
     }
 
@@ -168,7 +712,121 @@ impl XousArguments {
         total_length
     }
 
+    /// Returns `true` if there are no arguments besides the `XArg` header.
+    pub fn is_empty(&self) -> bool {
+        self.arguments.is_empty()
+    }
+
     pub fn header_len(&self) -> usize {
         8
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample_args, write_image};
+
+    #[test]
+    fn round_trip() {
+        let mut args = sample_args();
+        let image = write_image(&mut args);
+        let mut parsed = XousArguments::parse(&image).unwrap();
+
+        assert_eq!(parsed.ram_start(), 0x4000_0000);
+        assert_eq!(parsed.ram_length(), 0x0100_0000);
+        assert_eq!(parsed.ram_name(), make_type!("SrEx"));
+
+        let regions = parsed.get::<MemoryRegions>().unwrap();
+        assert_eq!(regions.regions()[0].name(), make_type!("CSRs"));
+        assert_eq!(regions.regions()[0].length(), 0x3000);
+
+        let xkrn = parsed.get::<XousKernel>().unwrap();
+        let original = args.get::<XousKernel>().unwrap();
+        assert_eq!(xkrn.text_offset(), original.text_offset());
+        assert_eq!(xkrn.data_size(), original.data_size());
+        assert_eq!(xkrn.bss_size(), original.bss_size());
+        assert_eq!(xkrn.program(), original.program());
+
+        let inie = parsed.get::<IniE>().unwrap();
+        let original = args.get::<IniE>().unwrap();
+        assert_eq!(inie.entrypoint(), original.entrypoint());
+        assert_eq!(inie.sections().len(), 3);
+        for (a, b) in inie.sections().iter().zip(original.sections()) {
+            assert_eq!((a.virt, a.size, a.flags), (b.virt, b.size, b.flags));
+        }
+        assert_eq!(inie.data(), original.data());
+
+        // Writing a parsed image back out gives the same bytes
+        assert_eq!(write_image(&mut parsed), image);
+    }
+
+    #[test]
+    fn display_ram_at_top_of_memory() {
+        let args = XousArguments::new(0xff00_0000, 0x0100_0000, make_type!("SrEx"));
+        assert!(args.to_string().contains("ff000000 - 100000000"));
+    }
+
+    #[test]
+    fn unknown_tags_are_kept() {
+        let mut args = sample_args();
+        args.add(RawTag::new(make_type!("Wxyz"), vec![1, 2, 3, 4]));
+        let image = write_image(&mut args);
+        let mut parsed = XousArguments::parse(&image).unwrap();
+        let raw = parsed.get::<RawTag>().unwrap();
+        assert_eq!(raw.code(), make_type!("Wxyz"));
+        assert_eq!(raw.data(), &[1, 2, 3, 4]);
+        assert_eq!(write_image(&mut parsed), image);
+    }
+
+    #[test]
+    fn bad_crc() {
+        let mut image = write_image(&mut sample_args());
+        // The first byte of the tag after `XArg`
+        image[36] ^= 1;
+        match XousArguments::parse(&image) {
+            Err(ParseError::CrcMismatch(28, code, _, _)) => assert_eq!(code, make_type!("MREx")),
+            Err(e) => panic!("expected a CRC mismatch, got {}", e),
+            Ok(_) => panic!("image with a bad CRC was parsed"),
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let image = write_image(&mut sample_args());
+        assert!(matches!(
+            XousArguments::parse(&image[..4]),
+            Err(ParseError::Truncated(0, 8, 4))
+        ));
+        assert!(XousArguments::parse(&image[..40]).is_err());
+        // Payloads are at the end, so losing them is caught too
+        assert!(XousArguments::parse(&image[..image.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn missing_xarg() {
+        let mut image = write_image(&mut sample_args());
+        image[0] = b'Y';
+        assert!(matches!(
+            XousArguments::parse(&image),
+            Err(ParseError::MissingXArg(_))
+        ));
+    }
+
+    #[test]
+    fn overflowing_sections() {
+        // Enough maximum-size sections to add up to more than 4 GiB
+        let mut data = vec![0u8; 8];
+        for idx in 0..65u32 {
+            data.extend_from_slice(&(idx << 26).to_le_bytes());
+            data.extend_from_slice(&0x00ff_ffffu32.to_le_bytes());
+        }
+        let mut args = XousArguments::new(0x4000_0000, 0x0100_0000, make_type!("SrEx"));
+        args.add(RawTag::new(make_type!("IniE"), data));
+        let image = write_image(&mut args);
+        assert!(matches!(
+            XousArguments::parse(&image),
+            Err(ParseError::BadTagLength(28, _, 528))
+        ));
+    }
+}